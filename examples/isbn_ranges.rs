@@ -0,0 +1,36 @@
+/*!
+ * Convert a RangeMessage.xml of the International ISBN Agency to the range
+ * data shipped in data/isbn_ranges.txt.
+ *
+ * Usage: cargo run --example isbn_ranges -- RangeMessage.xml > data/isbn_ranges.txt
+ */
+use marclib::standardnumber::IsbnRanges;
+use std::io::Write;
+
+const HEADER: &str = "\
+# ISBN registrant ranges, converted from the RangeMessage.xml published by the
+# International ISBN Agency (https://www.isbn-international.org/range_file_generation)
+# with
+#
+#   cargo run --example isbn_ranges -- RangeMessage.xml > data/isbn_ranges.txt
+#
+# Each line names an EAN prefix and registration group, followed by ranges over
+# the seven digits after the group and the registrant element length each
+# range implies. A length of 0 marks ranges not yet assigned.
+";
+
+fn main() -> std::io::Result<()> {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: isbn_ranges RangeMessage.xml");
+            std::process::exit(2)
+        }
+    };
+    let ranges = IsbnRanges::from_path(&path)?;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    out.write_all(HEADER.as_bytes())?;
+    ranges.write(&mut out)?;
+    out.flush()
+}
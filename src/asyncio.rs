@@ -0,0 +1,202 @@
+/*!
+ * Reading and writing records on tokio's `AsyncRead` and `AsyncWrite`.
+ *
+ * `AsyncMarcReader` is a `Stream` of owned records. It cuts the records out of
+ * its buffer with the same state machine as `MarcStreamReader`, and parses them
+ * with `MarcRecord`. `AsyncMarcWriter` serializes records with
+ * `Record::to_marc21`.
+ */
+use crate::marcrecord::{record_in, RecordBuffer, Scan};
+use crate::ownedrecord::OwnedRecord;
+use crate::record::Record;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+const MIN_READ: usize = 64 * 1024;
+
+pub struct AsyncMarcReader<R> {
+    reader: R,
+    records: RecordBuffer<Vec<u8>>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncMarcReader<R> {
+    pub fn new(reader: R) -> AsyncMarcReader<R> {
+        AsyncMarcReader {
+            reader,
+            records: RecordBuffer::new(),
+        }
+    }
+
+    /**
+     * The next record, None at the end of the input. After an error, reading
+     * continues behind the next record terminator.
+     */
+    pub async fn read_record(&mut self) -> std::io::Result<Option<OwnedRecord>> {
+        std::future::poll_fn(|cx| self.poll_record(cx)).await
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn poll_record(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<Option<OwnedRecord>>> {
+        loop {
+            match self.records.next_record()? {
+                Scan::Record(range) => {
+                    let record = record_in(&self.records.buffer[range]).to_owned();
+                    return Poll::Ready(Ok(Some(record)));
+                }
+                Scan::End => return Poll::Ready(Ok(None)),
+                Scan::More => {}
+            }
+            let mut read_buf = ReadBuf::new(self.records.spare(MIN_READ));
+            match Pin::new(&mut self.reader).poll_read(cx, &mut read_buf) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(())) => {
+                    let read = read_buf.filled().len();
+                    self.records.filled(read);
+                }
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for AsyncMarcReader<R> {
+    type Item = std::io::Result<OwnedRecord>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_record(cx).map(|r| r.transpose())
+    }
+}
+
+pub struct AsyncMarcWriter<W> {
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncMarcWriter<W> {
+    pub fn new(writer: W) -> AsyncMarcWriter<W> {
+        AsyncMarcWriter {
+            writer,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub async fn write_record<R: Record + ?Sized>(&mut self, record: &R) -> std::io::Result<()> {
+        self.buffer.clear();
+        record.to_marc21(&mut self.buffer)?;
+        self.writer.write_all(&self.buffer).await
+    }
+
+    pub async fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush().await
+    }
+
+    /** Flush and close the underlying writer **/
+    pub async fn shutdown(&mut self) -> std::io::Result<()> {
+        self.writer.shutdown().await
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asyncio::*;
+    use crate::testutil;
+    use std::io::Error;
+
+    fn record(control_number: &str) -> OwnedRecord {
+        testutil::record(
+            testutil::AUTHORITY,
+            &[
+                (1, control_number.to_string()),
+                (150, format!("  \x1faHeading {}", control_number)),
+            ],
+        )
+    }
+
+    fn control_number(record: &OwnedRecord) -> String {
+        record
+            .field_iter(Some(1))
+            .next()
+            .unwrap()
+            .utf8_data()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn duplex_roundtrip() -> Result<(), String> {
+        // a small buffer, so records arrive in pieces
+        let (client, server) = tokio::io::duplex(16);
+        let write = async move {
+            let mut writer = AsyncMarcWriter::new(client);
+            for n in ["1", "2", "3"] {
+                writer.write_record(&record(n)).await?;
+            }
+            writer.shutdown().await
+        };
+        let read = async move {
+            let mut reader = AsyncMarcReader::new(server);
+            let first = reader.read_record().await?;
+            let mut rest = Vec::new();
+            // the remaining records through the Stream interface
+            while let Some(r) = std::future::poll_fn(|cx| Pin::new(&mut reader).poll_next(cx)).await
+            {
+                rest.push(r?);
+            }
+            Ok::<_, Error>((first, rest))
+        };
+        let (written, read) = tokio::join!(write, read);
+        written.map_err(|e| e.to_string())?;
+        let (first, rest) = read.map_err(|e| e.to_string())?;
+        assert_eq!(control_number(&first.ok_or("first record")?), "1");
+        assert_eq!(rest.len(), 2);
+        assert_eq!(control_number(&rest[1]), "3");
+        assert_eq!(
+            rest[0]
+                .field_iter(Some(150))
+                .next()
+                .ok_or("150")?
+                .utf8_data(),
+            "  \x1faHeading 2"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn truncated_input() {
+        let mut data = Vec::new();
+        record("1").to_marc21(&mut data).unwrap();
+        record("2").to_marc21(&mut data).unwrap();
+        data.truncate(data.len() - 5);
+        let mut reader = AsyncMarcReader::new(&data[..]);
+        assert!(reader.read_record().await.unwrap().is_some());
+        let err = match reader.read_record().await {
+            Err(e) => e,
+            Ok(_) => panic!("expected an error"),
+        };
+        assert!(err.to_string().contains("truncated"), "{}", err);
+        assert!(reader.read_record().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn resync() {
+        // a damaged record between two good ones, its length points into the next one
+        let mut data = Vec::new();
+        record("1").to_marc21(&mut data).unwrap();
+        data.extend_from_slice(b"00040nz  a2200000nc 4500garbage\x1d");
+        record("2").to_marc21(&mut data).unwrap();
+        let mut reader = AsyncMarcReader::new(&data[..]);
+        assert!(reader.read_record().await.unwrap().is_some());
+        assert!(reader.read_record().await.is_err());
+        let record = reader.read_record().await.unwrap().unwrap();
+        assert_eq!(control_number(&record), "2");
+        assert!(reader.read_record().await.unwrap().is_none());
+    }
+}
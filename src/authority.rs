@@ -0,0 +1,331 @@
+use crate::record::*;
+
+/** The kind of heading, derived from the last two digits of the tag **/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HeadingType {
+    PersonalName,
+    CorporateName,
+    MeetingName,
+    UniformTitle,
+    NamedEvent,
+    ChronologicalTerm,
+    TopicalTerm,
+    GeographicName,
+    GenreFormTerm,
+    MediumOfPerformance,
+    GeneralSubdivision,
+    GeographicSubdivision,
+    ChronologicalSubdivision,
+    FormSubdivision,
+}
+
+impl HeadingType {
+    /**
+     * Works for the 1XX, 4XX, 5XX and 7XX tags of authority records and for the
+     * 1XX, 6XX, 7XX and 8XX headings of bibliographic records. The same tag
+     * means different things in the two, e.g. a bibliographic 550 is a note.
+     **/
+    pub fn from_tag(field_type: usize, record_type: RecordType) -> Option<HeadingType> {
+        use HeadingType::*;
+        let heading = match record_type {
+            RecordType::Authority => matches!(field_type / 100, 1 | 4 | 5 | 7),
+            // 76X-78X are linking entries, 84X-88X holdings and alternate graphics
+            t if t.is_bibliographic() => {
+                matches!(field_type, 100..=130 | 600..=662 | 700..=758 | 800..=830)
+            }
+            _ => false,
+        };
+        if !heading {
+            return None;
+        }
+        Some(match field_type % 100 {
+            0 => PersonalName,
+            10 => CorporateName,
+            11 => MeetingName,
+            30 => UniformTitle,
+            47 => NamedEvent,
+            48 => ChronologicalTerm,
+            50 => TopicalTerm,
+            51 => GeographicName,
+            55 => GenreFormTerm,
+            62 => MediumOfPerformance,
+            80 => GeneralSubdivision,
+            81 => GeographicSubdivision,
+            82 => ChronologicalSubdivision,
+            85 => FormSubdivision,
+            _ => return None,
+        })
+    }
+}
+
+/** An identifier from $0, e.g. `(DE-588)4027242-4` or a URI **/
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AuthorityLink {
+    // the MARC organization code in parentheses, None for URIs
+    pub source: Option<String>,
+    pub identifier: String,
+}
+
+impl AuthorityLink {
+    pub fn parse(value: &str) -> AuthorityLink {
+        if let Some(rest) = value.strip_prefix('(') {
+            if let Some(end) = rest.find(')') {
+                return AuthorityLink {
+                    source: Some(rest[..end].to_string()),
+                    identifier: rest[end + 1..].to_string(),
+                };
+            }
+        }
+        AuthorityLink {
+            source: None,
+            identifier: value.to_string(),
+        }
+    }
+
+    pub fn is_uri(&self) -> bool {
+        self.source.is_none() && self.identifier.contains("://")
+    }
+}
+
+impl std::fmt::Display for AuthorityLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            Some(s) => write!(f, "({}){}", s, self.identifier),
+            None => write!(f, "{}", self.identifier),
+        }
+    }
+}
+
+fn is_heading_subfield(code: u8) -> bool {
+    // numeric codes are control subfields, $i and $w carry relationship information
+    code.is_ascii_lowercase() && code != b'i' && code != b'w'
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub field_type: usize,
+    pub heading_type: HeadingType,
+    pub indicators: [u8; 2],
+    // only the subfields that make up the heading itself
+    pub subfields: Vec<(u8, String)>,
+}
+
+impl Heading {
+    /** The heading of a field of a record of the given type, see `HeadingType::from_tag` **/
+    pub fn from_field(field: &RecordField, record_type: RecordType) -> Option<Heading> {
+        let heading_type = HeadingType::from_tag(field.field_type, record_type)?;
+        let subfields: Vec<(u8, String)> = field
+            .subfields()
+            .filter(|s| is_heading_subfield(s.code()))
+            .map(|s| (s.code(), s.utf8_value().to_string()))
+            .collect();
+        if subfields.is_empty() {
+            return None;
+        }
+        Some(Heading {
+            field_type: field.field_type,
+            heading_type,
+            indicators: [field.indicator(0)?, field.indicator(1)?],
+            subfields,
+        })
+    }
+
+    pub fn subfield(&self, code: u8) -> Option<&str> {
+        self.subfields
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /** The heading as display text, with subdivisions separated by `--` **/
+    pub fn text(&self) -> String {
+        let mut s = String::new();
+        for (code, value) in self.subfields.iter() {
+            if !s.is_empty() {
+                if matches!(code, b'v' | b'x' | b'y' | b'z') {
+                    s.push_str(" -- ");
+                } else {
+                    s.push(' ');
+                }
+            }
+            s.push_str(value);
+        }
+        s
+    }
+}
+
+/** A see-from (4XX) or see-also-from (5XX) tracing **/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tracing {
+    pub heading: Heading,
+    // $4, codes or URIs
+    pub relationship_codes: Vec<String>,
+    // $i
+    pub relationship_designators: Vec<String>,
+    // $w
+    pub control: Option<String>,
+    // $0
+    pub links: Vec<AuthorityLink>,
+}
+
+impl Tracing {
+    /** A tracing of an authority record **/
+    pub fn from_field(field: &RecordField) -> Option<Tracing> {
+        let values = |code| field.subfield_values(code).map(|s| s.to_string()).collect();
+        Some(Tracing {
+            heading: Heading::from_field(field, RecordType::Authority)?,
+            relationship_codes: values(b'4'),
+            relationship_designators: values(b'i'),
+            control: field.subfield_values(b'w').next().map(|s| s.to_string()),
+            links: field
+                .subfield_values(b'0')
+                .map(|v| AuthorityLink::parse(&v))
+                .collect(),
+        })
+    }
+
+    /** $w/0, e.g. `g` for broader term, `h` for narrower term, `r` for see $i/$4 **/
+    pub fn special_relationship(&self) -> Option<u8> {
+        self.control
+            .as_ref()
+            .and_then(|w| w.bytes().next())
+            .filter(|&b| b != b'n')
+    }
+
+    /** The identifier of the linked authority from the given source, e.g. `DE-588` **/
+    pub fn link(&self, source: &str) -> Option<&str> {
+        self.links
+            .iter()
+            .find(|l| l.source.as_deref() == Some(source))
+            .map(|l| l.identifier.as_str())
+    }
+}
+
+/** Typed access to the headings of an authority record **/
+pub struct AuthorityRecord<'r, R: Record + ?Sized> {
+    record: &'r R,
+}
+
+impl<'r, R: Record + ?Sized> AuthorityRecord<'r, R> {
+    /** None if the record is not an authority record **/
+    pub fn new(record: &'r R) -> Option<AuthorityRecord<'r, R>> {
+        if record.leader()[6] != b'z' {
+            return None;
+        }
+        Some(AuthorityRecord { record })
+    }
+
+    pub fn record(&self) -> &'r R {
+        self.record
+    }
+
+    pub fn control_number(&self) -> Option<&'r str> {
+        let f = self.record.field_iter(Some(1)).next()?;
+        std::str::from_utf8(f.data).ok()
+    }
+
+    /** The first 1XX **/
+    pub fn established_heading(&self) -> Option<Heading> {
+        self.record
+            .field_iter(None)
+            .filter(|f| f.field_type / 100 == 1)
+            .find_map(|f| Heading::from_field(&f, RecordType::Authority))
+    }
+
+    pub fn heading_type(&self) -> Option<HeadingType> {
+        self.established_heading().map(|h| h.heading_type)
+    }
+
+    fn tracings(&self, hundreds: usize) -> Vec<Tracing> {
+        self.record
+            .field_iter(None)
+            .filter(|f| f.field_type / 100 == hundreds)
+            .filter_map(|f| Tracing::from_field(&f))
+            .collect()
+    }
+
+    /** See from tracings, 4XX **/
+    pub fn variant_headings(&self) -> Vec<Tracing> {
+        self.tracings(4)
+    }
+
+    /** See also from tracings, 5XX **/
+    pub fn related_headings(&self) -> Vec<Tracing> {
+        self.tracings(5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authority::*;
+    use crate::marcrecord::MarcHeader;
+    use crate::marcrecord::MarcRecord;
+    use crate::testutil::STR;
+
+    #[test]
+    fn headings() -> Result<(), String> {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let authority = AuthorityRecord::new(&record).ok_or("not an authority")?;
+        assert_eq!(authority.control_number(), Some("040000028"));
+        let heading = authority.established_heading().ok_or("no heading")?;
+        assert_eq!(heading.field_type, 150);
+        assert_eq!(heading.heading_type, HeadingType::TopicalTerm);
+        assert_eq!(heading.text(), "A 302 D");
+        assert_eq!(authority.variant_headings().len(), 0);
+
+        let related = authority.related_headings();
+        assert_eq!(related.len(), 1);
+        let tracing = &related[0];
+        assert_eq!(tracing.heading.text(), "Integrierte Schaltung");
+        assert_eq!(tracing.heading.heading_type, HeadingType::TopicalTerm);
+        assert_eq!(tracing.relationship_codes[0], "obal");
+        assert_eq!(tracing.relationship_designators, ["Oberbegriff allgemein"]);
+        assert_eq!(tracing.special_relationship(), Some(b'r'));
+        assert_eq!(tracing.links.len(), 3);
+        assert_eq!(tracing.link("DE-588"), Some("4027242-4"));
+        assert_eq!(tracing.link("DE-101"), Some("040272427"));
+        assert!(tracing.links[2].is_uri());
+        assert_eq!(tracing.links[1].to_string(), "(DE-588)4027242-4");
+        Ok(())
+    }
+
+    #[test]
+    fn heading_types() {
+        assert_eq!(
+            HeadingType::from_tag(100, RecordType::Authority),
+            Some(HeadingType::PersonalName)
+        );
+        assert_eq!(
+            HeadingType::from_tag(410, RecordType::Authority),
+            Some(HeadingType::CorporateName)
+        );
+        assert_eq!(
+            HeadingType::from_tag(511, RecordType::Authority),
+            Some(HeadingType::MeetingName)
+        );
+        assert_eq!(
+            HeadingType::from_tag(130, RecordType::Authority),
+            Some(HeadingType::UniformTitle)
+        );
+        assert_eq!(
+            HeadingType::from_tag(451, RecordType::Authority),
+            Some(HeadingType::GeographicName)
+        );
+        assert_eq!(HeadingType::from_tag(245, RecordType::Authority), None);
+        assert_eq!(HeadingType::from_tag(152, RecordType::Authority), None);
+        let bibliographic = RecordType::LanguageMaterial;
+        assert_eq!(
+            HeadingType::from_tag(650, bibliographic),
+            Some(HeadingType::TopicalTerm)
+        );
+        assert_eq!(
+            HeadingType::from_tag(830, bibliographic),
+            Some(HeadingType::UniformTitle)
+        );
+        assert_eq!(HeadingType::from_tag(650, RecordType::Authority), None);
+        for tag in [150, 500, 510, 511, 550, 780, 850, 880] {
+            assert_eq!(HeadingType::from_tag(tag, bibliographic), None);
+        }
+        assert_eq!(HeadingType::from_tag(100, RecordType::Classification), None);
+    }
+}
@@ -0,0 +1,629 @@
+use crate::authority::*;
+use crate::marcrecord::MarcReader;
+use crate::record::*;
+use crate::util::{escape_csv, escape_xml};
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+
+/** The relationship a 5XX tracing expresses, from $4 or else from $w/0 **/
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EdgeType {
+    BroaderGeneral,
+    BroaderGeneric,
+    BroaderInstantial,
+    BroaderPartitive,
+    Narrower,
+    Related,
+    Earlier,
+    Later,
+    Other(String),
+}
+
+impl EdgeType {
+    pub fn from_tracing(tracing: &Tracing) -> EdgeType {
+        // $4 may also hold URIs for the same relationship, the code comes first
+        if let Some(code) = tracing
+            .relationship_codes
+            .iter()
+            .find(|c| !c.contains("://"))
+        {
+            return match code.as_str() {
+                "obal" => EdgeType::BroaderGeneral,
+                "obge" => EdgeType::BroaderGeneric,
+                "obin" => EdgeType::BroaderInstantial,
+                "obpa" => EdgeType::BroaderPartitive,
+                "vbal" => EdgeType::Related,
+                "vorg" => EdgeType::Earlier,
+                "nach" => EdgeType::Later,
+                _ => EdgeType::Other(code.clone()),
+            };
+        }
+        match tracing.special_relationship() {
+            Some(b'g') => EdgeType::BroaderGeneral,
+            Some(b'h') => EdgeType::Narrower,
+            Some(b'a') => EdgeType::Earlier,
+            Some(b'b') => EdgeType::Later,
+            _ => EdgeType::Related,
+        }
+    }
+
+    pub fn is_broader(&self) -> bool {
+        matches!(
+            self,
+            EdgeType::BroaderGeneral
+                | EdgeType::BroaderGeneric
+                | EdgeType::BroaderInstantial
+                | EdgeType::BroaderPartitive
+        )
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            EdgeType::BroaderGeneral => "obal",
+            EdgeType::BroaderGeneric => "obge",
+            EdgeType::BroaderInstantial => "obin",
+            EdgeType::BroaderPartitive => "obpa",
+            EdgeType::Narrower => "narrower",
+            EdgeType::Related => "related",
+            EdgeType::Earlier => "earlier",
+            EdgeType::Later => "later",
+            EdgeType::Other(s) => s,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub control_number: String,
+    pub label: String,
+    pub heading_type: Option<HeadingType>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub from: usize,
+    // None if no record in the file carries any of the $0 identifiers
+    pub to: Option<usize>,
+    // the first $0 of the tracing, or its heading if there is none
+    pub target: String,
+    pub edge_type: EdgeType,
+}
+
+struct PendingEdge {
+    from: usize,
+    links: Vec<String>,
+    target: String,
+    edge_type: EdgeType,
+}
+
+/** Collects authority records, resolving links between them once all are known **/
+#[derive(Default)]
+pub struct AuthorityGraphBuilder {
+    nodes: Vec<Node>,
+    identifiers: HashMap<String, usize>,
+    pending: Vec<PendingEdge>,
+}
+
+impl AuthorityGraphBuilder {
+    pub fn new() -> AuthorityGraphBuilder {
+        AuthorityGraphBuilder::default()
+    }
+
+    /** Records without 001 or that are not authorities are skipped **/
+    pub fn add_record<R: Record + ?Sized>(&mut self, record: &R) {
+        let authority = match AuthorityRecord::new(record) {
+            Some(a) => a,
+            None => return,
+        };
+        let control_number = match authority.control_number() {
+            Some(c) => c.to_string(),
+            None => return,
+        };
+        let idx = self.nodes.len();
+        let heading = authority.established_heading();
+        self.nodes.push(Node {
+            control_number: control_number.clone(),
+            label: heading.as_ref().map(|h| h.text()).unwrap_or_default(),
+            heading_type: heading.map(|h| h.heading_type),
+        });
+        self.identifiers.insert(control_number, idx);
+        for field in record.field_iter_vec(&[24, 35]) {
+            for value in field
+                .subfields()
+                .filter(|s| s.code() == b'a' || s.code() == b'0')
+            {
+                self.identifiers
+                    .entry(value.utf8_value().to_string())
+                    .or_insert(idx);
+            }
+        }
+        for tracing in authority.related_headings() {
+            let links: Vec<String> = tracing.links.iter().map(|l| l.to_string()).collect();
+            self.pending.push(PendingEdge {
+                from: idx,
+                target: links
+                    .first()
+                    .cloned()
+                    .unwrap_or_else(|| tracing.heading.text()),
+                links,
+                edge_type: EdgeType::from_tracing(&tracing),
+            });
+        }
+    }
+
+    pub fn build(self) -> AuthorityGraph {
+        let identifiers = self.identifiers;
+        let edges = self
+            .pending
+            .into_iter()
+            .map(|p| Edge {
+                from: p.from,
+                to: p.links.iter().find_map(|l| identifiers.get(l).cloned()),
+                target: p.target,
+                edge_type: p.edge_type,
+            })
+            .collect();
+        let index = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.control_number.clone(), i))
+            .collect();
+        AuthorityGraph {
+            nodes: self.nodes,
+            index,
+            edges,
+        }
+    }
+}
+
+// the node ids of dangling edge targets start with this, so they cannot be
+// taken for control numbers
+const DANGLING_PREFIX: &str = "missing:";
+
+/** The relationships between the records of an authority file **/
+pub struct AuthorityGraph {
+    nodes: Vec<Node>,
+    index: HashMap<String, usize>,
+    edges: Vec<Edge>,
+}
+
+impl AuthorityGraph {
+    pub fn build<R: Read + Seek>(
+        reader: &mut MarcReader<R>,
+        buffer: &mut [u8],
+    ) -> std::io::Result<AuthorityGraph> {
+        let mut builder = AuthorityGraphBuilder::new();
+        while let Some(batch) = reader.read_batch(buffer)? {
+            for record in batch.records.iter() {
+                builder.add_record(record);
+            }
+        }
+        Ok(builder.build())
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    pub fn node(&self, control_number: &str) -> Option<usize> {
+        self.index.get(control_number).cloned()
+    }
+
+    /** Edges whose target is not in the file **/
+    pub fn dangling_edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(|e| e.to.is_none())
+    }
+
+    /** The direct broader terms, from broader edges and from narrower edges pointing here **/
+    pub fn broader(&self, node: usize) -> Vec<usize> {
+        let mut result = Vec::new();
+        for e in self.edges.iter() {
+            let other = match (e.from == node, e.to == Some(node)) {
+                (true, _) if e.edge_type.is_broader() => e.to,
+                (_, true) if e.edge_type == EdgeType::Narrower => Some(e.from),
+                _ => None,
+            };
+            if let Some(o) = other {
+                if !result.contains(&o) {
+                    result.push(o);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn narrower(&self, node: usize) -> Vec<usize> {
+        let mut result = Vec::new();
+        for e in self.edges.iter() {
+            let other = match (e.from == node, e.to == Some(node)) {
+                (true, _) if e.edge_type == EdgeType::Narrower => e.to,
+                (_, true) if e.edge_type.is_broader() => Some(e.from),
+                _ => None,
+            };
+            if let Some(o) = other {
+                if !result.contains(&o) {
+                    result.push(o);
+                }
+            }
+        }
+        result
+    }
+
+    fn transitive(&self, node: usize, step: impl Fn(usize) -> Vec<usize>) -> Vec<usize> {
+        let mut seen = vec![false; self.nodes.len()];
+        seen[node] = true;
+        let mut result = Vec::new();
+        let mut queue: VecDeque<usize> = VecDeque::from(vec![node]);
+        while let Some(n) = queue.pop_front() {
+            for next in step(n) {
+                if !seen[next] {
+                    seen[next] = true;
+                    result.push(next);
+                    queue.push_back(next);
+                }
+            }
+        }
+        result
+    }
+
+    /** All broader terms up to the top of the hierarchy, nearest first **/
+    pub fn ancestors(&self, node: usize) -> Vec<usize> {
+        let broader = self.broader_lists();
+        self.transitive(node, |n| broader[n].clone())
+    }
+
+    /** All narrower terms, nearest first **/
+    pub fn descendants(&self, node: usize) -> Vec<usize> {
+        let broader = self.broader_lists();
+        let mut narrower = vec![Vec::new(); self.nodes.len()];
+        for (n, bs) in broader.iter().enumerate() {
+            for &b in bs.iter() {
+                narrower[b].push(n);
+            }
+        }
+        self.transitive(node, |n| narrower[n].clone())
+    }
+
+    fn broader_lists(&self) -> Vec<Vec<usize>> {
+        let mut broader = vec![Vec::new(); self.nodes.len()];
+        for e in self.edges.iter() {
+            match (e.to, &e.edge_type) {
+                (Some(to), t) if t.is_broader() => broader[e.from].push(to),
+                (Some(to), EdgeType::Narrower) => broader[to].push(e.from),
+                _ => (),
+            }
+        }
+        broader
+    }
+
+    /**
+     * Groups of nodes that are broader than themselves, i.e. the strongly
+     * connected components of the broader hierarchy with a cycle in them
+     */
+    pub fn cycles(&self) -> Vec<Vec<usize>> {
+        let broader = self.broader_lists();
+        let n = self.nodes.len();
+        // iterative Tarjan, authority files are too deep for recursion
+        let mut index = vec![usize::MAX; n];
+        let mut lowlink = vec![0; n];
+        let mut on_stack = vec![false; n];
+        let mut stack = Vec::new();
+        let mut next_index = 0;
+        let mut result = Vec::new();
+        for root in 0..n {
+            if index[root] != usize::MAX {
+                continue;
+            }
+            let mut call_stack = vec![(root, 0)];
+            while let Some(&mut (v, ref mut child)) = call_stack.last_mut() {
+                if *child == 0 {
+                    index[v] = next_index;
+                    lowlink[v] = next_index;
+                    next_index += 1;
+                    stack.push(v);
+                    on_stack[v] = true;
+                }
+                if *child < broader[v].len() {
+                    let w = broader[v][*child];
+                    *child += 1;
+                    if index[w] == usize::MAX {
+                        call_stack.push((w, 0));
+                    } else if on_stack[w] {
+                        lowlink[v] = lowlink[v].min(index[w]);
+                    }
+                    continue;
+                }
+                call_stack.pop();
+                if let Some(&(parent, _)) = call_stack.last() {
+                    lowlink[parent] = lowlink[parent].min(lowlink[v]);
+                }
+                if lowlink[v] == index[v] {
+                    let mut component = Vec::new();
+                    loop {
+                        let w = stack.pop().unwrap();
+                        on_stack[w] = false;
+                        component.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    if component.len() > 1 || broader[v].contains(&v) {
+                        component.reverse();
+                        result.push(component);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    fn target_id(&self, e: &Edge) -> String {
+        match e.to {
+            Some(to) => self.nodes[to].control_number.clone(),
+            None => e.target.clone(),
+        }
+    }
+
+    /** The node id of the target in DOT and GraphML **/
+    fn target_node_id(&self, e: &Edge) -> String {
+        match e.to {
+            Some(to) => self.nodes[to].control_number.clone(),
+            None => format!("{}{}", DANGLING_PREFIX, e.target),
+        }
+    }
+
+    /** The targets of dangling edges, each once **/
+    fn dangling_targets(&self) -> Vec<&str> {
+        let mut targets: Vec<&str> = Vec::new();
+        for e in self.dangling_edges() {
+            if !targets.contains(&e.target.as_str()) {
+                targets.push(&e.target);
+            }
+        }
+        targets
+    }
+
+    /**
+     * Dangling edges are dashed and point to a node labeled with their target
+     * identifier, whose id has the prefix `missing:`
+     */
+    pub fn write_dot(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        writeln!(writer, "digraph authorities {{")?;
+        for node in self.nodes.iter() {
+            writeln!(
+                writer,
+                "  {} [label={}];",
+                quote(&node.control_number),
+                quote(&node.label)
+            )?;
+        }
+        for target in self.dangling_targets() {
+            writeln!(
+                writer,
+                "  {} [label={}];",
+                quote(&format!("{}{}", DANGLING_PREFIX, target)),
+                quote(target)
+            )?;
+        }
+        for e in self.edges.iter() {
+            let style = if e.to.is_none() { ", style=dashed" } else { "" };
+            writeln!(
+                writer,
+                "  {} -> {} [label={}{}];",
+                quote(&self.nodes[e.from].control_number),
+                quote(&self.target_node_id(e)),
+                quote(e.edge_type.name()),
+                style
+            )?;
+        }
+        writeln!(writer, "}}")
+    }
+
+    /**
+     * Like in DOT, the targets of dangling edges become nodes labeled with
+     * their identifier, GraphML does not allow edges to unknown nodes
+     */
+    pub fn write_graphml(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(
+            writer,
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"
+        )?;
+        writeln!(
+            writer,
+            "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>"
+        )?;
+        writeln!(
+            writer,
+            "  <key id=\"type\" for=\"edge\" attr.name=\"type\" attr.type=\"string\"/>"
+        )?;
+        writeln!(
+            writer,
+            "  <graph id=\"authorities\" edgedefault=\"directed\">"
+        )?;
+        for node in self.nodes.iter() {
+            writeln!(
+                writer,
+                "    <node id=\"{}\"><data key=\"label\">{}</data></node>",
+                escape_xml(&node.control_number),
+                escape_xml(&node.label)
+            )?;
+        }
+        for target in self.dangling_targets() {
+            writeln!(
+                writer,
+                "    <node id=\"{}{}\"><data key=\"label\">{}</data></node>",
+                DANGLING_PREFIX,
+                escape_xml(target),
+                escape_xml(target)
+            )?;
+        }
+        for e in self.edges.iter() {
+            writeln!(
+                writer,
+                "    <edge source=\"{}\" target=\"{}\"><data key=\"type\">{}</data></edge>",
+                escape_xml(&self.nodes[e.from].control_number),
+                escape_xml(&self.target_node_id(e)),
+                escape_xml(e.edge_type.name())
+            )?;
+        }
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")
+    }
+
+    /** One line per edge, dangling edges have an empty target label **/
+    pub fn write_csv(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(writer, "source,target,type,source_label,target_label")?;
+        for e in self.edges.iter() {
+            let target_label = e.to.map_or("", |t| self.nodes[t].label.as_str());
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                escape_csv(&self.nodes[e.from].control_number),
+                escape_csv(&self.target_id(e)),
+                escape_csv(e.edge_type.name()),
+                escape_csv(&self.nodes[e.from].label),
+                escape_csv(target_label)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authoritygraph::*;
+    use crate::marcrecord::MarcHeader;
+    use crate::marcrecord::MarcRecord;
+    use crate::ownedrecord::OwnedRecord;
+    use crate::testutil::{record, AUTHORITY, STR};
+
+    /** `$` stands for the subfield delimiter **/
+    fn authority(control_number: &str, fields: &[(usize, &str)]) -> OwnedRecord {
+        let mut all = vec![(1, control_number.to_string())];
+        all.extend(
+            fields
+                .iter()
+                .map(|(t, data)| (*t, data.replace('$', "\x1f"))),
+        );
+        record(AUTHORITY, &all)
+    }
+
+    fn graph() -> AuthorityGraph {
+        let mut builder = AuthorityGraphBuilder::new();
+        builder.add_record(&MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]));
+        builder.add_record(&authority(
+            "040272427",
+            &[
+                (35, "  $a(DE-588)4027242-4"),
+                (150, "  $aIntegrierte Schaltung"),
+                (550, "  $0(DE-588)4000001-1$aElektronik$4obal"),
+            ],
+        ));
+        builder.add_record(&authority(
+            "040000011",
+            &[
+                (35, "  $a(DE-588)4000001-1"),
+                (150, "  $aElektronik"),
+                (550, "  $0(DE-588)4000003-5$aTechnik$4obin"),
+                (550, "  $aMikroelektronik$wh"),
+            ],
+        ));
+        builder.build()
+    }
+
+    #[test]
+    fn hierarchy() {
+        let g = graph();
+        assert_eq!(g.nodes().len(), 3);
+        assert_eq!(g.edges().len(), 4);
+        let a302 = g.node("040000028").unwrap();
+        let schaltung = g.node("040272427").unwrap();
+        let elektronik = g.node("040000011").unwrap();
+        assert_eq!(g.edges()[0].edge_type, EdgeType::BroaderGeneral);
+        assert_eq!(g.broader(a302), [schaltung]);
+        assert_eq!(g.narrower(schaltung), [a302]);
+        assert_eq!(g.ancestors(a302), [schaltung, elektronik]);
+        assert_eq!(g.descendants(elektronik), [schaltung, a302]);
+        let dangling: Vec<&str> = g.dangling_edges().map(|e| e.target.as_str()).collect();
+        assert_eq!(dangling, ["(DE-588)4000003-5", "Mikroelektronik"]);
+        assert!(g.cycles().is_empty());
+    }
+
+    #[test]
+    fn cycles() {
+        let mut builder = AuthorityGraphBuilder::new();
+        builder.add_record(&authority(
+            "1",
+            &[(150, "  $aA"), (550, "  $0(DE-588)2$aB$4obal")],
+        ));
+        builder.add_record(&authority(
+            "2",
+            &[
+                (35, "  $a(DE-588)2"),
+                (150, "  $aB"),
+                (550, "  $01$aA$4obal"),
+            ],
+        ));
+        builder.add_record(&authority("3", &[(150, "  $aC"), (550, "  $03$aC$wg")]));
+        let g = builder.build();
+        assert_eq!(g.cycles(), [vec![0, 1], vec![2]]);
+        assert_eq!(g.ancestors(0), [1]);
+    }
+
+    #[test]
+    fn dangling_ids() -> Result<(), String> {
+        // the heading of the dangling tracing is the control number of another record
+        let mut builder = AuthorityGraphBuilder::new();
+        builder.add_record(&authority("1", &[(150, "  $aA"), (550, "  $a2$4obal")]));
+        builder.add_record(&authority("2", &[(150, "  $aB")]));
+        let g = builder.build();
+        assert_eq!(g.dangling_edges().count(), 1);
+        let mut graphml = Vec::new();
+        g.write_graphml(&mut graphml).map_err(|e| e.to_string())?;
+        let graphml = String::from_utf8(graphml).unwrap();
+        assert_eq!(graphml.matches("<node id=\"2\">").count(), 1);
+        assert!(graphml.contains("<edge source=\"1\" target=\"missing:2\">"));
+        Ok(())
+    }
+
+    #[test]
+    fn export() -> Result<(), String> {
+        let g = graph();
+        let mut dot = Vec::new();
+        g.write_dot(&mut dot).map_err(|e| e.to_string())?;
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains("\"040000028\" -> \"040272427\" [label=\"obal\"];"));
+        assert!(dot.contains(
+            "\"040000011\" -> \"missing:Mikroelektronik\" [label=\"narrower\", style=dashed];"
+        ));
+        assert!(dot.contains("\"missing:Mikroelektronik\" [label=\"Mikroelektronik\"];"));
+        let mut graphml = Vec::new();
+        g.write_graphml(&mut graphml).map_err(|e| e.to_string())?;
+        let graphml = String::from_utf8(graphml).unwrap();
+        assert_eq!(graphml.matches("<edge ").count(), 4);
+        assert!(graphml.contains(
+            "<node id=\"missing:Mikroelektronik\"><data key=\"label\">Mikroelektronik</data></node>"
+        ));
+        assert!(graphml.contains("<edge source=\"040000011\" target=\"missing:Mikroelektronik\">"));
+        let mut csv = Vec::new();
+        g.write_csv(&mut csv).map_err(|e| e.to_string())?;
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[1],
+            "040000028,040272427,obal,A 302 D,Integrierte Schaltung"
+        );
+        Ok(())
+    }
+}
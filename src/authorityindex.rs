@@ -0,0 +1,241 @@
+use crate::authority::*;
+use crate::marcrecord::MarcReader;
+use crate::naco::normalize_heading;
+use crate::record::*;
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Seek;
+
+/** A record whose established (1XX) or variant (4XX) heading matched **/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeadingMatch {
+    // the position of the record in the file, counting from 0
+    pub position: usize,
+    pub established: bool,
+}
+
+/**
+ * In-memory index from headings and identifiers to the positions of authority
+ * records in a file.
+ */
+#[derive(Default)]
+pub struct AuthorityIndex {
+    headings: HashMap<String, Vec<HeadingMatch>>,
+    identifiers: HashMap<String, Vec<usize>>,
+    control_numbers: Vec<Option<String>>,
+}
+
+/**
+ * The key headings are indexed under, their NACO normalized form prefixed with
+ * the heading type, so a corporate body does not match a place of the same name
+ */
+pub fn heading_key(heading: &Heading) -> String {
+    format!("{:?} {}", heading.heading_type, normalize_heading(heading))
+}
+
+impl AuthorityIndex {
+    pub fn new() -> AuthorityIndex {
+        AuthorityIndex::default()
+    }
+
+    /** Index every record the reader yields, positions count from 0 **/
+    pub fn build<R: Read + Seek>(
+        reader: &mut MarcReader<R>,
+        buffer: &mut [u8],
+    ) -> std::io::Result<AuthorityIndex> {
+        let mut index = AuthorityIndex::new();
+        while let Some(batch) = reader.read_batch(buffer)? {
+            for record in batch.records.iter() {
+                index.add_record(record);
+            }
+        }
+        Ok(index)
+    }
+
+    /** Add the record at the next position, non-authority records only take up a position **/
+    pub fn add_record<R: Record + ?Sized>(&mut self, record: &R) {
+        let position = self.control_numbers.len();
+        let authority = match AuthorityRecord::new(record) {
+            Some(a) => a,
+            None => {
+                self.control_numbers.push(None);
+                return;
+            }
+        };
+        if let Some(heading) = authority.established_heading() {
+            self.add_heading(&heading, position, true);
+        }
+        for tracing in authority.variant_headings() {
+            self.add_heading(&tracing.heading, position, false);
+        }
+        for field in record.field_iter_vec(&[1, 24, 35]) {
+            if field.field_type == 1 {
+                self.add_identifier(&field.utf8_data_lossy(), position);
+                continue;
+            }
+            // 024 carries the identifier in $a and often a URI in $0, 035 only in $a
+            for value in field
+                .subfields()
+                .filter(|s| s.code() == b'a' || (field.field_type == 24 && s.code() == b'0'))
+            {
+                self.add_identifier(&value.utf8_value(), position);
+            }
+        }
+        self.control_numbers
+            .push(authority.control_number().map(|s| s.to_string()));
+    }
+
+    fn add_heading(&mut self, heading: &Heading, position: usize, established: bool) {
+        let matches = self.headings.entry(heading_key(heading)).or_default();
+        let m = HeadingMatch {
+            position,
+            established,
+        };
+        if !matches.contains(&m) {
+            matches.push(m);
+        }
+    }
+
+    fn add_identifier(&mut self, identifier: &str, position: usize) {
+        let positions = self.identifiers.entry(identifier.to_string()).or_default();
+        if !positions.contains(&position) {
+            positions.push(position);
+        }
+    }
+
+    /** The number of records added, including non-authority records **/
+    pub fn len(&self) -> usize {
+        self.control_numbers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.control_numbers.is_empty()
+    }
+
+    pub fn control_number(&self, position: usize) -> Option<&str> {
+        self.control_numbers.get(position)?.as_deref()
+    }
+
+    pub fn lookup_heading(&self, heading: &Heading) -> &[HeadingMatch] {
+        self.lookup_key(&heading_key(heading))
+    }
+
+    /** Look up a heading field of a record of the given type, e.g. a 650 of a bibliographic record **/
+    pub fn lookup_field(&self, field: &RecordField, record_type: RecordType) -> &[HeadingMatch] {
+        match Heading::from_field(field, record_type) {
+            Some(h) => self.lookup_heading(&h),
+            None => &[],
+        }
+    }
+
+    pub fn lookup_key(&self, key: &str) -> &[HeadingMatch] {
+        self.headings.get(key).map_or(&[], |v| v.as_slice())
+    }
+
+    /** Look up a 001, an 024 value or URI, or an 035 such as `(DE-588)4000002-3` **/
+    pub fn lookup_identifier(&self, identifier: &str) -> &[usize] {
+        self.identifiers
+            .get(identifier)
+            .map_or(&[], |v| v.as_slice())
+    }
+
+    /**
+     * The position of the single record the heading resolves to, preferring
+     * established headings over variants. None if there is no or no unique match.
+     */
+    pub fn resolve_heading(&self, heading: &Heading) -> Option<usize> {
+        let matches = self.lookup_heading(heading);
+        let established: Vec<&HeadingMatch> = matches.iter().filter(|m| m.established).collect();
+        match (established.len(), matches.len()) {
+            (1, _) => Some(established[0].position),
+            (0, 1) => Some(matches[0].position),
+            _ => None,
+        }
+    }
+
+    pub fn resolve_identifier(&self, identifier: &str) -> Option<usize> {
+        match self.lookup_identifier(identifier) {
+            [position] => Some(*position),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authorityindex::*;
+    use crate::marcrecord::MarcReader;
+    use crate::testutil::STR;
+    use std::io::Cursor;
+
+    fn heading(field_type: usize, text: &str) -> Heading {
+        let record_type = if field_type / 100 == 6 {
+            RecordType::LanguageMaterial
+        } else {
+            RecordType::Authority
+        };
+        Heading {
+            field_type,
+            heading_type: HeadingType::from_tag(field_type, record_type).unwrap(),
+            indicators: [b' ', b' '],
+            subfields: vec![(b'a', text.to_string())],
+        }
+    }
+
+    #[test]
+    fn build_and_lookup() -> Result<(), String> {
+        let mut data = STR.to_vec();
+        data.extend_from_slice(STR);
+        let mut reader = MarcReader::new(Cursor::new(data));
+        let mut buffer = vec![0; 1000];
+        let index = AuthorityIndex::build(&mut reader, &mut buffer).map_err(|e| e.to_string())?;
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.control_number(1), Some("040000028"));
+
+        let matches = index.lookup_heading(&heading(650, "a 302 d."));
+        assert_eq!(
+            matches,
+            [
+                HeadingMatch {
+                    position: 0,
+                    established: true
+                },
+                HeadingMatch {
+                    position: 1,
+                    established: true
+                }
+            ]
+        );
+        assert_eq!(index.resolve_heading(&heading(650, "A 302 D")), None);
+        assert!(index
+            .lookup_heading(&heading(650, "Integrierte Schaltung"))
+            .is_empty());
+
+        assert_eq!(index.lookup_identifier("040000028"), [0, 1]);
+        assert_eq!(index.lookup_identifier("(DE-588)4000002-3"), [0, 1]);
+        assert_eq!(
+            index.lookup_identifier("http://d-nb.info/gnd/4000002-3"),
+            [0, 1]
+        );
+        assert!(index.lookup_identifier("(DE-588c)4000002-3").is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn resolve() {
+        let mut index = AuthorityIndex::new();
+        index.add_heading(&heading(150, "Schaltung"), 0, true);
+        index.add_heading(&heading(450, "Schaltkreis"), 0, false);
+        index.add_heading(&heading(450, "Schaltung"), 1, false);
+        index.add_identifier("4000002-3", 1);
+        assert_eq!(index.resolve_heading(&heading(650, "schaltung")), Some(0));
+        assert_eq!(index.resolve_heading(&heading(650, "Schaltkreis")), Some(0));
+        assert_eq!(index.resolve_heading(&heading(650, "Schalter")), None);
+        assert_eq!(index.resolve_identifier("4000002-3"), Some(1));
+
+        index.add_heading(&heading(110, "Berlin"), 2, true);
+        index.add_heading(&heading(151, "Berlin"), 3, true);
+        assert_eq!(index.resolve_heading(&heading(610, "Berlin")), Some(2));
+        assert_eq!(index.resolve_heading(&heading(651, "Berlin")), Some(3));
+    }
+}
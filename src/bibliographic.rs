@@ -0,0 +1,306 @@
+use crate::authority::Heading;
+use crate::fixedfield::*;
+use crate::linkingentry::*;
+use crate::record::*;
+
+/**
+ * Strip trailing ISBD punctuation (` /`, ` :`, ` ;`, ` =`, `,` and a final
+ * period) and blanks. A period after an initial or an ellipsis is kept.
+ */
+pub fn strip_isbd(value: &str) -> &str {
+    let mut s = value.trim_end_matches([' ', '/', ':', ';', '=', ',']);
+    if s.ends_with('.') && !s.ends_with("...") {
+        let word = s[..s.len() - 1].rsplit([' ', '.']).next().unwrap_or("");
+        let is_initial = word.chars().count() == 1 && word.chars().all(char::is_uppercase);
+        if !is_initial {
+            s = s[..s.len() - 1].trim_end_matches([' ', '/', ':', ';', '=', ',']);
+        }
+    }
+    s.trim()
+}
+
+/** Publication statement from 260 or 264 **/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Imprint {
+    pub places: Vec<String>,
+    pub publishers: Vec<String>,
+    pub date: Option<String>,
+}
+
+/** Typed access to the commonly used data of a bibliographic record **/
+pub struct BibliographicRecord<'r, R: Record + ?Sized> {
+    record: &'r R,
+    record_type: RecordType,
+}
+
+impl<'r, R: Record + ?Sized> BibliographicRecord<'r, R> {
+    /** None if the record is not a bibliographic record **/
+    pub fn new(record: &'r R) -> Option<BibliographicRecord<'r, R>> {
+        let record_type = record.record_type()?;
+        if !record_type.is_bibliographic() {
+            return None;
+        }
+        Some(BibliographicRecord {
+            record,
+            record_type,
+        })
+    }
+
+    pub fn record(&self) -> &'r R {
+        self.record
+    }
+
+    pub fn control_number(&self) -> Option<&'r str> {
+        let f = self.record.field_iter(Some(1)).next()?;
+        std::str::from_utf8(f.data).ok()
+    }
+
+    fn first_field(&self, field_type: usize) -> Option<RecordField<'_>> {
+        self.record.field_iter(Some(field_type)).next()
+    }
+
+    fn values(&self, field_type: usize, code: u8) -> Vec<String> {
+        self.record
+            .field_iter(Some(field_type))
+            .flat_map(|f| {
+                f.subfield_values(code)
+                    .map(|s| strip_isbd(&s).to_string())
+                    .collect::<Vec<String>>()
+            })
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    /**
+     * The title proper, 245 $a with $n and $p, without the nonfiling characters
+     * given by the second indicator
+     */
+    pub fn title(&self) -> Option<String> {
+        let f = self.first_field(245)?;
+        let mut title = String::new();
+        for s in f
+            .subfields()
+            .filter(|s| matches!(s.code(), b'a' | b'n' | b'p'))
+        {
+            if !title.is_empty() {
+                title.push_str(". ");
+            }
+            title.push_str(strip_isbd(&s.utf8_value()));
+        }
+        let nonfiling = match f.indicator(1) {
+            Some(b) if b.is_ascii_digit() => (b - b'0') as usize,
+            _ => 0,
+        };
+        let skip = title
+            .char_indices()
+            .nth(nonfiling)
+            .map_or(title.len(), |(i, _)| i);
+        let title = title[skip..].to_string();
+        if title.is_empty() {
+            None
+        } else {
+            Some(title)
+        }
+    }
+
+    /** 245 $b **/
+    pub fn subtitle(&self) -> Option<String> {
+        self.values(245, b'b').into_iter().next()
+    }
+
+    /** 245 $c **/
+    pub fn statement_of_responsibility(&self) -> Option<String> {
+        self.values(245, b'c').into_iter().next()
+    }
+
+    /** 100, 110, 111 or 130 **/
+    pub fn main_entry(&self) -> Option<Heading> {
+        self.record
+            .field_iter_vec(&[100, 110, 111, 130])
+            .find_map(|f| Heading::from_field(&f, self.record_type))
+    }
+
+    /** 700, 710, 711 and 730, in record order **/
+    pub fn added_entries(&self) -> Vec<Heading> {
+        self.record
+            .field_iter_vec(&[700, 710, 711, 730])
+            .filter_map(|f| Heading::from_field(&f, self.record_type))
+            .collect()
+    }
+
+    /** 264 with second indicator 1 (publication), else 260 **/
+    pub fn imprint(&self) -> Option<Imprint> {
+        let field = self
+            .record
+            .field_iter(Some(264))
+            .find(|f| f.indicator(1) == Some(b'1'))
+            .or_else(|| self.first_field(260))?;
+        let values = |code| {
+            field
+                .subfield_values(code)
+                .map(|s| strip_isbd(&s).to_string())
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<String>>()
+        };
+        Some(Imprint {
+            places: values(b'a'),
+            publishers: values(b'b'),
+            date: values(b'c').into_iter().next(),
+        })
+    }
+
+    /** 250 $a **/
+    pub fn edition(&self) -> Option<String> {
+        self.values(250, b'a').into_iter().next()
+    }
+
+    /** 020 $a without qualifiers such as `(pbk.)` **/
+    pub fn isbns(&self) -> Vec<String> {
+        self.values(20, b'a')
+            .iter()
+            .filter_map(|s| s.split_whitespace().next())
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    /** 022 $a **/
+    pub fn issns(&self) -> Vec<String> {
+        self.values(22, b'a')
+    }
+
+    /** 010 $a **/
+    pub fn lccn(&self) -> Option<String> {
+        self.values(10, b'a').into_iter().next()
+    }
+
+    /** 035 $a with prefix `(OCoLC)`, without the prefix and the `ocm`, `ocn` or `on` of the number **/
+    pub fn oclc_numbers(&self) -> Vec<String> {
+        self.values(35, b'a')
+            .iter()
+            .filter_map(|s| s.strip_prefix("(OCoLC)"))
+            .map(|s| s.trim_start_matches(char::is_alphabetic).to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    /** 760 to 787 **/
+    pub fn linking_entries(&self) -> Vec<LinkingEntry> {
+        linking_entries(self.record)
+    }
+
+    /**
+     * The language codes of 008/35-37 and 041 $a, without duplicates. Old
+     * records run several codes together in one 041 $a, these are split.
+     */
+    pub fn languages(&self) -> Vec<String> {
+        let mut languages: Vec<String> = Vec::new();
+        let mut add = |code: &str| {
+            let code = code.trim();
+            if code.len() == 3 && code != "|||" && !languages.iter().any(|l| l == code) {
+                languages.push(code.to_string());
+            }
+        };
+        if let (Some(kind), Some(f)) = (
+            Field008Kind::for_leader(self.record.leader()),
+            self.first_field(8),
+        ) {
+            if let Some(language) = Field008::parse(kind, f.data)
+                .ok()
+                .as_ref()
+                .and_then(|f| f.language())
+            {
+                add(language);
+            }
+        }
+        for value in self.values(41, b'a') {
+            if value.len() % 3 == 0 && value.is_ascii() {
+                for i in (0..value.len()).step_by(3) {
+                    add(&value[i..i + 3]);
+                }
+            }
+        }
+        languages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bibliographic::*;
+    use crate::marcrecord::MarcHeader;
+    use crate::marcrecord::MarcRecord;
+    use crate::testutil::{record, BOOK, STR};
+
+    #[test]
+    fn isbd_punctuation() {
+        assert_eq!(strip_isbd("The title /"), "The title");
+        assert_eq!(strip_isbd("Berlin :"), "Berlin");
+        assert_eq!(strip_isbd("Springer,"), "Springer");
+        assert_eq!(strip_isbd("2nd ed."), "2nd ed");
+        assert_eq!(strip_isbd("Smith, John A."), "Smith, John A.");
+        assert_eq!(strip_isbd("And then..."), "And then...");
+        assert_eq!(strip_isbd("[2019]."), "[2019]");
+    }
+
+    #[test]
+    fn accessors() {
+        let r = record(
+            BOOK,
+            &[
+                (1, "12345"),
+                (8, "190101s2019    gw            000 0 ger d"),
+                (10, "  \x1fa   2019012345 "),
+                (20, "  \x1fa9783161484100 (pbk.) :\x1fc19.99 EUR"),
+                (20, "  \x1fz3161484100"),
+                (35, "  \x1fa(OCoLC)ocn123456789"),
+                (35, "  \x1fa(DE-599)DNB123"),
+                (41, "1 \x1faengfre\x1fhger"),
+                (100, "1 \x1faMüller, Hans,\x1fd1950-\x1feauthor."),
+                (
+                    245,
+                    "14\x1faThe handbook of things /\x1fbA guide :\x1fcHans Müller.",
+                ),
+                (250, "  \x1fa2nd ed."),
+                (260, "  \x1faLondon :\x1fbOld Press,\x1fc1990."),
+                (
+                    264,
+                    " 1\x1faBerlin ;\x1faNew York :\x1fbSpringer,\x1fc[2019]",
+                ),
+                (700, "1 \x1faSchmidt, Eva,\x1feeditor."),
+                (710, "2 \x1faDeutsche Forschungsgemeinschaft."),
+                (740, "0 \x1faOther title."),
+            ],
+        );
+        let b = BibliographicRecord::new(&r).unwrap();
+        assert_eq!(b.control_number(), Some("12345"));
+        assert_eq!(b.title().as_deref(), Some("handbook of things"));
+        assert_eq!(b.subtitle().as_deref(), Some("A guide"));
+        assert_eq!(
+            b.statement_of_responsibility().as_deref(),
+            Some("Hans Müller")
+        );
+        let main = b.main_entry().unwrap();
+        assert_eq!(main.field_type, 100);
+        assert_eq!(main.subfield(b'a'), Some("Müller, Hans,"));
+        let added: Vec<usize> = b.added_entries().iter().map(|h| h.field_type).collect();
+        assert_eq!(added, [700, 710]);
+        assert_eq!(
+            b.imprint(),
+            Some(Imprint {
+                places: vec!["Berlin".to_string(), "New York".to_string()],
+                publishers: vec!["Springer".to_string()],
+                date: Some("[2019]".to_string()),
+            })
+        );
+        assert_eq!(b.edition().as_deref(), Some("2nd ed"));
+        assert_eq!(b.isbns(), ["9783161484100"]);
+        assert_eq!(b.lccn().as_deref(), Some("2019012345"));
+        assert_eq!(b.oclc_numbers(), ["123456789"]);
+        assert_eq!(b.languages(), ["ger", "eng", "fre"]);
+    }
+
+    #[test]
+    fn not_bibliographic() {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        assert!(BibliographicRecord::new(&record).is_none());
+    }
+}
@@ -0,0 +1,88 @@
+/*!
+ * Compare two MARC files keyed by control number.
+ *
+ * Usage: marc-compare OLD NEW OUTDIR [--key TAG[$CODE]] [--diff] [--max-in-memory N]
+ *
+ * Writes added.mrc, deleted.mrc and changed.mrc, and with `--diff` also
+ * changed.diff, to OUTDIR.
+ */
+use marclib::compare::*;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::process::exit;
+
+fn usage() -> ! {
+    eprintln!("usage: marc-compare OLD NEW OUTDIR [--key TAG[$CODE]] [--diff] [--max-in-memory N]");
+    exit(2)
+}
+
+fn parse_key(s: &str, config: &mut CompareConfig) -> Option<()> {
+    let (tag, code) = match s.find('$') {
+        Some(i) => (&s[..i], Some(*s.as_bytes().get(i + 1)?)),
+        None => (s, None),
+    };
+    config.key_field = tag.parse().ok()?;
+    config.key_subfield = code;
+    Some(())
+}
+
+fn create(dir: &Path, name: &str) -> std::io::Result<BufWriter<File>> {
+    Ok(BufWriter::new(File::create(dir.join(name))?))
+}
+
+fn main() -> std::io::Result<()> {
+    let mut config = CompareConfig::default();
+    let mut paths = Vec::new();
+    let mut write_diffs = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--diff" => write_diffs = true,
+            "--key" => {
+                let key = args.next().unwrap_or_else(|| usage());
+                parse_key(&key, &mut config).unwrap_or_else(|| usage());
+            }
+            "--max-in-memory" => {
+                let n = args.next().and_then(|n| n.parse().ok());
+                config.max_in_memory = n.unwrap_or_else(|| usage());
+            }
+            _ if arg.starts_with("--") => usage(),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 3 {
+        usage();
+    }
+    let dir = Path::new(&paths[2]);
+    std::fs::create_dir_all(dir)?;
+    let mut added = create(dir, "added.mrc")?;
+    let mut deleted = create(dir, "deleted.mrc")?;
+    let mut changed = create(dir, "changed.mrc")?;
+    let mut diffs = if write_diffs {
+        Some(create(dir, "changed.diff")?)
+    } else {
+        None
+    };
+    let summary = compare_files(
+        Path::new(&paths[0]),
+        Path::new(&paths[1]),
+        CompareOutput {
+            added: &mut added,
+            deleted: &mut deleted,
+            changed: &mut changed,
+            diffs: diffs.as_mut().map(|d| d as &mut dyn std::io::Write),
+        },
+        &config,
+    )?;
+    eprintln!(
+        "added {}, deleted {}, changed {}, unchanged {}, without key {}, duplicate keys {}",
+        summary.added,
+        summary.deleted,
+        summary.changed,
+        summary.unchanged,
+        summary.missing_keys,
+        summary.duplicate_keys
+    );
+    Ok(())
+}
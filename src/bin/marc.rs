@@ -0,0 +1,388 @@
+/*!
+ * Inspect, convert and filter MARC files.
+ *
+ * Usage: marc [--strict | --lenient] COMMAND [OPTIONS] [FILE...]
+ *
+ * Reads ISO 2709, MARCXML, MARC-in-JSON and MRK, compressed or not, from the
+ * files or from standard input. By default the first damaged record stops
+ * the command, with `--lenient` damaged records are skipped with a warning.
+ */
+use marclib::compression;
+use marclib::ownedrecord::OwnedRecord;
+use marclib::profiler::Profile;
+use marclib::record::*;
+use marclib::recordreader::{open_any, open_any_reader, Format, RecordReader};
+use marclib::recordwriter::{create_writer, RecordWriter};
+use regex::Regex;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Error, Write};
+use std::path::Path;
+use std::process::exit;
+
+const USAGE: &str = "usage: marc [--strict | --lenient] COMMAND [OPTIONS] [FILE...]
+
+commands:
+  dump                      print the records in mnemonic (MRK) form
+  count                     count the records by type and status
+  profile [--json]          tag, subfield, indicator and leader value frequencies
+  convert                   write the records in another format
+  filter CONDITION...       keep the records matching all conditions
+  head [-n N]               the first N records, 10 by default
+  tail [-n N]               the last N records, 10 by default
+  slice START[:END]         the records from START to before END, counting from 0
+
+options for the commands writing records:
+  -o, --output FILE         write to FILE, compressed according to its extension
+  -t, --to FORMAT           iso2709, marcxml, json or mrk, by default guessed
+                            from the output file name, else iso2709 (mrk for dump)
+
+conditions:
+  --has TAG                 a field with the tag exists
+  --lacks TAG               no field with the tag exists
+  --match TAG[$CODE]=REGEX  a field or subfield matches the regular expression,
+                            subfield delimiters of whole fields are written $";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2)
+}
+
+enum Condition {
+    Has(usize),
+    Lacks(usize),
+    Matches(usize, Option<u8>, Regex),
+}
+
+impl Condition {
+    fn parse_tag(tag: &str) -> usize {
+        match tag.parse() {
+            Ok(t) if tag.len() == 3 => t,
+            _ => usage(),
+        }
+    }
+
+    /** Parse `TAG[$CODE]=REGEX` **/
+    fn parse_match(s: &str) -> Condition {
+        let (key, regex) = s.split_once('=').unwrap_or_else(|| usage());
+        let (tag, code) = match key.split_once('$') {
+            Some((tag, code)) if code.len() == 1 => (tag, Some(code.as_bytes()[0])),
+            Some(_) => usage(),
+            None => (key, None),
+        };
+        let regex = Regex::new(regex).unwrap_or_else(|e| {
+            eprintln!("invalid regular expression: {}", e);
+            exit(2)
+        });
+        Condition::Matches(Condition::parse_tag(tag), code, regex)
+    }
+
+    fn matches(&self, record: &OwnedRecord) -> bool {
+        match self {
+            Condition::Has(tag) => record.field_iter(Some(*tag)).next().is_some(),
+            Condition::Lacks(tag) => record.field_iter(Some(*tag)).next().is_none(),
+            Condition::Matches(tag, None, regex) => record
+                .field_iter(Some(*tag))
+                .any(|f| regex.is_match(&String::from_utf8_lossy(f.data).replace('\x1f', "$"))),
+            Condition::Matches(tag, Some(code), regex) => record.field_iter(Some(*tag)).any(|f| {
+                f.subfields()
+                    .filter(|s| s.code() == *code)
+                    .any(|s| regex.is_match(&String::from_utf8_lossy(s.value())))
+            }),
+        }
+    }
+}
+
+/** The input files, standard input if there are none **/
+struct Input {
+    paths: Vec<String>,
+    lenient: bool,
+    // damaged records skipped in lenient mode
+    skipped: usize,
+}
+
+impl Input {
+    fn open(&self, path: &str) -> std::io::Result<Box<dyn RecordReader>> {
+        if path == "-" {
+            open_any_reader(std::io::stdin())
+        } else {
+            open_any(Path::new(path))
+        }
+    }
+
+    /**
+     * Call `f` with every record of all inputs, until it returns false.
+     * Damaged records stop reading, or are skipped in lenient mode. The
+     * readers report every damaged record with exactly one error.
+     */
+    fn for_each_record(
+        &mut self,
+        mut f: impl FnMut(OwnedRecord) -> std::io::Result<bool>,
+    ) -> std::io::Result<()> {
+        let paths = if self.paths.is_empty() {
+            vec!["-".to_string()]
+        } else {
+            self.paths.clone()
+        };
+        for path in paths {
+            let context = |e: Error| Error::new(e.kind(), format!("{}: {}", path, e));
+            let mut reader = self.open(&path).map_err(context)?;
+            loop {
+                match reader.read_record() {
+                    Ok(Some(record)) => {
+                        if !f(record)? {
+                            return Ok(());
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) if self.lenient => {
+                        eprintln!("warning: {}", context(e));
+                        self.skipped += 1;
+                    }
+                    Err(e) => return Err(context(e)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/** The output file, standard output if there is none **/
+struct Output {
+    path: Option<String>,
+    format: Option<Format>,
+}
+
+impl Output {
+    /** Call `f` with a writer for the output, completing the output afterwards **/
+    fn with_writer(
+        &self,
+        default_format: Format,
+        f: impl FnOnce(&mut dyn RecordWriter) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        match &self.path {
+            Some(path) => {
+                let path = Path::new(path);
+                let format = self
+                    .format
+                    .or_else(|| Format::from_path(path))
+                    .unwrap_or(default_format);
+                let mut out = compression::create(path)?;
+                let mut writer = create_writer(&mut out, format)?;
+                f(writer.as_mut())?;
+                writer.finish()?;
+                drop(writer);
+                out.finish()?;
+            }
+            None => {
+                let stdout = std::io::stdout();
+                let format = self.format.unwrap_or(default_format);
+                let mut writer = create_writer(BufWriter::new(stdout.lock()), format)?;
+                f(writer.as_mut())?;
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/** The records to write: those matching all conditions, then a range of them or the last ones **/
+struct Selection {
+    conditions: Vec<Condition>,
+    start: usize,
+    end: Option<usize>,
+    last: Option<usize>,
+}
+
+fn count(input: &mut Input) -> std::io::Result<()> {
+    let mut records = 0;
+    let mut types = BTreeMap::new();
+    let mut statuses = BTreeMap::new();
+    input.for_each_record(|record| {
+        records += 1;
+        let leader = record.leader();
+        let record_type = match record.record_type() {
+            Some(t) => format!("{:?}", t),
+            None => format!("unknown ({})", leader[6] as char),
+        };
+        *types.entry(record_type).or_insert(0) += 1;
+        *statuses.entry(leader[5] as char).or_insert(0) += 1;
+        Ok(true)
+    })?;
+    println!("records\t{}", records);
+    for (record_type, n) in types {
+        println!("type\t{}\t{}", record_type, n);
+    }
+    for (status, n) in statuses {
+        println!("status\t{}\t{}", status, n);
+    }
+    Ok(())
+}
+
+fn profile(input: &mut Input, output: &Output, json: bool) -> std::io::Result<()> {
+    let mut profile = Profile::new();
+    input.for_each_record(|record| {
+        profile.add_record(&record);
+        Ok(true)
+    })?;
+    let mut out: Box<dyn Write> = match &output.path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+    if json {
+        profile.write_json(&mut out)?;
+    } else {
+        profile.write_table(&mut out)?;
+    }
+    out.flush()
+}
+
+fn copy(
+    input: &mut Input,
+    output: &Output,
+    default_format: Format,
+    selection: &Selection,
+) -> std::io::Result<()> {
+    output.with_writer(default_format, |writer| {
+        // position among the matching records
+        let mut position = 0;
+        let mut last = VecDeque::new();
+        input.for_each_record(|record| {
+            if !selection.conditions.iter().all(|c| c.matches(&record)) {
+                return Ok(true);
+            }
+            if selection.end.is_some_and(|end| position >= end) {
+                return Ok(false);
+            }
+            if position >= selection.start {
+                match selection.last {
+                    Some(n) => {
+                        if last.len() == n {
+                            last.pop_front();
+                        }
+                        if n > 0 {
+                            last.push_back(record);
+                        }
+                    }
+                    None => writer.write_record(&record)?,
+                }
+            }
+            position += 1;
+            Ok(true)
+        })?;
+        for record in &last {
+            writer.write_record(record)?;
+        }
+        Ok(())
+    })
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("marc: {}", e);
+        exit(1)
+    }
+}
+
+fn run() -> std::io::Result<()> {
+    let mut input = Input {
+        paths: Vec::new(),
+        lenient: false,
+        skipped: 0,
+    };
+    let mut output = Output {
+        path: None,
+        format: None,
+    };
+    let mut selection = Selection {
+        conditions: Vec::new(),
+        start: 0,
+        end: None,
+        last: None,
+    };
+    let mut count_arg = 10;
+    let mut json = false;
+    let mut range = None;
+    let mut command = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strict" => input.lenient = false,
+            "--lenient" => input.lenient = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            "-o" | "--output" => output.path = Some(args.next().unwrap_or_else(|| usage())),
+            "-t" | "--to" => {
+                let format = args.next().and_then(|f| Format::from_name(&f));
+                output.format = Some(format.unwrap_or_else(|| usage()));
+            }
+            "--json" => json = true,
+            "-n" => {
+                let n = args.next().and_then(|n| n.parse().ok());
+                count_arg = n.unwrap_or_else(|| usage());
+            }
+            "--has" => {
+                let tag = args.next().unwrap_or_else(|| usage());
+                let condition = Condition::Has(Condition::parse_tag(&tag));
+                selection.conditions.push(condition);
+            }
+            "--lacks" => {
+                let tag = args.next().unwrap_or_else(|| usage());
+                let condition = Condition::Lacks(Condition::parse_tag(&tag));
+                selection.conditions.push(condition);
+            }
+            "--match" => {
+                let condition = args.next().unwrap_or_else(|| usage());
+                selection
+                    .conditions
+                    .push(Condition::parse_match(&condition));
+            }
+            _ if arg.starts_with('-') && arg != "-" => usage(),
+            _ if command.is_none() => command = Some(arg),
+            _ if command.as_deref() == Some("slice") && range.is_none() => {
+                range = Some(parse_range(&arg).unwrap_or_else(|| usage()));
+            }
+            _ => input.paths.push(arg),
+        }
+    }
+    let command = command.unwrap_or_else(|| usage());
+    let default_format = match command.as_str() {
+        "count" | "profile" => None,
+        "filter" if selection.conditions.is_empty() => usage(),
+        "dump" => Some(Format::Mrk),
+        "convert" | "filter" => Some(Format::Iso2709),
+        "head" => {
+            selection.end = Some(count_arg);
+            Some(Format::Iso2709)
+        }
+        "tail" => {
+            selection.last = Some(count_arg);
+            Some(Format::Iso2709)
+        }
+        "slice" => {
+            (selection.start, selection.end) = range.unwrap_or_else(|| usage());
+            Some(Format::Iso2709)
+        }
+        _ => usage(),
+    };
+    match default_format {
+        Some(format) => copy(&mut input, &output, format, &selection)?,
+        None if command == "profile" => profile(&mut input, &output, json)?,
+        None => count(&mut input)?,
+    }
+    if input.skipped > 0 {
+        eprintln!("skipped {} damaged records", input.skipped);
+    }
+    Ok(())
+}
+
+/** Parse `START[:END]` **/
+fn parse_range(s: &str) -> Option<(usize, Option<usize>)> {
+    match s.split_once(':') {
+        Some((start, "")) => Some((start.parse().ok()?, None)),
+        Some((start, end)) => Some((start.parse().ok()?, Some(end.parse().ok()?))),
+        None => Some((s.parse().ok()?, None)),
+    }
+}
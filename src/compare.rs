@@ -0,0 +1,356 @@
+/*!
+ * Comparison of two MARC files, e.g. consecutive dumps of an authority file.
+ *
+ * Both files are read once to collect a key, a 128 bit content hash and the
+ * position of every record. These entries are sorted by key, in memory or, for files
+ * with more records than `max_in_memory`, in sorted runs spilled to temporary
+ * files that are merged afterwards. Joining the sorted entries of both files
+ * yields the added, deleted and changed records, which are then copied from
+ * the input files to the outputs. Records with equal hashes are taken as
+ * unchanged without reading them again.
+ */
+use crate::diff::diff;
+use crate::marcrecord::{MarcHeader, MarcReader, MarcRecord};
+use crate::record::*;
+use crate::sortedruns::{RunEntry, SortedEntries, SortedRuns};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::Xxh3;
+
+#[derive(Debug, Clone)]
+pub struct CompareConfig {
+    // records are keyed by the first occurrence of this field
+    pub key_field: usize,
+    // and of this subfield, None for control fields
+    pub key_subfield: Option<u8>,
+    // entries held in memory per file before sorted runs are spilled to disk
+    pub max_in_memory: usize,
+    pub temp_dir: PathBuf,
+    // read buffer, must hold the largest record
+    pub buffer_size: usize,
+}
+
+impl Default for CompareConfig {
+    fn default() -> CompareConfig {
+        CompareConfig {
+            key_field: 1,
+            key_subfield: None,
+            max_in_memory: 1_000_000,
+            temp_dir: std::env::temp_dir(),
+            buffer_size: 1 << 20,
+        }
+    }
+}
+
+/** Where the records of each category go, `diffs` receives a text diff per changed record **/
+pub struct CompareOutput<'a> {
+    pub added: &'a mut dyn Write,
+    pub deleted: &'a mut dyn Write,
+    pub changed: &'a mut dyn Write,
+    pub diffs: Option<&'a mut dyn Write>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompareSummary {
+    pub added: usize,
+    pub deleted: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+    // records without the key field, they are ignored
+    pub missing_keys: usize,
+    // records whose key an earlier record of the same file already had, they are ignored
+    pub duplicate_keys: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Entry {
+    key: String,
+    // the position in the file breaks ties, so the first of duplicates comes first
+    offset: u64,
+    len: u32,
+    hash: u128,
+}
+
+impl RunEntry for Entry {
+    fn write(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        writer.write_all(&(self.key.len() as u32).to_le_bytes())?;
+        writer.write_all(self.key.as_bytes())?;
+        writer.write_all(&self.offset.to_le_bytes())?;
+        writer.write_all(&self.len.to_le_bytes())?;
+        writer.write_all(&self.hash.to_le_bytes())
+    }
+
+    fn read(reader: &mut dyn Read) -> std::io::Result<Option<Entry>> {
+        let mut len = [0; 4];
+        match reader.read_exact(&mut len) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+        let mut key = vec![0; u32::from_le_bytes(len) as usize];
+        reader.read_exact(&mut key)?;
+        let (mut offset, mut len, mut hash) = ([0; 8], [0; 4], [0; 16]);
+        reader.read_exact(&mut offset)?;
+        reader.read_exact(&mut len)?;
+        reader.read_exact(&mut hash)?;
+        Ok(Some(Entry {
+            key: String::from_utf8_lossy(&key).to_string(),
+            offset: u64::from_le_bytes(offset),
+            len: u32::from_le_bytes(len),
+            hash: u128::from_le_bytes(hash),
+        }))
+    }
+}
+
+/** The value of the first `field`, or of its first `subfield`, trimmed for control fields **/
+pub(crate) fn record_key<R: Record + ?Sized>(
+    record: &R,
+    field: usize,
+    subfield: Option<u8>,
+) -> Option<String> {
+    let field = record.field_iter(Some(field)).next()?;
+    match subfield {
+        Some(code) => field.subfield_values(code).next().map(|s| s.to_string()),
+        None => Some(field.utf8_data_lossy().trim().to_string()),
+    }
+}
+
+fn collect_entries(
+    path: &Path,
+    config: &CompareConfig,
+    missing_keys: &mut usize,
+) -> std::io::Result<SortedRuns<Entry>> {
+    let mut reader = MarcReader::new(File::open(path)?);
+    let mut buffer = vec![0; config.buffer_size];
+    let mut entries = SortedRuns::new("compare", config.max_in_memory, &config.temp_dir);
+    let mut offset = 0u64;
+    while let Some(batch) = reader.read_batch(&mut buffer)? {
+        for record in batch.records.iter() {
+            let len = record.record_length();
+            let mut hasher = Xxh3::new();
+            hasher.update(record.header().header);
+            hasher.update(record.data());
+            match record_key(record, config.key_field, config.key_subfield) {
+                Some(key) => entries.push(Entry {
+                    key,
+                    offset,
+                    len: len as u32,
+                    hash: hasher.digest128(),
+                })?,
+                None => *missing_keys += 1,
+            }
+            offset += len as u64;
+        }
+    }
+    Ok(entries)
+}
+
+/** The next entry with a key not seen before, counting skipped duplicates **/
+fn next_unique(
+    entries: &mut SortedEntries<Entry>,
+    last: &mut Option<String>,
+    duplicates: &mut usize,
+) -> std::io::Result<Option<Entry>> {
+    while let Some(entry) = entries.next_entry()? {
+        if last.as_deref() == Some(entry.key.as_str()) {
+            *duplicates += 1;
+            continue;
+        }
+        *last = Some(entry.key.clone());
+        return Ok(Some(entry));
+    }
+    Ok(None)
+}
+
+fn read_record(file: &mut File, entry: &Entry) -> std::io::Result<Vec<u8>> {
+    let mut data = vec![0; entry.len as usize];
+    file.seek(SeekFrom::Start(entry.offset))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/**
+ * Compare two files, writing the records only in `new` to `added`, the
+ * records only in `old` to `deleted` and the new version of records whose
+ * content differs to `changed`, all in key order.
+ */
+pub fn compare_files(
+    old: &Path,
+    new: &Path,
+    output: CompareOutput,
+    config: &CompareConfig,
+) -> std::io::Result<CompareSummary> {
+    let mut summary = CompareSummary::default();
+    let mut old_runs = collect_entries(old, config, &mut summary.missing_keys)?;
+    let mut new_runs = collect_entries(new, config, &mut summary.missing_keys)?;
+    let mut old_entries = old_runs.sorted()?;
+    let mut new_entries = new_runs.sorted()?;
+    let (mut old_file, mut new_file) = (File::open(old)?, File::open(new)?);
+    let (mut old_last, mut new_last) = (None, None);
+    let mut o = next_unique(&mut old_entries, &mut old_last, &mut summary.duplicate_keys)?;
+    let mut n = next_unique(&mut new_entries, &mut new_last, &mut summary.duplicate_keys)?;
+    let CompareOutput {
+        added,
+        deleted,
+        changed,
+        mut diffs,
+    } = output;
+    loop {
+        let ordering = match (&o, &n) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(a), Some(b)) => a.key.cmp(&b.key),
+        };
+        match ordering {
+            Ordering::Less => {
+                let entry = o.take().unwrap();
+                deleted.write_all(&read_record(&mut old_file, &entry)?)?;
+                summary.deleted += 1;
+            }
+            Ordering::Greater => {
+                let entry = n.take().unwrap();
+                added.write_all(&read_record(&mut new_file, &entry)?)?;
+                summary.added += 1;
+            }
+            Ordering::Equal => {
+                let (a, b) = (o.take().unwrap(), n.take().unwrap());
+                if a.hash == b.hash && a.len == b.len {
+                    summary.unchanged += 1;
+                } else {
+                    let new_data = read_record(&mut new_file, &b)?;
+                    changed.write_all(&new_data)?;
+                    summary.changed += 1;
+                    if let Some(writer) = diffs.as_mut() {
+                        let old_data = read_record(&mut old_file, &a)?;
+                        let old_record =
+                            MarcRecord::new(MarcHeader::new(&old_data[..24]), &old_data[24..]);
+                        let new_record =
+                            MarcRecord::new(MarcHeader::new(&new_data[..24]), &new_data[24..]);
+                        writeln!(writer, "=== {}", b.key)?;
+                        write!(writer, "{}", diff(&old_record, &new_record))?;
+                    }
+                }
+            }
+        }
+        if o.is_none() {
+            o = next_unique(&mut old_entries, &mut old_last, &mut summary.duplicate_keys)?;
+        }
+        if n.is_none() {
+            n = next_unique(&mut new_entries, &mut new_last, &mut summary.duplicate_keys)?;
+        }
+    }
+    added.flush()?;
+    deleted.flush()?;
+    changed.flush()?;
+    if let Some(writer) = diffs {
+        writer.flush()?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compare::*;
+    use crate::testutil;
+
+    fn record(control_number: &str, title: &str) -> Vec<u8> {
+        let fields = [
+            (1, control_number.to_string()),
+            (150, format!("  \x1fa{}", title)),
+        ];
+        let mut out = Vec::new();
+        testutil::record(testutil::AUTHORITY, &fields)
+            .to_marc21(&mut out)
+            .unwrap();
+        out
+    }
+
+    fn write_file(dir: &Path, name: &str, records: &[Vec<u8>]) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, records.concat()).unwrap();
+        path
+    }
+
+    fn run(max_in_memory: usize) -> (CompareSummary, Vec<u8>, Vec<u8>, Vec<u8>, String) {
+        let dir = std::env::temp_dir().join(format!(
+            "marclib-compare-test-{}-{}",
+            std::process::id(),
+            max_in_memory
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let old = write_file(
+            &dir,
+            "old.mrc",
+            &[
+                record("3", "Drei"),
+                record("1", "Eins"),
+                record("2", "Zwei"),
+                record("5", "Fünf"),
+                record("1", "Eins doppelt"),
+            ],
+        );
+        let new = write_file(
+            &dir,
+            "new.mrc",
+            &[
+                record("4", "Vier"),
+                record("2", "Zwei"),
+                record("1", "Eins!"),
+                record("5", "Fünf"),
+            ],
+        );
+        let (mut added, mut deleted, mut changed, mut diffs) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let config = CompareConfig {
+            max_in_memory,
+            temp_dir: dir.clone(),
+            ..CompareConfig::default()
+        };
+        let summary = compare_files(
+            &old,
+            &new,
+            CompareOutput {
+                added: &mut added,
+                deleted: &mut deleted,
+                changed: &mut changed,
+                diffs: Some(&mut diffs),
+            },
+            &config,
+        )
+        .unwrap();
+        let leftover = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(leftover, 2, "run files are removed");
+        (
+            summary,
+            added,
+            deleted,
+            changed,
+            String::from_utf8(diffs).unwrap(),
+        )
+    }
+
+    #[test]
+    fn in_memory_and_spilled() {
+        for max_in_memory in [100, 2] {
+            let (summary, added, deleted, changed, diffs) = run(max_in_memory);
+            assert_eq!(
+                summary,
+                CompareSummary {
+                    added: 1,
+                    deleted: 1,
+                    changed: 1,
+                    unchanged: 2,
+                    missing_keys: 0,
+                    duplicate_keys: 1,
+                }
+            );
+            assert_eq!(added, record("4", "Vier"));
+            assert_eq!(deleted, record("3", "Drei"));
+            assert_eq!(changed, record("1", "Eins!"));
+            assert_eq!(diffs, "=== 1\n~ 150\n    ~ $aEins -> $aEins!\n");
+        }
+    }
+}
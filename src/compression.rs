@@ -0,0 +1,266 @@
+/*!
+ * Transparent decompression of input and compression of output.
+ *
+ * Compressed input is recognized by its magic bytes, output is compressed
+ * according to the file extension. Each format needs its cargo feature:
+ * `gzip`, `bzip2`, `zstd` and `xz`, or `compression` for all of them.
+ * Compressed streams cannot seek, read them with `MarcStreamReader`.
+ */
+use crate::marcrecord::MarcStreamReader;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /** Recognize the format by the first bytes of the data **/
+    pub fn from_magic(data: &[u8]) -> Compression {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if data.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if data.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+
+    /** Guess the format from the file extension, e.g. `.mrc.gz` **/
+    pub fn from_path(path: &Path) -> Compression {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") | Some("gzip") => Compression::Gzip,
+            Some("bz2") => Compression::Bzip2,
+            Some("zst") | Some("zstd") => Compression::Zstd,
+            Some("xz") => Compression::Xz,
+            _ => Compression::None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Bzip2 => "bzip2",
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+        }
+    }
+}
+
+#[allow(dead_code)]
+fn unsupported(compression: Compression) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "{} support is not enabled, build with the {} feature",
+            compression.name(),
+            compression.name()
+        ),
+    )
+}
+
+/** Wrap the reader in a decoder for the compression its data starts with **/
+pub fn decompress<R: Read + Send + 'static>(reader: R) -> std::io::Result<Box<dyn Read + Send>> {
+    let mut reader = BufReader::new(reader);
+    let compression = Compression::from_magic(reader.fill_buf()?);
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+        #[cfg(feature = "xz")]
+        Compression::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+        #[allow(unreachable_patterns)]
+        other => return Err(unsupported(other)),
+    })
+}
+
+/** Open a file, decompressing it if needed **/
+pub fn open(path: &Path) -> std::io::Result<MarcStreamReader<Box<dyn Read + Send>>> {
+    Ok(MarcStreamReader::new(decompress(File::open(path)?)?))
+}
+
+/**
+ * A writer compressing its output. The compressed stream has to be completed
+ * with `finish`, dropping the writer may lose data.
+ */
+pub enum CompressedWriter<W: Write> {
+    None(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "bzip2")]
+    Bzip2(bzip2::write::BzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    #[cfg(feature = "xz")]
+    Xz(xz2::write::XzEncoder<W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    pub fn new(writer: W, compression: Compression) -> std::io::Result<CompressedWriter<W>> {
+        Ok(match compression {
+            Compression::None => CompressedWriter::None(writer),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => CompressedWriter::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => CompressedWriter::Bzip2(bzip2::write::BzEncoder::new(
+                writer,
+                bzip2::Compression::default(),
+            )),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                CompressedWriter::Zstd(zstd::stream::write::Encoder::new(writer, 0)?)
+            }
+            #[cfg(feature = "xz")]
+            Compression::Xz => CompressedWriter::Xz(xz2::write::XzEncoder::new(writer, 6)),
+            #[allow(unreachable_patterns)]
+            other => return Err(unsupported(other)),
+        })
+    }
+
+    /** Complete the compressed stream and return the underlying writer **/
+    pub fn finish(self) -> std::io::Result<W> {
+        match self {
+            CompressedWriter::None(mut w) => {
+                w.flush()?;
+                Ok(w)
+            }
+            #[cfg(feature = "gzip")]
+            CompressedWriter::Gzip(w) => w.finish(),
+            #[cfg(feature = "bzip2")]
+            CompressedWriter::Bzip2(w) => w.finish(),
+            #[cfg(feature = "zstd")]
+            CompressedWriter::Zstd(w) => w.finish(),
+            #[cfg(feature = "xz")]
+            CompressedWriter::Xz(w) => w.finish(),
+        }
+    }
+
+    fn inner(&mut self) -> &mut dyn Write {
+        match self {
+            CompressedWriter::None(w) => w,
+            #[cfg(feature = "gzip")]
+            CompressedWriter::Gzip(w) => w,
+            #[cfg(feature = "bzip2")]
+            CompressedWriter::Bzip2(w) => w,
+            #[cfg(feature = "zstd")]
+            CompressedWriter::Zstd(w) => w,
+            #[cfg(feature = "xz")]
+            CompressedWriter::Xz(w) => w,
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+/** Create a file, compressed according to its extension **/
+pub fn create(path: &Path) -> std::io::Result<CompressedWriter<BufWriter<File>>> {
+    CompressedWriter::new(
+        BufWriter::new(File::create(path)?),
+        Compression::from_path(path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compression::*;
+    use crate::record::*;
+    use crate::testutil::{record, AUTHORITY};
+
+    fn records() -> Vec<u8> {
+        let mut out = Vec::new();
+        for n in 0..50 {
+            record(AUTHORITY, &[(1, n.to_string())])
+                .to_marc21(&mut out)
+                .unwrap();
+        }
+        out
+    }
+
+    fn roundtrip(compression: Compression) -> Result<(), String> {
+        let data = records();
+        let mut writer =
+            CompressedWriter::new(Vec::new(), compression).map_err(|e| e.to_string())?;
+        writer.write_all(&data).map_err(|e| e.to_string())?;
+        let compressed = writer.finish().map_err(|e| e.to_string())?;
+        assert_eq!(Compression::from_magic(&compressed), compression);
+        let mut reader = MarcStreamReader::new(
+            decompress(std::io::Cursor::new(compressed)).map_err(|e| e.to_string())?,
+        );
+        let mut n = 0;
+        while let Some(record) = reader.read_record().map_err(|e| e.to_string())? {
+            let control_number = record.field_iter(Some(1)).next().ok_or("001")?;
+            assert_eq!(control_number.utf8_data(), n.to_string());
+            n += 1;
+        }
+        assert_eq!(n, 50);
+        Ok(())
+    }
+
+    #[test]
+    fn detection() {
+        assert_eq!(
+            Compression::from_path(Path::new("gnd.mrc.gz")),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::from_path(Path::new("gnd.mrc")),
+            Compression::None
+        );
+        assert_eq!(Compression::from_magic(b"00827nz"), Compression::None);
+    }
+
+    #[test]
+    fn uncompressed() -> Result<(), String> {
+        roundtrip(Compression::None)
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip() -> Result<(), String> {
+        roundtrip(Compression::Gzip)
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn bzip2() -> Result<(), String> {
+        roundtrip(Compression::Bzip2)
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() -> Result<(), String> {
+        roundtrip(Compression::Zstd)
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn xz() -> Result<(), String> {
+        roundtrip(Compression::Xz)
+    }
+}
@@ -0,0 +1,882 @@
+/*!
+ * Duplicate detection across bibliographic records.
+ *
+ * Every record is reduced to a `MatchKey` of normalized values. Records that
+ * share a blocking key (an ISBN, an ISSN, or the start of the title together
+ * with the date) are compared pairwise, and pairs scoring at least the
+ * threshold are joined into clusters. Blocking keeps the comparisons close to
+ * linear in the number of records. The blocking keys are sorted on disk when
+ * there are more than fit into memory, and the match keys are then moved to a
+ * temporary file and read back by offset for each block.
+ */
+use crate::bibliographic::BibliographicRecord;
+use crate::fixedfield::*;
+use crate::marcrecord::MarcReader;
+use crate::naco::normalize;
+use crate::record::*;
+use crate::recordreader::invalid;
+use crate::sortedruns::{RunEntry, SortedRuns, TempFile};
+use crate::standardnumber::{split_qualifier, Isbn, Issn};
+use crate::util::escape_csv;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatchElement {
+    Isbn,
+    Issn,
+    Title,
+    Date,
+    Publisher,
+    Pagination,
+    Edition,
+    MaterialType,
+}
+
+impl MatchElement {
+    pub fn from_name(s: &str) -> Option<MatchElement> {
+        Some(match s {
+            "isbn" => MatchElement::Isbn,
+            "issn" => MatchElement::Issn,
+            "title" => MatchElement::Title,
+            "date" => MatchElement::Date,
+            "publisher" => MatchElement::Publisher,
+            "pagination" => MatchElement::Pagination,
+            "edition" => MatchElement::Edition,
+            "material_type" => MatchElement::MaterialType,
+            _ => return None,
+        })
+    }
+}
+
+/** The normalized values of a record that duplicates are detected by **/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MatchKey {
+    // ISBN-13 without hyphens
+    pub isbns: Vec<String>,
+    // with hyphen
+    pub issns: Vec<String>,
+    // NACO normalized title proper
+    pub title: String,
+    pub date: Option<u16>,
+    pub publisher: String,
+    // the largest number in 300 $a
+    pub pagination: Option<u32>,
+    // the first number in the edition statement, or the normalized statement
+    pub edition: String,
+    pub material_type: Option<MaterialType>,
+}
+
+fn year(s: &str) -> Option<u16> {
+    let b = s.as_bytes();
+    (0..b.len().saturating_sub(3))
+        .find(|&i| b[i..i + 4].iter().all(u8::is_ascii_digit))
+        .and_then(|i| s[i..i + 4].parse().ok())
+}
+
+impl MatchKey {
+    pub fn from_record<R: Record + ?Sized>(record: &R) -> MatchKey {
+        let b = match BibliographicRecord::new(record) {
+            Some(b) => b,
+            None => return MatchKey::default(),
+        };
+        let mut isbns: Vec<String> = Vec::new();
+        for field in record.field_iter(Some(20)) {
+            for value in field.subfield_values(b'a') {
+                if let Ok(isbn) = Isbn::parse(split_qualifier(&value).0) {
+                    if !isbns.contains(&isbn.to_isbn13()) {
+                        isbns.push(isbn.to_isbn13());
+                    }
+                }
+            }
+        }
+        let issns = b
+            .issns()
+            .iter()
+            .filter_map(|s| Issn::parse(s).ok())
+            .map(|i| i.to_string())
+            .collect();
+        let imprint = b.imprint().unwrap_or_default();
+        let date = Field008Kind::for_leader(record.leader())
+            .zip(record.field_iter(Some(8)).next())
+            .and_then(|(kind, f)| Field008::parse(kind, f.data).ok())
+            .and_then(|f| f.date1().and_then(year))
+            .or_else(|| imprint.date.as_deref().and_then(year));
+        let pagination = record
+            .field_iter(Some(300))
+            .next()
+            .and_then(|f| f.subfield_values(b'a').next().map(|s| s.to_string()))
+            .and_then(|s| {
+                s.split(|c: char| !c.is_ascii_digit())
+                    .filter_map(|n| n.parse().ok())
+                    .max()
+            });
+        let edition = b.edition().unwrap_or_default();
+        let edition = match edition
+            .split(|c: char| !c.is_ascii_digit())
+            .find(|n| !n.is_empty())
+        {
+            Some(n) => n.to_string(),
+            None => normalize(&edition),
+        };
+        MatchKey {
+            isbns,
+            issns,
+            title: normalize(&b.title().unwrap_or_default()),
+            date,
+            publisher: imprint
+                .publishers
+                .first()
+                .map(|p| normalize(p))
+                .unwrap_or_default(),
+            pagination,
+            edition,
+            material_type: record.material_type(),
+        }
+    }
+
+    /** The values of the given elements joined by `|`, for exact matching **/
+    pub fn to_key(&self, elements: &[MatchElement]) -> String {
+        let parts: Vec<String> = elements
+            .iter()
+            .map(|e| match e {
+                MatchElement::Isbn => self.isbns.first().cloned().unwrap_or_default(),
+                MatchElement::Issn => self.issns.first().cloned().unwrap_or_default(),
+                MatchElement::Title => self.title.clone(),
+                MatchElement::Date => self.date.map(|d| d.to_string()).unwrap_or_default(),
+                MatchElement::Publisher => self.publisher.clone(),
+                MatchElement::Pagination => {
+                    self.pagination.map(|p| p.to_string()).unwrap_or_default()
+                }
+                MatchElement::Edition => self.edition.clone(),
+                MatchElement::MaterialType => self
+                    .material_type
+                    .map(|m| format!("{:?}", m))
+                    .unwrap_or_default(),
+            })
+            .collect();
+        parts.join("|")
+    }
+
+    fn blocking_keys(&self, title_len: usize) -> Vec<String> {
+        let mut keys: Vec<String> = Vec::new();
+        keys.extend(self.isbns.iter().map(|i| format!("isbn:{}", i)));
+        keys.extend(self.issns.iter().map(|i| format!("issn:{}", i)));
+        if !self.title.is_empty() {
+            let title: String = self.title.chars().take(title_len).collect();
+            keys.push(format!(
+                "title:{}|{}",
+                title,
+                self.date.map(|d| d.to_string()).unwrap_or_default()
+            ));
+        }
+        keys
+    }
+
+    /**
+     * The similarity of two keys between 0 and 1, the weighted mean of the
+     * similarities of the elements both records have. Different material
+     * types always score 0.
+     */
+    pub fn similarity(&self, other: &MatchKey, config: &MatchConfig) -> f64 {
+        if let (Some(a), Some(b)) = (self.material_type, other.material_type) {
+            if a != b && config.weight(MatchElement::MaterialType) > 0.0 {
+                return 0.0;
+            }
+        }
+        let overlap = |a: &Vec<String>, b: &Vec<String>| {
+            if a.is_empty() || b.is_empty() {
+                None
+            } else if a.iter().any(|x| b.contains(x)) {
+                Some(1.0)
+            } else {
+                Some(0.0)
+            }
+        };
+        let text = |a: &String, b: &String| {
+            if a.is_empty() || b.is_empty() {
+                None
+            } else {
+                Some(dice(a, b))
+            }
+        };
+        let (mut sum, mut total) = (0.0, 0.0);
+        for &(element, weight) in config.weights.iter() {
+            let similarity = match element {
+                MatchElement::Isbn => overlap(&self.isbns, &other.isbns),
+                MatchElement::Issn => overlap(&self.issns, &other.issns),
+                MatchElement::Title => text(&self.title, &other.title),
+                MatchElement::Publisher => text(&self.publisher, &other.publisher),
+                MatchElement::Edition => {
+                    text(&self.edition, &other.edition).map(|s| if s == 1.0 { 1.0 } else { 0.0 })
+                }
+                MatchElement::Date => match (self.date, other.date) {
+                    (Some(a), Some(b)) if a == b => Some(1.0),
+                    (Some(a), Some(b)) if a.abs_diff(b) == 1 => Some(0.5),
+                    (Some(_), Some(_)) => Some(0.0),
+                    _ => None,
+                },
+                MatchElement::Pagination => match (self.pagination, other.pagination) {
+                    (Some(a), Some(b)) if a == b => Some(1.0),
+                    (Some(a), Some(b)) if a.abs_diff(b) * 10 <= a.max(b) => Some(0.5),
+                    (Some(_), Some(_)) => Some(0.0),
+                    _ => None,
+                },
+                MatchElement::MaterialType => None,
+            };
+            if let Some(s) = similarity {
+                sum += weight * s;
+                total += weight;
+            }
+        }
+        if total == 0.0 {
+            0.0
+        } else {
+            sum / total
+        }
+    }
+}
+
+const MATERIAL_TYPES: [MaterialType; 7] = [
+    MaterialType::Books,
+    MaterialType::Serials,
+    MaterialType::Maps,
+    MaterialType::Music,
+    MaterialType::VisualMaterials,
+    MaterialType::ComputerFiles,
+    MaterialType::MixedMaterials,
+];
+
+fn write_str(writer: &mut dyn Write, s: &str) -> std::io::Result<()> {
+    writer.write_all(&(s.len() as u32).to_le_bytes())?;
+    writer.write_all(s.as_bytes())
+}
+
+fn read_u8(reader: &mut dyn Read) -> std::io::Result<u8> {
+    let mut b = [0; 1];
+    reader.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn read_u32(reader: &mut dyn Read) -> std::io::Result<u32> {
+    let mut b = [0; 4];
+    reader.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_str(reader: &mut dyn Read) -> std::io::Result<String> {
+    let mut s = vec![0; read_u32(reader)? as usize];
+    reader.read_exact(&mut s)?;
+    Ok(String::from_utf8_lossy(&s).to_string())
+}
+
+impl MatchKey {
+    fn write(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        for list in [&self.isbns, &self.issns] {
+            writer.write_all(&(list.len() as u32).to_le_bytes())?;
+            for s in list.iter() {
+                write_str(writer, s)?;
+            }
+        }
+        for s in [&self.title, &self.publisher, &self.edition] {
+            write_str(writer, s)?;
+        }
+        // options are written as a flag and the value
+        writer.write_all(&[self.date.is_some() as u8])?;
+        writer.write_all(&self.date.unwrap_or(0).to_le_bytes())?;
+        writer.write_all(&[self.pagination.is_some() as u8])?;
+        writer.write_all(&self.pagination.unwrap_or(0).to_le_bytes())?;
+        // 0 or the index in MATERIAL_TYPES plus 1
+        writer.write_all(&[self.material_type.map_or(0, |m| m as u8 + 1)])
+    }
+
+    fn read(reader: &mut dyn Read) -> std::io::Result<MatchKey> {
+        let mut lists = [Vec::new(), Vec::new()];
+        for list in lists.iter_mut() {
+            for _ in 0..read_u32(reader)? {
+                list.push(read_str(reader)?);
+            }
+        }
+        let [isbns, issns] = lists;
+        let title = read_str(reader)?;
+        let publisher = read_str(reader)?;
+        let edition = read_str(reader)?;
+        let has_date = read_u8(reader)? == 1;
+        let mut date = [0; 2];
+        reader.read_exact(&mut date)?;
+        let has_pagination = read_u8(reader)? == 1;
+        let pagination = read_u32(reader)?;
+        let material_type = read_u8(reader)?;
+        Ok(MatchKey {
+            isbns,
+            issns,
+            title,
+            date: has_date.then(|| u16::from_le_bytes(date)),
+            publisher,
+            pagination: has_pagination.then_some(pagination),
+            edition,
+            material_type: (material_type as usize)
+                .checked_sub(1)
+                .and_then(|i| MATERIAL_TYPES.get(i).copied()),
+        })
+    }
+}
+
+/** Dice coefficient of the character bigrams of two strings **/
+fn dice(a: &str, b: &str) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+    let bigrams = |s: &str| {
+        let chars: Vec<char> = s.chars().collect();
+        chars
+            .windows(2)
+            .map(|w| (w[0], w[1]))
+            .collect::<Vec<(char, char)>>()
+    };
+    let a = bigrams(a);
+    let mut b = bigrams(b);
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let total = a.len() + b.len();
+    let mut common = 0;
+    for x in a.iter() {
+        if let Some(i) = b.iter().position(|y| y == x) {
+            b.swap_remove(i);
+            common += 1;
+        }
+    }
+    2.0 * common as f64 / total as f64
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchConfig {
+    // elements with weight 0 or missing are ignored, a weight for
+    // MaterialType makes different material types never match
+    pub weights: Vec<(MatchElement, f64)>,
+    // pairs scoring at least this are duplicates
+    pub threshold: f64,
+    // characters of the normalized title in the title blocking key
+    pub title_len: usize,
+    // larger blocks are split by full title and date, larger parts are skipped and reported
+    pub max_block_size: usize,
+    // blocking key entries held in memory before sorted runs are spilled to
+    // disk, and match keys held in memory before they are moved to a file
+    pub max_in_memory: usize,
+    pub temp_dir: PathBuf,
+}
+
+impl Default for MatchConfig {
+    fn default() -> MatchConfig {
+        MatchConfig {
+            weights: vec![
+                (MatchElement::Isbn, 3.0),
+                (MatchElement::Issn, 3.0),
+                (MatchElement::Title, 3.0),
+                (MatchElement::Date, 1.0),
+                (MatchElement::Publisher, 1.0),
+                (MatchElement::Pagination, 1.0),
+                (MatchElement::Edition, 1.0),
+                (MatchElement::MaterialType, 1.0),
+            ],
+            threshold: 0.85,
+            title_len: 25,
+            max_block_size: 500,
+            max_in_memory: 1_000_000,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+impl MatchConfig {
+    pub fn weight(&self, element: MatchElement) -> f64 {
+        self.weights
+            .iter()
+            .find(|(e, _)| *e == element)
+            .map_or(0.0, |(_, w)| *w)
+    }
+}
+
+/** Candidate duplicates, by position in the file **/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    pub positions: Vec<usize>,
+    // the lowest score of the pairs that joined the cluster
+    pub score: f64,
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/** A record under one of its blocking keys **/
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct BlockEntry {
+    block: String,
+    position: u32,
+}
+
+impl RunEntry for BlockEntry {
+    fn write(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        writer.write_all(&(self.block.len() as u32).to_le_bytes())?;
+        writer.write_all(self.block.as_bytes())?;
+        writer.write_all(&self.position.to_le_bytes())
+    }
+
+    fn read(reader: &mut dyn Read) -> std::io::Result<Option<BlockEntry>> {
+        let mut len = [0; 4];
+        match reader.read_exact(&mut len) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+        let mut block = vec![0; u32::from_le_bytes(len) as usize];
+        reader.read_exact(&mut block)?;
+        let mut position = [0; 4];
+        reader.read_exact(&mut position)?;
+        Ok(Some(BlockEntry {
+            block: String::from_utf8_lossy(&block).to_string(),
+            position: u32::from_le_bytes(position),
+        }))
+    }
+}
+
+/** The clusters found so far, joined pair by pair **/
+struct Clustering {
+    parents: Vec<usize>,
+    // the lowest score of the pairs that joined the cluster, by root
+    scores: Vec<f64>,
+}
+
+impl Clustering {
+    /** `keys` are the match keys of the positions in `block` **/
+    fn join_block(&mut self, block: &[usize], keys: &[MatchKey], config: &MatchConfig) {
+        for (i, &a) in block.iter().enumerate() {
+            for (j, &b) in block.iter().enumerate().skip(i + 1) {
+                let (ra, rb) = (find(&mut self.parents, a), find(&mut self.parents, b));
+                if ra == rb {
+                    continue;
+                }
+                let score = keys[i].similarity(&keys[j], config);
+                if score >= config.threshold {
+                    let (root, other) = (ra.min(rb), ra.max(rb));
+                    self.parents[other] = root;
+                    self.scores[root] = self.scores[root].min(self.scores[other]).min(score);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * The match keys and control numbers by position, encoded in memory and moved
+ * to a temporary file once there are `max_in_memory` of them
+ */
+struct KeyStore {
+    max_in_memory: usize,
+    temp_dir: PathBuf,
+    // the start of every entry in `memory` or the file
+    offsets: Vec<u64>,
+    end: u64,
+    memory: Vec<u8>,
+    // the file with a writer appending to it and a reader seeking in it
+    file: Option<(TempFile, BufWriter<File>, File)>,
+}
+
+impl KeyStore {
+    fn push(&mut self, key: &MatchKey, control_number: Option<&str>) -> std::io::Result<()> {
+        let mut entry = Vec::new();
+        key.write(&mut entry)?;
+        entry.push(control_number.is_some() as u8);
+        write_str(&mut entry, control_number.unwrap_or(""))?;
+        self.offsets.push(self.end);
+        self.end += entry.len() as u64;
+        match self.file.as_mut() {
+            Some((_, writer, _)) => writer.write_all(&entry)?,
+            None => self.memory.extend_from_slice(&entry),
+        }
+        if self.file.is_none() && self.offsets.len() >= self.max_in_memory {
+            let (temp, file) = TempFile::create(&self.temp_dir, "dedup-keys", 0)?;
+            let mut writer = BufWriter::new(file);
+            writer.write_all(&self.memory)?;
+            let reader = File::open(&temp.path)?;
+            self.memory = Vec::new();
+            self.file = Some((temp, writer, reader));
+        }
+        Ok(())
+    }
+
+    fn get(&mut self, position: usize) -> std::io::Result<(MatchKey, Option<String>)> {
+        let start = self.offsets[position];
+        let end = self.offsets.get(position + 1).copied().unwrap_or(self.end);
+        let mut entry = vec![0; (end - start) as usize];
+        match self.file.as_mut() {
+            Some((_, writer, reader)) => {
+                writer.flush()?;
+                reader.seek(SeekFrom::Start(start))?;
+                reader.read_exact(&mut entry)?;
+            }
+            None => entry.copy_from_slice(&self.memory[start as usize..end as usize]),
+        }
+        let mut reader: &[u8] = &entry;
+        let key = MatchKey::read(&mut reader)?;
+        let has_control_number = read_u8(&mut reader)? == 1;
+        let control_number = read_str(&mut reader)?;
+        Ok((key, has_control_number.then_some(control_number)))
+    }
+
+    fn keys(&mut self, positions: &[usize]) -> std::io::Result<Vec<MatchKey>> {
+        positions
+            .iter()
+            .map(|&position| self.get(position).map(|(key, _)| key))
+            .collect()
+    }
+}
+
+/** Collects match keys of the records of a file and clusters them **/
+pub struct Deduplicator {
+    config: MatchConfig,
+    keys: KeyStore,
+    blocks: SortedRuns<BlockEntry>,
+    oversized: Vec<(String, usize)>,
+}
+
+impl Deduplicator {
+    pub fn new(config: MatchConfig) -> Deduplicator {
+        let blocks = SortedRuns::new("dedup", config.max_in_memory, &config.temp_dir);
+        let keys = KeyStore {
+            max_in_memory: config.max_in_memory,
+            temp_dir: config.temp_dir.clone(),
+            offsets: Vec::new(),
+            end: 0,
+            memory: Vec::new(),
+            file: None,
+        };
+        Deduplicator {
+            config,
+            keys,
+            blocks,
+            oversized: Vec::new(),
+        }
+    }
+
+    pub fn build<R: Read + Seek>(
+        reader: &mut MarcReader<R>,
+        buffer: &mut [u8],
+        config: MatchConfig,
+    ) -> std::io::Result<Deduplicator> {
+        let mut dedup = Deduplicator::new(config);
+        while let Some(batch) = reader.read_batch(buffer)? {
+            for record in batch.records.iter() {
+                dedup.add_record(record)?;
+            }
+        }
+        Ok(dedup)
+    }
+
+    /**
+     * Add the record at the next position, fails if the keys cannot be spilled
+     * or there are more records than positions fit into 32 bits
+     */
+    pub fn add_record<R: Record + ?Sized>(&mut self, record: &R) -> std::io::Result<()> {
+        let position = u32::try_from(self.len())
+            .map_err(|_| invalid(format!("more than {} records", u32::MAX)))?;
+        let key = MatchKey::from_record(record);
+        for block in key.blocking_keys(self.config.title_len) {
+            self.blocks.push(BlockEntry { block, position })?;
+        }
+        let control_number = record
+            .field_iter(Some(1))
+            .next()
+            .map(|f| f.utf8_data_lossy().to_string());
+        self.keys.push(&key, control_number.as_deref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.offsets.is_empty()
+    }
+
+    /** The match key of a record, read back from the temporary file if it was spilled **/
+    pub fn key(&mut self, position: usize) -> std::io::Result<MatchKey> {
+        Ok(self.keys.get(position)?.0)
+    }
+
+    pub fn control_number(&mut self, position: usize) -> std::io::Result<Option<String>> {
+        Ok(self.keys.get(position)?.1)
+    }
+
+    /**
+     * Blocks that were too large to compare even after splitting them by full
+     * title and date, with the number of records in the skipped part. Filled
+     * by `clusters`.
+     */
+    pub fn oversized_blocks(&self) -> &[(String, usize)] {
+        &self.oversized
+    }
+
+    /** Clusters of two or more records, ordered by their first position **/
+    pub fn clusters(&mut self) -> std::io::Result<Vec<Cluster>> {
+        let n = self.len();
+        let mut clustering = Clustering {
+            parents: (0..n).collect(),
+            scores: vec![1.0; n],
+        };
+        let (store, config, oversized) = (&mut self.keys, &self.config, &mut self.oversized);
+        oversized.clear();
+        let mut join = |block_key: &str, block: &[usize]| -> std::io::Result<()> {
+            if block.len() < 2 {
+                return Ok(());
+            }
+            let keys = store.keys(block)?;
+            if block.len() <= config.max_block_size {
+                clustering.join_block(block, &keys, config);
+                return Ok(());
+            }
+            let mut parts: BTreeMap<String, (Vec<usize>, Vec<MatchKey>)> = BTreeMap::new();
+            for (&position, key) in block.iter().zip(keys) {
+                let part = parts
+                    .entry(key.to_key(&[MatchElement::Title, MatchElement::Date]))
+                    .or_default();
+                part.0.push(position);
+                part.1.push(key);
+            }
+            for (positions, keys) in parts.values() {
+                if positions.len() <= config.max_block_size {
+                    clustering.join_block(positions, keys, config);
+                } else {
+                    oversized.push((block_key.to_string(), positions.len()));
+                }
+            }
+            Ok(())
+        };
+        let mut entries = self.blocks.sorted()?;
+        let mut block_key = String::new();
+        let mut block: Vec<usize> = Vec::new();
+        while let Some(entry) = entries.next_entry()? {
+            if entry.block != block_key {
+                join(&block_key, &block)?;
+                block.clear();
+                block_key = entry.block;
+            }
+            block.push(entry.position as usize);
+        }
+        join(&block_key, &block)?;
+        let Clustering {
+            mut parents,
+            scores,
+        } = clustering;
+        let mut clusters: HashMap<usize, Vec<usize>> = HashMap::new();
+        for i in 0..n {
+            let root = find(&mut parents, i);
+            clusters.entry(root).or_default().push(i);
+        }
+        let mut result: Vec<Cluster> = clusters
+            .into_iter()
+            .filter(|(_, positions)| positions.len() > 1)
+            .map(|(root, positions)| Cluster {
+                positions,
+                score: scores[root],
+            })
+            .collect();
+        result.sort_by_key(|c| c.positions[0]);
+        Ok(result)
+    }
+
+    /** One CSV line per clustered record: `cluster,position,control_number,score` **/
+    pub fn write_report(
+        &mut self,
+        clusters: &[Cluster],
+        writer: &mut dyn Write,
+    ) -> std::io::Result<()> {
+        writeln!(writer, "cluster,position,control_number,score")?;
+        for (n, cluster) in clusters.iter().enumerate() {
+            for &position in cluster.positions.iter() {
+                writeln!(
+                    writer,
+                    "{},{},{},{:.3}",
+                    n,
+                    position,
+                    escape_csv(&self.control_number(position)?.unwrap_or_default()),
+                    cluster.score
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dedup::*;
+    use crate::ownedrecord::OwnedRecord;
+    use crate::testutil::{record, BOOK};
+
+    fn records() -> Vec<OwnedRecord> {
+        vec![
+            record(
+                BOOK,
+                &[
+                    (1, "r0"),
+                    (8, "190101s2019    gw            000 0 eng d"),
+                    (20, "  \x1fa316148410X"),
+                    (245, "14\x1faThe handbook of things /"),
+                    (264, " 1\x1faBerlin :\x1fbSpringer,\x1fc2019."),
+                    (300, "  \x1faxii, 350 p. ;"),
+                ],
+            ),
+            record(
+                BOOK,
+                &[
+                    (1, "r1"),
+                    (20, "  \x1fa978-3-16-148410-0 (hbk.)"),
+                    (245, "10\x1faHandbook of things :\x1fba guide"),
+                    (260, "  \x1faBerlin :\x1fbSpringer-Verlag,\x1fc[2019]"),
+                    (300, "  \x1fa350 p."),
+                ],
+            ),
+            record(
+                BOOK,
+                &[
+                    (1, "r2"),
+                    (245, "10\x1faHandbook of things."),
+                    (264, " 1\x1faBerlin :\x1fbSpringer,\x1fc2019."),
+                    (300, "  \x1fa350 pages"),
+                ],
+            ),
+            record(
+                BOOK,
+                &[
+                    (1, "r3"),
+                    (245, "10\x1faHandbook of things."),
+                    (264, " 1\x1faLondon :\x1fbRoutledge,\x1fc2019."),
+                    (300, "  \x1fa120 pages"),
+                ],
+            ),
+            record(
+                BOOK,
+                &[
+                    (1, "r4"),
+                    (245, "10\x1faAnother book."),
+                    (264, " 1\x1faBerlin :\x1fbSpringer,\x1fc2019."),
+                ],
+            ),
+        ]
+    }
+
+    #[test]
+    fn match_keys() {
+        let rs = records();
+        let key = MatchKey::from_record(&rs[0]);
+        assert_eq!(key.isbns, ["9783161484100"]);
+        assert_eq!(key.title, "HANDBOOK OF THINGS");
+        assert_eq!(key.date, Some(2019));
+        assert_eq!(key.publisher, "SPRINGER");
+        assert_eq!(key.pagination, Some(350));
+        assert_eq!(key.material_type, Some(MaterialType::Books));
+        assert_eq!(
+            key.to_key(&[MatchElement::Isbn, MatchElement::Title, MatchElement::Date]),
+            "9783161484100|HANDBOOK OF THINGS|2019"
+        );
+        let other = MatchKey::from_record(&rs[1]);
+        assert_eq!(other.isbns, key.isbns);
+        assert_eq!(other.date, Some(2019));
+        let score = key.similarity(&other, &MatchConfig::default());
+        assert!(score > 0.9 && score < 1.0);
+    }
+
+    #[test]
+    fn clusters() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("marclib-dedup-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        for max_in_memory in [1000, 2] {
+            let mut dedup = Deduplicator::new(MatchConfig {
+                max_in_memory,
+                temp_dir: dir.clone(),
+                ..MatchConfig::default()
+            });
+            for r in records() {
+                dedup.add_record(&r).map_err(|e| e.to_string())?;
+            }
+            assert_eq!(
+                dedup.key(3).map_err(|e| e.to_string())?,
+                MatchKey::from_record(&records()[3])
+            );
+            assert_eq!(
+                dedup.control_number(4).map_err(|e| e.to_string())?,
+                Some("r4".to_string())
+            );
+            let clusters = dedup.clusters().map_err(|e| e.to_string())?;
+            assert_eq!(clusters.len(), 1);
+            assert_eq!(clusters[0].positions, [0, 1, 2]);
+            assert!(clusters[0].score > 0.9);
+            assert!(dedup.oversized_blocks().is_empty());
+            let mut out = Vec::new();
+            dedup
+                .write_report(&clusters, &mut out)
+                .map_err(|e| e.to_string())?;
+            let report = String::from_utf8(out).map_err(|e| e.to_string())?;
+            assert!(report.starts_with("cluster,position,control_number,score\n0,0,r0,"));
+            assert_eq!(report.lines().count(), 4);
+        }
+        let leftover = std::fs::read_dir(&dir).map_err(|e| e.to_string())?.count();
+        std::fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+        assert_eq!(leftover, 0, "run files are removed");
+        Ok(())
+    }
+
+    #[test]
+    fn oversized_blocks() -> Result<(), String> {
+        let mut rs = records();
+        rs.push(record(
+            BOOK,
+            &[
+                (1, "r5"),
+                (245, "10\x1faHandbook of other things."),
+                (264, " 1\x1faBerlin :\x1fbSpringer,\x1fc2019."),
+            ],
+        ));
+        // the title block of r0 to r3 and r5 is split by the full title
+        let config = MatchConfig {
+            title_len: 8,
+            max_block_size: 4,
+            ..MatchConfig::default()
+        };
+        let mut dedup = Deduplicator::new(config.clone());
+        for r in rs.iter() {
+            dedup.add_record(r).map_err(|e| e.to_string())?;
+        }
+        let clusters = dedup.clusters().map_err(|e| e.to_string())?;
+        assert_eq!(clusters[0].positions, [0, 1, 2]);
+        assert!(dedup.oversized_blocks().is_empty());
+        // r0 to r3 share the full title too, only the ISBN block is left
+        let mut dedup = Deduplicator::new(MatchConfig {
+            max_block_size: 2,
+            ..config
+        });
+        for r in rs.iter() {
+            dedup.add_record(r).map_err(|e| e.to_string())?;
+        }
+        let clusters = dedup.clusters().map_err(|e| e.to_string())?;
+        assert_eq!(clusters[0].positions, [0, 1]);
+        assert_eq!(
+            dedup.oversized_blocks(),
+            [("title:HANDBOOK|2019".to_string(), 4)]
+        );
+        Ok(())
+    }
+}
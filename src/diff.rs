@@ -0,0 +1,708 @@
+/*!
+ * Differences between two records, and patches that re-apply them.
+ *
+ * Fields are compared per tag. Identical fields are matched first, the
+ * remaining fields of a tag are then paired in order as modified, and what is
+ * left over was added or removed. Fields are addressed by tag and occurrence
+ * of that tag in the old record.
+ *
+ * The patch format has one change per line, its columns separated by tabs:
+ *
+ * ```text
+ * L <old leader> <new leader>
+ * - <tag> <occurrence> <old data>
+ * + <tag> <data>
+ * ~ <tag> <occurrence> <old data> <new data>
+ * ```
+ *
+ * Backslash, tab, line breaks and the delimiters 0x1e and 0x1f are escaped as
+ * `\\`, `\t`, `\n`, `\r`, `\x1e` and `\x1f`, bytes that are not UTF-8, e.g.
+ * of MARC-8 records, as `\xNN`. A patch thus reproduces the data byte for
+ * byte. The JSON output escapes backslashes and such bytes the same way.
+ */
+use crate::ownedrecord::OwnedRecord;
+use crate::record::*;
+use crate::util::escape_json;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Write;
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubfieldChange {
+    Added {
+        code: u8,
+        value: Vec<u8>,
+    },
+    Removed {
+        code: u8,
+        value: Vec<u8>,
+    },
+    Modified {
+        code: u8,
+        old: Vec<u8>,
+        new: Vec<u8>,
+    },
+}
+
+/** Old and new indicators **/
+pub type IndicatorChange = ([u8; 2], [u8; 2]);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldChange {
+    Added {
+        field_type: usize,
+        data: Vec<u8>,
+    },
+    Removed {
+        field_type: usize,
+        occurrence: usize,
+        data: Vec<u8>,
+    },
+    Modified {
+        field_type: usize,
+        occurrence: usize,
+        old: Vec<u8>,
+        new: Vec<u8>,
+    },
+}
+
+impl FieldChange {
+    pub fn field_type(&self) -> usize {
+        match self {
+            FieldChange::Added { field_type, .. }
+            | FieldChange::Removed { field_type, .. }
+            | FieldChange::Modified { field_type, .. } => *field_type,
+        }
+    }
+
+    /** The indicator and subfield changes of a modified data field **/
+    pub fn subfield_changes(&self) -> (Option<IndicatorChange>, Vec<SubfieldChange>) {
+        match self {
+            FieldChange::Modified {
+                field_type,
+                old,
+                new,
+                ..
+            } if RecordField::is_data_field_type(*field_type) => {
+                let old_field = RecordField {
+                    field_type: *field_type,
+                    data: old,
+                };
+                let new_field = RecordField {
+                    field_type: *field_type,
+                    data: new,
+                };
+                let indicators = |f: &RecordField| {
+                    [
+                        f.indicator(0).unwrap_or(b' '),
+                        f.indicator(1).unwrap_or(b' '),
+                    ]
+                };
+                let (oi, ni) = (indicators(&old_field), indicators(&new_field));
+                (
+                    Some((oi, ni)).filter(|_| oi != ni),
+                    diff_subfields(&old_field, &new_field),
+                )
+            }
+            _ => (None, Vec::new()),
+        }
+    }
+}
+
+/**
+ * Subfields are paired by code and occurrence of the code, the n-th `$a` of
+ * the old field with the n-th `$a` of the new one
+ */
+fn diff_subfields(old: &RecordField, new: &RecordField) -> Vec<SubfieldChange> {
+    let mut changes = Vec::new();
+    let old: Vec<(u8, &[u8])> = old.subfields().map(|s| (s.code(), s.value())).collect();
+    let new: Vec<(u8, &[u8])> = new.subfields().map(|s| (s.code(), s.value())).collect();
+    let nth = |list: &[(u8, &[u8])], code: u8, n: usize| {
+        list.iter()
+            .filter(|(c, _)| *c == code)
+            .nth(n)
+            .map(|(_, v)| v.to_vec())
+    };
+    let mut seen: Vec<u8> = Vec::new();
+    for &(code, _) in old.iter().chain(new.iter()) {
+        if seen.contains(&code) {
+            continue;
+        }
+        seen.push(code);
+        for n in 0.. {
+            match (nth(&old, code, n), nth(&new, code, n)) {
+                (None, None) => break,
+                (Some(o), Some(v)) if o == v => {}
+                (Some(o), Some(v)) => changes.push(SubfieldChange::Modified {
+                    code,
+                    old: o,
+                    new: v,
+                }),
+                (Some(o), None) => changes.push(SubfieldChange::Removed { code, value: o }),
+                (None, Some(v)) => changes.push(SubfieldChange::Added { code, value: v }),
+            }
+        }
+    }
+    changes
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordDiff {
+    // old and new leader, if they differ outside length and base address
+    pub leader: Option<(Vec<u8>, Vec<u8>)>,
+    pub changes: Vec<FieldChange>,
+}
+
+/** The leader without the positions that follow from the record's content **/
+fn leader_content(leader: &[u8]) -> Vec<u8> {
+    let mut l = leader.to_vec();
+    for i in (0..5).chain(12..17) {
+        if let Some(b) = l.get_mut(i) {
+            *b = b'0';
+        }
+    }
+    l
+}
+
+pub fn diff<A: Record + ?Sized, B: Record + ?Sized>(old: &A, new: &B) -> RecordDiff {
+    let mut result = RecordDiff::default();
+    if leader_content(old.leader()) != leader_content(new.leader()) {
+        result.leader = Some((old.leader().to_vec(), new.leader().to_vec()));
+    }
+    let mut tags: Vec<usize> = old
+        .field_iter(None)
+        .chain(new.field_iter(None))
+        .map(|f| f.field_type)
+        .collect();
+    tags.sort_unstable();
+    tags.dedup();
+    for tag in tags {
+        let old_fields: Vec<&[u8]> = old.field_iter(Some(tag)).map(|f| f.data).collect();
+        let new_fields: Vec<&[u8]> = new.field_iter(Some(tag)).map(|f| f.data).collect();
+        let mut new_used = vec![false; new_fields.len()];
+        let mut old_unmatched: Vec<usize> = Vec::new();
+        for (i, o) in old_fields.iter().enumerate() {
+            match (0..new_fields.len()).find(|&j| !new_used[j] && new_fields[j] == *o) {
+                Some(j) => new_used[j] = true,
+                None => old_unmatched.push(i),
+            }
+        }
+        let mut new_unmatched = (0..new_fields.len()).filter(|&j| !new_used[j]);
+        for i in old_unmatched {
+            match new_unmatched.next() {
+                Some(j) => result.changes.push(FieldChange::Modified {
+                    field_type: tag,
+                    occurrence: i,
+                    old: old_fields[i].to_vec(),
+                    new: new_fields[j].to_vec(),
+                }),
+                None => result.changes.push(FieldChange::Removed {
+                    field_type: tag,
+                    occurrence: i,
+                    data: old_fields[i].to_vec(),
+                }),
+            }
+        }
+        for j in new_unmatched {
+            result.changes.push(FieldChange::Added {
+                field_type: tag,
+                data: new_fields[j].to_vec(),
+            });
+        }
+    }
+    result
+}
+
+/** Field data for display, with `$` for the subfield delimiter **/
+fn display(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_end_matches('\x1e')
+        .replace('\x1f', "$")
+}
+
+/**
+ * Escape backslashes and the bytes that are not UTF-8, and with `controls`
+ * also tabs, line breaks and the delimiters
+ */
+fn escape_with(data: &[u8], controls: bool) -> String {
+    let mut out = String::with_capacity(data.len());
+    for chunk in data.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\t' if controls => out.push_str("\\t"),
+                '\n' if controls => out.push_str("\\n"),
+                '\r' if controls => out.push_str("\\r"),
+                '\x1e' | '\x1f' if controls => out.push_str(&format!("\\x{:02x}", c as u32)),
+                _ => out.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            out.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    out
+}
+
+fn escape(data: &[u8]) -> String {
+    escape_with(data, true)
+}
+
+fn unescape(s: &str) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    let bad_escape = || invalid(format!("bad escape in {}", s));
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push(b'\\'),
+            Some('t') => out.push(b'\t'),
+            Some('n') => out.push(b'\n'),
+            Some('r') => out.push(b'\r'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(b) if hex.len() == 2 => out.push(b),
+                    _ => return Err(bad_escape()),
+                }
+            }
+            _ => return Err(bad_escape()),
+        }
+    }
+    Ok(out)
+}
+
+impl RecordDiff {
+    pub fn is_empty(&self) -> bool {
+        self.leader.is_none() && self.changes.is_empty()
+    }
+
+    pub fn write_json(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        let s = |data: &[u8]| escape_json(&escape_with(data, false));
+        write!(writer, "{{")?;
+        if let Some((old, new)) = &self.leader {
+            write!(
+                writer,
+                "\"leader\":{{\"old\":\"{}\",\"new\":\"{}\"}},",
+                s(old),
+                s(new)
+            )?;
+        }
+        write!(writer, "\"changes\":[")?;
+        for (n, change) in self.changes.iter().enumerate() {
+            if n > 0 {
+                write!(writer, ",")?;
+            }
+            match change {
+                FieldChange::Added { field_type, data } => write!(
+                    writer,
+                    "{{\"op\":\"add\",\"tag\":\"{:03}\",\"data\":\"{}\"}}",
+                    field_type,
+                    s(data)
+                )?,
+                FieldChange::Removed {
+                    field_type,
+                    occurrence,
+                    data,
+                } => write!(
+                    writer,
+                    "{{\"op\":\"remove\",\"tag\":\"{:03}\",\"occurrence\":{},\"data\":\"{}\"}}",
+                    field_type,
+                    occurrence,
+                    s(data)
+                )?,
+                FieldChange::Modified {
+                    field_type,
+                    occurrence,
+                    old,
+                    new,
+                } => {
+                    write!(
+                        writer,
+                        "{{\"op\":\"modify\",\"tag\":\"{:03}\",\"occurrence\":{},\"old\":\"{}\",\"new\":\"{}\"",
+                        field_type,
+                        occurrence,
+                        s(old),
+                        s(new)
+                    )?;
+                    let (indicators, subfields) = change.subfield_changes();
+                    if let Some((o, n)) = indicators {
+                        write!(
+                            writer,
+                            ",\"indicators\":{{\"old\":\"{}\",\"new\":\"{}\"}}",
+                            s(&o),
+                            s(&n)
+                        )?;
+                    }
+                    write!(writer, ",\"subfields\":[")?;
+                    for (k, sc) in subfields.iter().enumerate() {
+                        if k > 0 {
+                            write!(writer, ",")?;
+                        }
+                        match sc {
+                            SubfieldChange::Added { code, value } => write!(
+                                writer,
+                                "{{\"op\":\"add\",\"code\":\"{}\",\"value\":\"{}\"}}",
+                                *code as char,
+                                s(value)
+                            )?,
+                            SubfieldChange::Removed { code, value } => write!(
+                                writer,
+                                "{{\"op\":\"remove\",\"code\":\"{}\",\"value\":\"{}\"}}",
+                                *code as char,
+                                s(value)
+                            )?,
+                            SubfieldChange::Modified { code, old, new } => write!(
+                                writer,
+                                "{{\"op\":\"modify\",\"code\":\"{}\",\"old\":\"{}\",\"new\":\"{}\"}}",
+                                *code as char,
+                                s(old),
+                                s(new)
+                            )?,
+                        }
+                    }
+                    write!(writer, "]}}")?;
+                }
+            }
+        }
+        write!(writer, "]}}")
+    }
+
+    pub fn write_patch(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        if let Some((old, new)) = &self.leader {
+            writeln!(writer, "L\t{}\t{}", escape(old), escape(new))?;
+        }
+        for change in self.changes.iter() {
+            match change {
+                FieldChange::Added { field_type, data } => {
+                    writeln!(writer, "+\t{:03}\t{}", field_type, escape(data))?
+                }
+                FieldChange::Removed {
+                    field_type,
+                    occurrence,
+                    data,
+                } => writeln!(
+                    writer,
+                    "-\t{:03}\t{}\t{}",
+                    field_type,
+                    occurrence,
+                    escape(data)
+                )?,
+                FieldChange::Modified {
+                    field_type,
+                    occurrence,
+                    old,
+                    new,
+                } => writeln!(
+                    writer,
+                    "~\t{:03}\t{}\t{}\t{}",
+                    field_type,
+                    occurrence,
+                    escape(old),
+                    escape(new)
+                )?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn parse_patch(patch: &str) -> std::io::Result<RecordDiff> {
+        let mut result = RecordDiff::default();
+        for (n, line) in patch.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let cols: Vec<&str> = line.split('\t').collect();
+            let bad_line = || invalid(format!("bad patch line {}", n + 1));
+            let number = |i: usize| -> std::io::Result<usize> {
+                cols.get(i)
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(bad_line)
+            };
+            let data = |i: usize| unescape(cols.get(i).ok_or_else(bad_line)?);
+            match (cols[0], cols.len()) {
+                ("L", 3) => result.leader = Some((data(1)?, data(2)?)),
+                ("+", 3) => result.changes.push(FieldChange::Added {
+                    field_type: number(1)?,
+                    data: data(2)?,
+                }),
+                ("-", 4) => result.changes.push(FieldChange::Removed {
+                    field_type: number(1)?,
+                    occurrence: number(2)?,
+                    data: data(3)?,
+                }),
+                ("~", 5) => result.changes.push(FieldChange::Modified {
+                    field_type: number(1)?,
+                    occurrence: number(2)?,
+                    old: data(3)?,
+                    new: data(4)?,
+                }),
+                _ => return Err(bad_line()),
+            }
+        }
+        Ok(result)
+    }
+
+    /**
+     * Apply the changes to the old record. Fails without changing the record
+     * if a removed or modified field is not found as recorded in the diff.
+     */
+    pub fn apply(&self, record: &mut OwnedRecord) -> std::io::Result<()> {
+        let position = |field_type: usize, occurrence: usize, data: &[u8]| {
+            let index = record
+                .field_types
+                .iter()
+                .enumerate()
+                .filter(|(_, &t)| t == field_type)
+                .nth(occurrence)
+                .map(|(i, _)| i);
+            match index {
+                Some(i) if record.field_data[i] == data => Ok(i),
+                _ => Err(invalid(format!(
+                    "{:03} occurrence {} does not match the patch",
+                    field_type, occurrence
+                ))),
+            }
+        };
+        let mut modified: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut removed: Vec<usize> = Vec::new();
+        for change in self.changes.iter() {
+            match change {
+                FieldChange::Modified {
+                    field_type,
+                    occurrence,
+                    old,
+                    new,
+                } => modified.push((position(*field_type, *occurrence, old)?, new.clone())),
+                FieldChange::Removed {
+                    field_type,
+                    occurrence,
+                    data,
+                } => removed.push(position(*field_type, *occurrence, data)?),
+                FieldChange::Added { .. } => {}
+            }
+        }
+        for (i, data) in modified {
+            record.field_data[i] = data;
+        }
+        removed.sort_unstable();
+        for i in removed.into_iter().rev() {
+            record.field_types.remove(i);
+            record.field_data.remove(i);
+        }
+        if let Some((_, new)) = &self.leader {
+            if new.len() == record.header.len() {
+                record.header.copy_from_slice(new);
+            }
+        }
+        for change in self.changes.iter() {
+            if let FieldChange::Added { field_type, data } = change {
+                record.insert_field(OwnedRecordField {
+                    field_type: *field_type,
+                    data: data.clone(),
+                })?;
+            }
+        }
+        record.update_len()
+    }
+}
+
+/** One line per change, `+`, `-` or `~` followed by the field, with `$` for delimiters **/
+impl std::fmt::Display for RecordDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some((old, new)) = &self.leader {
+            writeln!(f, "~ LDR {} -> {}", display(old), display(new))?;
+        }
+        for change in self.changes.iter() {
+            match change {
+                FieldChange::Added { field_type, data } => {
+                    writeln!(f, "+ {:03} {}", field_type, display(data))?
+                }
+                FieldChange::Removed {
+                    field_type, data, ..
+                } => writeln!(f, "- {:03} {}", field_type, display(data))?,
+                FieldChange::Modified {
+                    field_type,
+                    old,
+                    new,
+                    ..
+                } => {
+                    let (indicators, subfields) = change.subfield_changes();
+                    if !RecordField::is_data_field_type(*field_type) {
+                        writeln!(
+                            f,
+                            "~ {:03} {} -> {}",
+                            field_type,
+                            display(old),
+                            display(new)
+                        )?;
+                        continue;
+                    }
+                    writeln!(f, "~ {:03}", field_type)?;
+                    if let Some((o, n)) = indicators {
+                        writeln!(f, "    indicators {} -> {}", display(&o), display(&n))?;
+                    }
+                    for sc in subfields {
+                        match sc {
+                            SubfieldChange::Added { code, value } => {
+                                writeln!(f, "    + ${}{}", code as char, display(&value))?
+                            }
+                            SubfieldChange::Removed { code, value } => {
+                                writeln!(f, "    - ${}{}", code as char, display(&value))?
+                            }
+                            SubfieldChange::Modified { code, old, new } => writeln!(
+                                f,
+                                "    ~ ${}{} -> ${}{}",
+                                code as char,
+                                display(&old),
+                                code as char,
+                                display(&new)
+                            )?,
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::*;
+    use crate::marcrecord::MarcHeader;
+    use crate::marcrecord::MarcRecord;
+    use crate::testutil::STR;
+
+    fn edited() -> OwnedRecord {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let mut owned = record.to_owned();
+        owned.remove_fields(913).unwrap();
+        owned
+            .set_field(OwnedRecordField {
+                field_type: 150,
+                data: b"  \x1faA 302 D\x1fxBauelement".to_vec(),
+            })
+            .unwrap();
+        owned
+            .insert_field(OwnedRecordField {
+                field_type: 450,
+                data: b"  \x1faA-302-D".to_vec(),
+            })
+            .unwrap();
+        owned
+    }
+
+    #[test]
+    fn diff_records() {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let new = edited();
+        assert!(diff(&record, &record).is_empty());
+        let d = diff(&record, &new);
+        assert_eq!(d.leader, None);
+        let ops: Vec<(char, usize)> = d
+            .changes
+            .iter()
+            .map(|c| match c {
+                FieldChange::Added { field_type, .. } => ('+', *field_type),
+                FieldChange::Removed { field_type, .. } => ('-', *field_type),
+                FieldChange::Modified { field_type, .. } => ('~', *field_type),
+            })
+            .collect();
+        assert_eq!(ops, [('~', 150), ('+', 450), ('-', 913)]);
+        assert_eq!(
+            d.changes[0].subfield_changes(),
+            (
+                None,
+                vec![SubfieldChange::Added {
+                    code: b'x',
+                    value: b"Bauelement".to_vec()
+                }]
+            )
+        );
+        let text = d.to_string();
+        assert!(text.starts_with("~ 150\n    + $xBauelement\n+ 450   $aA-302-D\n- 913 "));
+    }
+
+    #[test]
+    fn json() -> Result<(), String> {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let d = diff(&record, &edited());
+        let mut out = Vec::new();
+        d.write_json(&mut out).map_err(|e| e.to_string())?;
+        let json = String::from_utf8(out).map_err(|e| e.to_string())?;
+        assert!(json.starts_with(
+            "{\"changes\":[{\"op\":\"modify\",\"tag\":\"150\",\"occurrence\":0,\"old\":\"  \\u001faA 302 D\""
+        ));
+        assert!(json
+            .contains("\"subfields\":[{\"op\":\"add\",\"code\":\"x\",\"value\":\"Bauelement\"}]}"));
+        assert!(json.ends_with("]}"));
+        Ok(())
+    }
+
+    #[test]
+    fn patch() -> Result<(), String> {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let new = edited();
+        let d = diff(&record, &new);
+        let mut out = Vec::new();
+        d.write_patch(&mut out).map_err(|e| e.to_string())?;
+        let patch = String::from_utf8(out).map_err(|e| e.to_string())?;
+        assert!(patch.starts_with("~\t150\t0\t  \\x1faA 302 D\t"));
+        let parsed = RecordDiff::parse_patch(&patch).map_err(|e| e.to_string())?;
+        assert_eq!(parsed, d);
+
+        let mut patched = record.to_owned();
+        parsed.apply(&mut patched).map_err(|e| e.to_string())?;
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        patched.to_marc21(&mut a).map_err(|e| e.to_string())?;
+        new.to_marc21(&mut b).map_err(|e| e.to_string())?;
+        assert_eq!(a, b);
+        // applying twice fails, the modified field is no longer as recorded
+        assert!(parsed.apply(&mut patched).is_err());
+        assert!(RecordDiff::parse_patch("?\t245").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn non_utf8_patch() -> Result<(), String> {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let mut new = record.to_owned();
+        // MARC-8 combining acute, a byte that is not UTF-8, and a backslash
+        new.set_field(OwnedRecordField {
+            field_type: 150,
+            data: b"  \x1faA 302 D\x1fxM\xe2uller\\x".to_vec(),
+        })
+        .map_err(|e| e.to_string())?;
+        let d = diff(&record, &new);
+        assert_eq!(
+            d.changes[0].subfield_changes().1,
+            vec![SubfieldChange::Added {
+                code: b'x',
+                value: b"M\xe2uller\\x".to_vec()
+            }]
+        );
+        let mut out = Vec::new();
+        d.write_patch(&mut out).map_err(|e| e.to_string())?;
+        let patch = String::from_utf8(out).map_err(|e| e.to_string())?;
+        assert!(patch.contains("\\x1fxM\\xe2uller\\\\x\n"));
+        let mut patched = record.to_owned();
+        RecordDiff::parse_patch(&patch)
+            .and_then(|p| p.apply(&mut patched))
+            .map_err(|e| e.to_string())?;
+        assert_eq!(patched.field_data, new.field_data);
+        let mut json = Vec::new();
+        d.write_json(&mut json).map_err(|e| e.to_string())?;
+        let json = String::from_utf8(json).map_err(|e| e.to_string())?;
+        assert!(json.contains("\"value\":\"M\\\\xe2uller\\\\\\\\x\""));
+        assert!(RecordDiff::parse_patch("+\t500\t\\xg0").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,883 @@
+/*!
+ * Decoders for the fixed-length control fields 006, 007 and 008.
+ *
+ * Every decoder keeps the raw positions and describes them with a table of
+ * named elements, so rarely used positions are available through `get` and
+ * `set` while the common ones also have typed accessors. Setters only accept
+ * values of the right length made of printable ASCII, so a decoded field can
+ * always be written back into an `OwnedRecord`.
+ */
+use crate::ownedrecord::OwnedRecord;
+use crate::record::*;
+use std::io::{Error, ErrorKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedFieldElement {
+    pub name: &'static str,
+    pub start: usize,
+    pub len: usize,
+}
+
+const fn el(name: &'static str, start: usize, len: usize) -> FixedFieldElement {
+    FixedFieldElement { name, start, len }
+}
+
+impl MaterialType {
+    /** 006/00 **/
+    pub fn from_form_of_material(b: u8) -> Option<MaterialType> {
+        use MaterialType::*;
+        Some(match b {
+            b'a' | b't' => Books,
+            b'm' => ComputerFiles,
+            b'e' | b'f' => Maps,
+            b'c' | b'd' | b'i' | b'j' => Music,
+            b's' => Serials,
+            b'g' | b'k' | b'o' | b'r' => VisualMaterials,
+            b'p' => MixedMaterials,
+            _ => return None,
+        })
+    }
+
+    /** The material specific elements of 008/18-34, with their 008 positions **/
+    pub fn fixed_field_elements(&self) -> &'static [FixedFieldElement] {
+        match self {
+            MaterialType::Books => &BOOKS,
+            MaterialType::ComputerFiles => &COMPUTER_FILES,
+            MaterialType::Maps => &MAPS,
+            MaterialType::Music => &MUSIC,
+            MaterialType::Serials => &CONTINUING_RESOURCES,
+            MaterialType::VisualMaterials => &VISUAL_MATERIALS,
+            MaterialType::MixedMaterials => &MIXED_MATERIALS,
+        }
+    }
+}
+
+const BIBLIOGRAPHIC_008: [FixedFieldElement; 8] = [
+    el("date_entered", 0, 6),
+    el("date_type", 6, 1),
+    el("date1", 7, 4),
+    el("date2", 11, 4),
+    el("place", 15, 3),
+    el("language", 35, 3),
+    el("modified_record", 38, 1),
+    el("cataloging_source", 39, 1),
+];
+
+const BOOKS: [FixedFieldElement; 10] = [
+    el("illustrations", 18, 4),
+    el("target_audience", 22, 1),
+    el("form_of_item", 23, 1),
+    el("nature_of_contents", 24, 4),
+    el("government_publication", 28, 1),
+    el("conference_publication", 29, 1),
+    el("festschrift", 30, 1),
+    el("index", 31, 1),
+    el("literary_form", 33, 1),
+    el("biography", 34, 1),
+];
+
+const COMPUTER_FILES: [FixedFieldElement; 4] = [
+    el("target_audience", 22, 1),
+    el("form_of_item", 23, 1),
+    el("type_of_computer_file", 26, 1),
+    el("government_publication", 28, 1),
+];
+
+const MAPS: [FixedFieldElement; 7] = [
+    el("relief", 18, 4),
+    el("projection", 22, 2),
+    el("type_of_cartographic_material", 25, 1),
+    el("government_publication", 28, 1),
+    el("form_of_item", 29, 1),
+    el("index", 31, 1),
+    el("special_format_characteristics", 33, 2),
+];
+
+const MUSIC: [FixedFieldElement; 8] = [
+    el("form_of_composition", 18, 2),
+    el("format_of_music", 20, 1),
+    el("music_parts", 21, 1),
+    el("target_audience", 22, 1),
+    el("form_of_item", 23, 1),
+    el("accompanying_matter", 24, 6),
+    el("literary_text_for_sound_recordings", 30, 2),
+    el("transposition_and_arrangement", 33, 1),
+];
+
+const CONTINUING_RESOURCES: [FixedFieldElement; 11] = [
+    el("frequency", 18, 1),
+    el("regularity", 19, 1),
+    el("type_of_continuing_resource", 21, 1),
+    el("form_of_original_item", 22, 1),
+    el("form_of_item", 23, 1),
+    el("nature_of_entire_work", 24, 1),
+    el("nature_of_contents", 25, 3),
+    el("government_publication", 28, 1),
+    el("conference_publication", 29, 1),
+    el("original_alphabet_or_script_of_title", 33, 1),
+    el("entry_convention", 34, 1),
+];
+
+const VISUAL_MATERIALS: [FixedFieldElement; 6] = [
+    el("running_time", 18, 3),
+    el("target_audience", 22, 1),
+    el("government_publication", 28, 1),
+    el("form_of_item", 29, 1),
+    el("type_of_visual_material", 33, 1),
+    el("technique", 34, 1),
+];
+
+const MIXED_MATERIALS: [FixedFieldElement; 1] = [el("form_of_item", 23, 1)];
+
+const AUTHORITY_008: [FixedFieldElement; 20] = [
+    el("date_entered", 0, 6),
+    el("direct_or_indirect_geographic_subdivision", 6, 1),
+    el("romanization_scheme", 7, 1),
+    el("language_of_catalog", 8, 1),
+    el("kind_of_record", 9, 1),
+    el("descriptive_cataloging_rules", 10, 1),
+    el("subject_heading_system", 11, 1),
+    el("type_of_series", 12, 1),
+    el("numbered_or_unnumbered_series", 13, 1),
+    el("heading_use_main_or_added_entry", 14, 1),
+    el("heading_use_subject_added_entry", 15, 1),
+    el("heading_use_series_added_entry", 16, 1),
+    el("type_of_subject_subdivision", 17, 1),
+    el("type_of_government_agency", 28, 1),
+    el("reference_evaluation", 29, 1),
+    el("record_update_in_process", 31, 1),
+    el("undifferentiated_personal_name", 32, 1),
+    el("level_of_establishment", 33, 1),
+    el("modified_record", 38, 1),
+    el("cataloging_source", 39, 1),
+];
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn check_ascii(field_type: usize, data: &[u8]) -> std::io::Result<()> {
+    if data.iter().all(|b| (b' '..=b'~').contains(b)) {
+        Ok(())
+    } else {
+        Err(invalid(format!(
+            "{:03} contains non-ASCII data",
+            field_type
+        )))
+    }
+}
+
+fn get_element<'d>(data: &'d [u8], e: &FixedFieldElement) -> Option<&'d str> {
+    // 007 fields are often shorter than their full definition
+    let bytes = data.get(e.start..e.start + e.len)?;
+    Some(std::str::from_utf8(bytes).unwrap())
+}
+
+fn set_element(data: &mut [u8], e: &FixedFieldElement, value: &str) -> std::io::Result<()> {
+    if value.len() != e.len {
+        return Err(invalid(format!(
+            "{} needs {} characters, got `{}`",
+            e.name, e.len, value
+        )));
+    }
+    if !value.bytes().all(|b| (b' '..=b'~').contains(&b)) {
+        return Err(invalid(format!(
+            "{} must be ASCII, got `{}`",
+            e.name, value
+        )));
+    }
+    data[e.start..e.start + e.len].copy_from_slice(value.as_bytes());
+    Ok(())
+}
+
+fn check_date(name: &str, value: &str) -> std::io::Result<()> {
+    // digits, `u` for unknown digits, blanks and fill characters
+    if value.len() == 4
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_digit() || b == b'u' || b == b' ' || b == b'|')
+    {
+        Ok(())
+    } else {
+        Err(invalid(format!(
+            "{} is not a valid date: `{}`",
+            name, value
+        )))
+    }
+}
+
+fn check_code(name: &str, value: &str, len: usize) -> std::io::Result<()> {
+    let all = |f: fn(&u8) -> bool| value.as_bytes().iter().all(f);
+    if value.len() == len
+        && (all(u8::is_ascii_lowercase) || all(|&b| b == b' ') || all(|&b| b == b'|'))
+    {
+        Ok(())
+    } else {
+        Err(invalid(format!(
+            "{} is not a valid code: `{}`",
+            name, value
+        )))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field008Kind {
+    Bibliographic(MaterialType),
+    Authority,
+}
+
+impl Field008Kind {
+    pub fn for_leader(leader: &[u8]) -> Option<Field008Kind> {
+        if leader[6] == b'z' {
+            Some(Field008Kind::Authority)
+        } else {
+            MaterialType::from_leader(leader).map(Field008Kind::Bibliographic)
+        }
+    }
+}
+
+/** 008, fixed-length data elements **/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field008 {
+    kind: Field008Kind,
+    data: [u8; 40],
+}
+
+impl Field008 {
+    /** A new 008 with all positions set to the fill character **/
+    pub fn new(kind: Field008Kind) -> Field008 {
+        Field008 {
+            kind,
+            data: [b'|'; 40],
+        }
+    }
+
+    pub fn parse(kind: Field008Kind, data: &[u8]) -> std::io::Result<Field008> {
+        if data.len() != 40 {
+            return Err(invalid(format!(
+                "008 must have 40 positions, has {}",
+                data.len()
+            )));
+        }
+        check_ascii(8, data)?;
+        let mut f = Field008::new(kind);
+        f.data.copy_from_slice(data);
+        Ok(f)
+    }
+
+    /** Decode the first 008 of the record, using the layout its leader calls for **/
+    pub fn from_record(record: &dyn Record) -> std::io::Result<Option<Field008>> {
+        let field = match record.field_iter(Some(8)).next() {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        let kind = Field008Kind::for_leader(record.leader())
+            .ok_or_else(|| invalid("no 008 layout for this record type".to_string()))?;
+        Field008::parse(kind, field.data).map(Some)
+    }
+
+    pub fn kind(&self) -> Field008Kind {
+        self.kind
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn elements(&self) -> Vec<FixedFieldElement> {
+        match self.kind {
+            Field008Kind::Authority => AUTHORITY_008.to_vec(),
+            Field008Kind::Bibliographic(material_type) => BIBLIOGRAPHIC_008
+                .iter()
+                .chain(material_type.fixed_field_elements().iter())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn element(&self, name: &str) -> Option<FixedFieldElement> {
+        self.elements().into_iter().find(|e| e.name == name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        get_element(&self.data, &self.element(name)?)
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        let e = self
+            .element(name)
+            .ok_or_else(|| invalid(format!("008 has no element {}", name)))?;
+        set_element(&mut self.data, &e, value)
+    }
+
+    fn get_bibliographic(&self, name: &str) -> Option<&str> {
+        match self.kind {
+            Field008Kind::Bibliographic(_) => self.get(name),
+            Field008Kind::Authority => None,
+        }
+    }
+
+    pub fn date_entered(&self) -> &str {
+        self.get("date_entered").unwrap()
+    }
+    pub fn date_type(&self) -> Option<u8> {
+        self.get_bibliographic("date_type").map(|s| s.as_bytes()[0])
+    }
+    pub fn date1(&self) -> Option<&str> {
+        self.get_bibliographic("date1")
+    }
+    pub fn date2(&self) -> Option<&str> {
+        self.get_bibliographic("date2")
+    }
+    pub fn place(&self) -> Option<&str> {
+        self.get_bibliographic("place")
+    }
+    pub fn language(&self) -> Option<&str> {
+        self.get_bibliographic("language")
+    }
+    pub fn form_of_item(&self) -> Option<u8> {
+        self.get("form_of_item").map(|s| s.as_bytes()[0])
+    }
+    /** 008/28, for authorities the type of government agency **/
+    pub fn government_publication(&self) -> Option<u8> {
+        self.get("government_publication")
+            .or_else(|| self.get("type_of_government_agency"))
+            .map(|s| s.as_bytes()[0])
+    }
+    pub fn kind_of_record(&self) -> Option<u8> {
+        self.get("kind_of_record").map(|s| s.as_bytes()[0])
+    }
+    pub fn descriptive_cataloging_rules(&self) -> Option<u8> {
+        self.get("descriptive_cataloging_rules")
+            .map(|s| s.as_bytes()[0])
+    }
+    pub fn subject_heading_system(&self) -> Option<u8> {
+        self.get("subject_heading_system").map(|s| s.as_bytes()[0])
+    }
+    pub fn level_of_establishment(&self) -> Option<u8> {
+        self.get("level_of_establishment").map(|s| s.as_bytes()[0])
+    }
+
+    pub fn set_date_entered(&mut self, date: &str) -> std::io::Result<()> {
+        if date.len() != 6 || !date.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid(format!(
+                "date_entered must be yymmdd, got `{}`",
+                date
+            )));
+        }
+        self.set("date_entered", date)
+    }
+    pub fn set_dates(&mut self, date_type: u8, date1: &str, date2: &str) -> std::io::Result<()> {
+        check_date("date1", date1)?;
+        check_date("date2", date2)?;
+        if !b"bcdeikmnpqrstu|".contains(&date_type) {
+            return Err(invalid(format!(
+                "invalid date type `{}`",
+                date_type as char
+            )));
+        }
+        self.set("date_type", std::str::from_utf8(&[date_type]).unwrap())?;
+        self.set("date1", date1)?;
+        self.set("date2", date2)
+    }
+    pub fn set_place(&mut self, place: &str) -> std::io::Result<()> {
+        // MARC country codes are two or three letters, left justified
+        let valid = (2..=3).contains(&place.len()) && place.bytes().all(|b| b.is_ascii_lowercase());
+        if !valid && place != "|||" {
+            return Err(invalid(format!("place is not a country code: `{}`", place)));
+        }
+        self.set("place", &format!("{:<3}", place))
+    }
+    pub fn set_language(&mut self, language: &str) -> std::io::Result<()> {
+        check_code("language", language, 3)?;
+        self.set("language", language)
+    }
+    pub fn set_government_publication(&mut self, code: u8) -> std::io::Result<()> {
+        if !b" acfilmosuz|".contains(&code) {
+            return Err(invalid(format!(
+                "invalid government publication code `{}`",
+                code as char
+            )));
+        }
+        let name = match self.kind {
+            Field008Kind::Authority => "type_of_government_agency",
+            Field008Kind::Bibliographic(_) => "government_publication",
+        };
+        self.set(name, std::str::from_utf8(&[code]).unwrap())
+    }
+
+    /** Replace the 008 of the record with this one **/
+    pub fn write_to(&self, record: &mut OwnedRecord) -> std::io::Result<()> {
+        record.set_field(OwnedRecordField {
+            field_type: 8,
+            data: self.data.to_vec(),
+        })
+    }
+}
+
+/** 006, fixed-length data elements for additional material characteristics **/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field006 {
+    material_type: MaterialType,
+    data: [u8; 18],
+}
+
+impl Field006 {
+    pub fn new(form_of_material: u8) -> std::io::Result<Field006> {
+        let material_type =
+            MaterialType::from_form_of_material(form_of_material).ok_or_else(|| {
+                invalid(format!(
+                    "invalid 006 form of material `{}`",
+                    form_of_material as char
+                ))
+            })?;
+        let mut data = [b'|'; 18];
+        data[0] = form_of_material;
+        Ok(Field006 {
+            material_type,
+            data,
+        })
+    }
+
+    pub fn parse(data: &[u8]) -> std::io::Result<Field006> {
+        if data.len() != 18 {
+            return Err(invalid(format!(
+                "006 must have 18 positions, has {}",
+                data.len()
+            )));
+        }
+        check_ascii(6, data)?;
+        let mut f = Field006::new(data[0])?;
+        f.data.copy_from_slice(data);
+        Ok(f)
+    }
+
+    pub fn from_record(record: &dyn Record) -> std::io::Result<Vec<Field006>> {
+        record
+            .field_iter(Some(6))
+            .map(|f| Field006::parse(f.data))
+            .collect()
+    }
+
+    pub fn material_type(&self) -> MaterialType {
+        self.material_type
+    }
+
+    pub fn form_of_material(&self) -> u8 {
+        self.data[0]
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /** The elements with 006 positions, i.e. 008/18-34 shifted to 006/01-17 **/
+    pub fn elements(&self) -> Vec<FixedFieldElement> {
+        std::iter::once(el("form_of_material", 0, 1))
+            .chain(
+                self.material_type
+                    .fixed_field_elements()
+                    .iter()
+                    .map(|e| el(e.name, e.start - 17, e.len)),
+            )
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let e = self.elements().into_iter().find(|e| e.name == name)?;
+        get_element(&self.data, &e)
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        if name == "form_of_material" {
+            return Err(invalid(
+                "the form of material of a 006 is fixed".to_string(),
+            ));
+        }
+        let e = self
+            .elements()
+            .into_iter()
+            .find(|e| e.name == name)
+            .ok_or_else(|| invalid(format!("006 has no element {}", name)))?;
+        set_element(&mut self.data, &e, value)
+    }
+
+    pub fn form_of_item(&self) -> Option<u8> {
+        self.get("form_of_item").map(|s| s.as_bytes()[0])
+    }
+
+    /** Add this 006 to the record, after any existing ones **/
+    pub fn add_to(&self, record: &mut OwnedRecord) -> std::io::Result<()> {
+        record.insert_field(OwnedRecordField {
+            field_type: 6,
+            data: self.data.to_vec(),
+        })
+    }
+}
+
+const MAP_007: [FixedFieldElement; 5] = [
+    el("color", 3, 1),
+    el("physical_medium", 4, 1),
+    el("type_of_reproduction", 5, 1),
+    el("production_details", 6, 1),
+    el("positive_negative_aspect", 7, 1),
+];
+
+const ELECTRONIC_RESOURCE_007: [FixedFieldElement; 9] = [
+    el("color", 3, 1),
+    el("dimensions", 4, 1),
+    el("sound", 5, 1),
+    el("image_bit_depth", 6, 3),
+    el("file_formats", 9, 1),
+    el("quality_assurance_targets", 10, 1),
+    el("antecedent_source", 11, 1),
+    el("level_of_compression", 12, 1),
+    el("reformatting_quality", 13, 1),
+];
+
+const GLOBE_007: [FixedFieldElement; 3] = [
+    el("color", 3, 1),
+    el("physical_medium", 4, 1),
+    el("type_of_reproduction", 5, 1),
+];
+
+const TACTILE_MATERIAL_007: [FixedFieldElement; 4] = [
+    el("class_of_braille_writing", 3, 2),
+    el("level_of_contraction", 5, 1),
+    el("braille_music_format", 6, 3),
+    el("special_physical_characteristics", 9, 1),
+];
+
+const PROJECTED_GRAPHIC_007: [FixedFieldElement; 6] = [
+    el("color", 3, 1),
+    el("base_of_emulsion", 4, 1),
+    el("sound_on_medium_or_separate", 5, 1),
+    el("medium_for_sound", 6, 1),
+    el("dimensions", 7, 1),
+    el("secondary_support_material", 8, 1),
+];
+
+const MICROFORM_007: [FixedFieldElement; 8] = [
+    el("positive_negative_aspect", 3, 1),
+    el("dimensions", 4, 1),
+    el("reduction_ratio_range", 5, 1),
+    el("reduction_ratio", 6, 3),
+    el("color", 9, 1),
+    el("emulsion_on_film", 10, 1),
+    el("generation", 11, 1),
+    el("base_of_film", 12, 1),
+];
+
+const NONPROJECTED_GRAPHIC_007: [FixedFieldElement; 3] = [
+    el("color", 3, 1),
+    el("primary_support_material", 4, 1),
+    el("secondary_support_material", 5, 1),
+];
+
+const MOTION_PICTURE_007: [FixedFieldElement; 15] = [
+    el("color", 3, 1),
+    el("motion_picture_presentation_format", 4, 1),
+    el("sound_on_medium_or_separate", 5, 1),
+    el("medium_for_sound", 6, 1),
+    el("dimensions", 7, 1),
+    el("configuration_of_playback_channels", 8, 1),
+    el("production_elements", 9, 1),
+    el("positive_negative_aspect", 10, 1),
+    el("generation", 11, 1),
+    el("base_of_film", 12, 1),
+    el("refined_categories_of_color", 13, 1),
+    el("kind_of_color_stock_or_print", 14, 1),
+    el("deterioration_stage", 15, 1),
+    el("completeness", 16, 1),
+    el("film_inspection_date", 17, 6),
+];
+
+const REMOTE_SENSING_IMAGE_007: [FixedFieldElement; 7] = [
+    el("altitude_of_sensor", 3, 1),
+    el("attitude_of_sensor", 4, 1),
+    el("cloud_cover", 5, 1),
+    el("platform_construction_type", 6, 1),
+    el("platform_use_category", 7, 1),
+    el("sensor_type", 8, 1),
+    el("data_type", 9, 2),
+];
+
+const SOUND_RECORDING_007: [FixedFieldElement; 11] = [
+    el("speed", 3, 1),
+    el("configuration_of_playback_channels", 4, 1),
+    el("groove_width_pitch", 5, 1),
+    el("dimensions", 6, 1),
+    el("tape_width", 7, 1),
+    el("tape_configuration", 8, 1),
+    el("kind_of_disc_cylinder_or_tape", 9, 1),
+    el("kind_of_material", 10, 1),
+    el("kind_of_cutting", 11, 1),
+    el("special_playback_characteristics", 12, 1),
+    el("capture_and_storage_technique", 13, 1),
+];
+
+const VIDEORECORDING_007: [FixedFieldElement; 6] = [
+    el("color", 3, 1),
+    el("videorecording_format", 4, 1),
+    el("sound_on_medium_or_separate", 5, 1),
+    el("medium_for_sound", 6, 1),
+    el("dimensions", 7, 1),
+    el("configuration_of_playback_channels", 8, 1),
+];
+
+/** 007/00 **/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category007 {
+    Map = b'a' as isize,
+    ElectronicResource = b'c' as isize,
+    Globe = b'd' as isize,
+    TactileMaterial = b'f' as isize,
+    ProjectedGraphic = b'g' as isize,
+    Microform = b'h' as isize,
+    NonprojectedGraphic = b'k' as isize,
+    MotionPicture = b'm' as isize,
+    Kit = b'o' as isize,
+    NotatedMusic = b'q' as isize,
+    RemoteSensingImage = b'r' as isize,
+    SoundRecording = b's' as isize,
+    Text = b't' as isize,
+    Videorecording = b'v' as isize,
+    Unspecified = b'z' as isize,
+}
+
+impl Category007 {
+    pub fn from_byte(b: u8) -> Option<Category007> {
+        use Category007::*;
+        Some(match b {
+            b'a' => Map,
+            b'c' => ElectronicResource,
+            b'd' => Globe,
+            b'f' => TactileMaterial,
+            b'g' => ProjectedGraphic,
+            b'h' => Microform,
+            b'k' => NonprojectedGraphic,
+            b'm' => MotionPicture,
+            b'o' => Kit,
+            b'q' => NotatedMusic,
+            b'r' => RemoteSensingImage,
+            b's' => SoundRecording,
+            b't' => Text,
+            b'v' => Videorecording,
+            b'z' => Unspecified,
+            _ => return None,
+        })
+    }
+
+    /** Elements after 007/00 and /01, which all categories share **/
+    pub fn elements(&self) -> &'static [FixedFieldElement] {
+        use Category007::*;
+        match self {
+            Map => &MAP_007,
+            ElectronicResource => &ELECTRONIC_RESOURCE_007,
+            Globe => &GLOBE_007,
+            TactileMaterial => &TACTILE_MATERIAL_007,
+            ProjectedGraphic => &PROJECTED_GRAPHIC_007,
+            Microform => &MICROFORM_007,
+            NonprojectedGraphic => &NONPROJECTED_GRAPHIC_007,
+            MotionPicture => &MOTION_PICTURE_007,
+            RemoteSensingImage => &REMOTE_SENSING_IMAGE_007,
+            SoundRecording => &SOUND_RECORDING_007,
+            Videorecording => &VIDEORECORDING_007,
+            Kit | NotatedMusic | Text | Unspecified => &[],
+        }
+    }
+
+    /** The number of positions of a complete 007 of this category **/
+    pub fn field_len(&self) -> usize {
+        self.elements().last().map_or(2, |e| e.start + e.len)
+    }
+}
+
+/** 007, physical description fixed field **/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field007 {
+    category: Category007,
+    data: Vec<u8>,
+}
+
+impl Field007 {
+    /** A complete 007 of the category with all positions but 00 set to the fill character **/
+    pub fn new(category: Category007) -> Field007 {
+        let mut data = vec![b'|'; category.field_len()];
+        data[0] = category as u8;
+        if category.field_len() > 2 {
+            // 007/02 is undefined in all categories
+            data[2] = b' ';
+        }
+        Field007 { category, data }
+    }
+
+    pub fn parse(data: &[u8]) -> std::io::Result<Field007> {
+        if data.len() < 2 {
+            return Err(invalid("007 needs at least two positions".to_string()));
+        }
+        check_ascii(7, data)?;
+        let category = Category007::from_byte(data[0])
+            .ok_or_else(|| invalid(format!("invalid 007 category `{}`", data[0] as char)))?;
+        Ok(Field007 {
+            category,
+            data: data.to_vec(),
+        })
+    }
+
+    pub fn from_record(record: &dyn Record) -> std::io::Result<Vec<Field007>> {
+        record
+            .field_iter(Some(7))
+            .map(|f| Field007::parse(f.data))
+            .collect()
+    }
+
+    pub fn category(&self) -> Category007 {
+        self.category
+    }
+
+    pub fn specific_material_designation(&self) -> u8 {
+        self.data[1]
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn elements(&self) -> Vec<FixedFieldElement> {
+        [
+            el("category_of_material", 0, 1),
+            el("specific_material_designation", 1, 1),
+        ]
+        .iter()
+        .chain(self.category.elements().iter())
+        .cloned()
+        .collect()
+    }
+
+    /** None if the element is not defined for the category or the field is too short **/
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let e = self.elements().into_iter().find(|e| e.name == name)?;
+        get_element(&self.data, &e)
+    }
+
+    /**
+     * Setting an element past the end of a short 007 pads it with fill
+     * characters, and the undefined 007/02 with a blank
+     */
+    pub fn set(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        if name == "category_of_material" {
+            return Err(invalid("the category of a 007 is fixed".to_string()));
+        }
+        let e = self
+            .elements()
+            .into_iter()
+            .find(|e| e.name == name)
+            .ok_or_else(|| invalid(format!("007 has no element {}", name)))?;
+        let len = self.data.len();
+        if len < e.start + e.len {
+            self.data.resize(e.start + e.len, b'|');
+            if len <= 2 {
+                self.data[2] = b' ';
+            }
+        }
+        set_element(&mut self.data, &e, value)
+    }
+
+    /** Add this 007 to the record, after any existing ones **/
+    pub fn add_to(&self, record: &mut OwnedRecord) -> std::io::Result<()> {
+        record.insert_field(OwnedRecordField {
+            field_type: 7,
+            data: self.data.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixedfield::*;
+    use crate::marcrecord::MarcHeader;
+    use crate::marcrecord::MarcRecord;
+    use crate::testutil::STR;
+
+    #[test]
+    fn authority_008() -> Result<(), String> {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let f = Field008::from_record(&record)
+            .map_err(|e| e.to_string())?
+            .ok_or("no 008")?;
+        assert_eq!(f.kind(), Field008Kind::Authority);
+        assert_eq!(f.date_entered(), "880701");
+        assert_eq!(f.kind_of_record(), Some(b'a'));
+        assert_eq!(f.descriptive_cataloging_rules(), Some(b'z'));
+        assert_eq!(f.subject_heading_system(), Some(b'z'));
+        assert_eq!(f.get("heading_use_subject_added_entry"), Some("a"));
+        assert_eq!(f.level_of_establishment(), Some(b'a'));
+        assert_eq!(f.government_publication(), Some(b' '));
+        assert_eq!(f.get("cataloging_source"), Some("c"));
+        assert_eq!(f.language(), None);
+        assert_eq!(f.date1(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn write_back() -> Result<(), String> {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let mut owned = record.to_owned();
+        let mut f = Field008::from_record(&owned)
+            .map_err(|e| e.to_string())?
+            .ok_or("no 008")?;
+        assert!(f.set_government_publication(b'x').is_err());
+        f.set_government_publication(b'f')
+            .map_err(|e| e.to_string())?;
+        f.set("level_of_establishment", "c")
+            .map_err(|e| e.to_string())?;
+        f.write_to(&mut owned).map_err(|e| e.to_string())?;
+        let mut result = Vec::new();
+        owned.to_marc21(&mut result).map_err(|e| e.to_string())?;
+        assert_eq!(result.len(), STR.len());
+        let reread = MarcRecord::new(MarcHeader::new(&result[..24]), &result[24..]);
+        let f = Field008::from_record(&reread)
+            .map_err(|e| e.to_string())?
+            .ok_or("no 008")?;
+        assert_eq!(f.government_publication(), Some(b'f'));
+        assert_eq!(f.level_of_establishment(), Some(b'c'));
+        Ok(())
+    }
+
+    #[test]
+    fn bibliographic_fields() -> Result<(), String> {
+        let mut f = Field008::new(Field008Kind::Bibliographic(MaterialType::Books));
+        f.set_date_entered("230115").map_err(|e| e.to_string())?;
+        f.set_dates(b's', "1999", "    ")
+            .map_err(|e| e.to_string())?;
+        f.set_place("gw").map_err(|e| e.to_string())?;
+        f.set_language("ger").map_err(|e| e.to_string())?;
+        f.set("form_of_item", "o").map_err(|e| e.to_string())?;
+        assert!(f.set_language("GER").is_err());
+        assert!(f.set_dates(b's', "19x9", "    ").is_err());
+        assert!(f.set("running_time", "123").is_err());
+        assert_eq!(&f.data()[..18], b"230115s1999    gw ");
+        assert_eq!(f.language(), Some("ger"));
+        assert_eq!(f.form_of_item(), Some(b'o'));
+
+        let mut f = Field006::new(b'm').map_err(|e| e.to_string())?;
+        f.set("type_of_computer_file", "d")
+            .map_err(|e| e.to_string())?;
+        f.set("form_of_item", "o").map_err(|e| e.to_string())?;
+        assert_eq!(f.data()[9], b'd');
+        assert_eq!(Field006::parse(f.data()).map_err(|e| e.to_string())?, f);
+
+        let f = Field007::parse(b"cr |||||||||||").map_err(|e| e.to_string())?;
+        assert_eq!(f.category(), Category007::ElectronicResource);
+        assert_eq!(f.specific_material_designation(), b'r');
+        assert_eq!(f.get("image_bit_depth"), Some("|||"));
+        let mut f = Field007::parse(b"vd").map_err(|e| e.to_string())?;
+        assert_eq!(f.get("color"), None);
+        f.set("videorecording_format", "v")
+            .map_err(|e| e.to_string())?;
+        assert_eq!(f.data(), b"vd |v");
+        assert_eq!(Field007::new(Category007::SoundRecording).data().len(), 14);
+        Ok(())
+    }
+}
@@ -1,7 +1,43 @@
+#[cfg(feature = "async")]
+pub mod asyncio;
+pub mod authority;
+pub mod authoritygraph;
+pub mod authorityindex;
+pub mod bibliographic;
+pub mod compare;
+pub mod compression;
+pub mod dedup;
+pub mod diff;
+pub mod fixedfield;
+pub mod linkage;
+pub mod linkingentry;
+#[cfg(feature = "json")]
+pub mod marcjson;
 pub mod marcrecord;
+#[cfg(feature = "xml")]
+pub mod marcxml;
+pub mod merge;
+#[cfg(feature = "mmap")]
+pub mod mmapreader;
+pub mod mrk;
+pub mod naco;
+pub mod offsetindex;
 pub mod ownedrecord;
+pub mod parallel;
+pub mod profiler;
+pub mod rdfexport;
 pub mod record;
+pub mod recordreader;
+pub mod recordwriter;
+pub mod resourceformat;
+pub mod sortedruns;
+pub mod standardnumber;
+#[cfg(test)]
+mod testutil;
+pub mod update;
 pub mod util;
+pub mod validation;
+pub mod validationprofile;
 
 #[cfg(test)]
 mod tests {
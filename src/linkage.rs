@@ -0,0 +1,517 @@
+/*!
+ * Field linkage: pairing fields with their 880 alternate graphic
+ * representations through `$6`, and grouping fields through `$8`.
+ *
+ * Fields are identified by their index in `field_iter(None)` order.
+ */
+use crate::ownedrecord::OwnedRecord;
+use crate::record::*;
+use crate::validation::*;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+
+/** The content of a `$6`, e.g. `880-01` or `245-01/$1` **/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Linkage {
+    pub tag: usize,
+    // 0 for 880s without a counterpart
+    pub occurrence: usize,
+    // script identification code, e.g. `$1` for CJK or `(N` for Cyrillic
+    pub script: Option<String>,
+    // `r` for right-to-left fields
+    pub orientation: Option<u8>,
+}
+
+impl Linkage {
+    pub fn parse(value: &str) -> Option<Linkage> {
+        let mut parts = value.split('/');
+        let (tag, occurrence) = parts.next()?.split_once('-')?;
+        if tag.len() != 3 || occurrence.len() < 2 {
+            return None;
+        }
+        let script = parts
+            .next()
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string());
+        let orientation = parts.next().and_then(|s| s.bytes().next());
+        Some(Linkage {
+            tag: tag.parse().ok()?,
+            occurrence: occurrence.parse().ok()?,
+            script,
+            orientation,
+        })
+    }
+}
+
+impl std::fmt::Display for Linkage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:03}-{:02}", self.tag, self.occurrence)?;
+        if let Some(s) = &self.script {
+            write!(f, "/{}", s)?;
+            if let Some(o) = self.orientation {
+                write!(f, "/{}", o as char)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/** A field and the 880 that holds its alternate script form **/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkedPair {
+    pub field: usize,
+    pub alternate: usize,
+    pub occurrence: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkageProblem {
+    // a $6 that does not parse
+    Malformed { field: usize, value: String },
+    // a field whose $6 points to an 880 that does not point back
+    MissingAlternate { field: usize, occurrence: usize },
+    // an 880 with an occurrence number that no field points to
+    MissingField { alternate: usize, occurrence: usize },
+    // an 880 that names a different tag than the field pointing to it
+    TagMismatch { field: usize, alternate: usize },
+    // an occurrence number used more than once among fields or among 880s
+    DuplicateOccurrence { field: usize, occurrence: usize },
+}
+
+impl LinkageProblem {
+    pub fn field(&self) -> usize {
+        match self {
+            LinkageProblem::Malformed { field, .. }
+            | LinkageProblem::MissingAlternate { field, .. }
+            | LinkageProblem::TagMismatch { field, .. }
+            | LinkageProblem::DuplicateOccurrence { field, .. } => *field,
+            LinkageProblem::MissingField { alternate, .. } => *alternate,
+        }
+    }
+}
+
+impl std::fmt::Display for LinkageProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkageProblem::Malformed { value, .. } => write!(f, "malformed $6 {}", value),
+            LinkageProblem::MissingAlternate { occurrence, .. } => {
+                write!(f, "no 880 for occurrence {:02}", occurrence)
+            }
+            LinkageProblem::MissingField { occurrence, .. } => {
+                write!(f, "no field links to 880 occurrence {:02}", occurrence)
+            }
+            LinkageProblem::TagMismatch { .. } => write!(f, "the linked 880 names a different tag"),
+            LinkageProblem::DuplicateOccurrence { occurrence, .. } => {
+                write!(f, "occurrence {:02} is used more than once", occurrence)
+            }
+        }
+    }
+}
+
+/** The result of pairing the `$6` linked fields of a record **/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldLinkage {
+    pub pairs: Vec<LinkedPair>,
+    // 880s with occurrence number 00
+    pub unlinked: Vec<usize>,
+    pub problems: Vec<LinkageProblem>,
+}
+
+impl FieldLinkage {
+    pub fn alternate_of(&self, field: usize) -> Option<usize> {
+        self.pairs
+            .iter()
+            .find(|p| p.field == field)
+            .map(|p| p.alternate)
+    }
+
+    pub fn field_of(&self, alternate: usize) -> Option<usize> {
+        self.pairs
+            .iter()
+            .find(|p| p.alternate == alternate)
+            .map(|p| p.field)
+    }
+}
+
+/** Pair every field that has a `$6` with its 880, and report what does not pair up **/
+pub fn link_fields<R: Record + ?Sized>(record: &R) -> FieldLinkage {
+    let mut result = FieldLinkage::default();
+    // occurrence number to field index and tag
+    let mut fields: BTreeMap<usize, (usize, usize)> = BTreeMap::new();
+    let mut alternates: BTreeMap<usize, (usize, usize)> = BTreeMap::new();
+    for (index, field) in record.field_iter(None).enumerate() {
+        if !RecordField::is_data_field_type(field.field_type) {
+            continue;
+        }
+        let value = match field.subfield_values(b'6').next() {
+            Some(v) => v,
+            None => continue,
+        };
+        let linkage = match Linkage::parse(&value) {
+            Some(l) if (field.field_type == 880) != (l.tag == 880) => l,
+            _ => {
+                result.problems.push(LinkageProblem::Malformed {
+                    field: index,
+                    value: value.to_string(),
+                });
+                continue;
+            }
+        };
+        if field.field_type == 880 && linkage.occurrence == 0 {
+            result.unlinked.push(index);
+            continue;
+        }
+        let (map, tag) = if field.field_type == 880 {
+            (&mut alternates, linkage.tag)
+        } else {
+            (&mut fields, field.field_type)
+        };
+        match map.entry(linkage.occurrence) {
+            Entry::Occupied(_) => result.problems.push(LinkageProblem::DuplicateOccurrence {
+                field: index,
+                occurrence: linkage.occurrence,
+            }),
+            Entry::Vacant(e) => {
+                e.insert((index, tag));
+            }
+        }
+    }
+    for (&occurrence, &(field, tag)) in fields.iter() {
+        match alternates.get(&occurrence) {
+            Some(&(alternate, alternate_tag)) if alternate_tag == tag => {
+                result.pairs.push(LinkedPair {
+                    field,
+                    alternate,
+                    occurrence,
+                })
+            }
+            Some(&(alternate, _)) => result
+                .problems
+                .push(LinkageProblem::TagMismatch { field, alternate }),
+            None => result
+                .problems
+                .push(LinkageProblem::MissingAlternate { field, occurrence }),
+        }
+    }
+    for (&occurrence, &(alternate, _)) in alternates.iter() {
+        if !fields.contains_key(&occurrence) {
+            result.problems.push(LinkageProblem::MissingField {
+                alternate,
+                occurrence,
+            });
+        }
+    }
+    result.problems.sort_by_key(|p| p.field());
+    result
+}
+
+/** Reports the problems `link_fields` finds as errors **/
+pub struct LinkageValidator;
+
+impl Validator for LinkageValidator {
+    fn validate(&self, record: &dyn Record, findings: &mut Vec<Finding>) {
+        let field_types: Vec<usize> = record.field_iter(None).map(|f| f.field_type).collect();
+        for problem in link_fields(record).problems {
+            findings.push(Finding {
+                severity: Severity::Error,
+                rule: "linkage".to_string(),
+                field_type: Some(field_types[problem.field()]),
+                subfield: Some(b'6'),
+                message: problem.to_string(),
+            });
+        }
+    }
+}
+
+/** Replace the value of the first subfield with the given code, or remove it if `value` is None **/
+fn replace_subfield(data: &[u8], code: u8, value: Option<&str>) -> Vec<u8> {
+    let start = match data.windows(2).position(|w| w == [0x1f, code]) {
+        Some(p) => p,
+        None => return data.to_vec(),
+    };
+    let end = data[start + 1..]
+        .iter()
+        .position(|&b| b == 0x1f || b == 0x1e)
+        .map_or(data.len(), |p| start + 1 + p);
+    let mut out = data[..start].to_vec();
+    if let Some(v) = value {
+        out.extend_from_slice(&[0x1f, code]);
+        out.extend_from_slice(v.as_bytes());
+    }
+    out.extend_from_slice(&data[end..]);
+    out
+}
+
+/**
+ * Renumber the `$6` occurrence numbers after editing: pairs are numbered from
+ * 01 in field order, 880s without a partner get occurrence 00 and the `$6` of
+ * fields without an 880 is removed. Malformed `$6` and the other problems are
+ * left alone, and the pairs skip the numbers these still use. Returns the
+ * number of pairs, fails if the record no longer fits into ISO 2709.
+ */
+pub fn renumber_links(record: &mut OwnedRecord) -> std::io::Result<usize> {
+    let linkage = link_fields(record);
+    let mut changes: Vec<(usize, Option<Linkage>)> = Vec::new();
+    let linkage_of = |index: usize| {
+        record.field_iter(None).nth(index).and_then(|f| {
+            f.subfield_values(b'6')
+                .next()
+                .and_then(|v| Linkage::parse(&v))
+        })
+    };
+    for problem in linkage.problems.iter() {
+        match *problem {
+            LinkageProblem::MissingAlternate { field, .. } => changes.push((field, None)),
+            LinkageProblem::MissingField { alternate, .. } => {
+                if let Some(mut l) = linkage_of(alternate) {
+                    l.occurrence = 0;
+                    changes.push((alternate, Some(l)));
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut pairs = linkage.pairs.clone();
+    pairs.sort_by_key(|p| p.field);
+    let renumbered: Vec<usize> = pairs
+        .iter()
+        .flat_map(|p| [p.field, p.alternate])
+        .chain(changes.iter().map(|(index, _)| *index))
+        .collect();
+    // occurrence numbers of the fields that are left alone
+    let kept: BTreeSet<usize> = record
+        .field_iter(None)
+        .enumerate()
+        .filter(|(index, _)| !renumbered.contains(index))
+        .filter_map(|(index, _)| linkage_of(index))
+        .map(|l| l.occurrence)
+        .collect();
+    let mut occurrence = 0;
+    for pair in pairs.iter() {
+        occurrence += 1;
+        while kept.contains(&occurrence) {
+            occurrence += 1;
+        }
+        for index in [pair.field, pair.alternate] {
+            if let Some(mut l) = linkage_of(index) {
+                l.occurrence = occurrence;
+                changes.push((index, Some(l)));
+            }
+        }
+    }
+    for (index, l) in changes {
+        let value = l.map(|l| l.to_string());
+        record.field_data[index] =
+            replace_subfield(&record.field_data[index], b'6', value.as_deref());
+    }
+    record.update_len()?;
+    Ok(linkage.pairs.len())
+}
+
+/** The content of a `$8`, e.g. `1.5\a` **/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldLink {
+    pub link_number: usize,
+    pub sequence: Option<usize>,
+    // field link type, e.g. `c` for constituent item, `p` for metadata provenance
+    pub link_type: Option<u8>,
+}
+
+impl FieldLink {
+    pub fn parse(value: &str) -> Option<FieldLink> {
+        let (numbers, link_type) = match value.split_once('\\') {
+            Some((n, t)) => (n, t.bytes().next()),
+            None => (value, None),
+        };
+        let (link_number, sequence) = match numbers.split_once('.') {
+            Some((l, s)) => (l.parse().ok()?, Some(s.parse().ok()?)),
+            None => (numbers.parse().ok()?, None),
+        };
+        Some(FieldLink {
+            link_number,
+            sequence,
+            link_type,
+        })
+    }
+}
+
+/** The fields sharing one `$8` link number, ordered by sequence number **/
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldLinkGroup {
+    pub link_number: usize,
+    pub fields: Vec<(usize, FieldLink)>,
+}
+
+/**
+ * Group fields by `$8` link number, in link number order. A field with
+ * several `$8` is in several groups, unparseable `$8` are ignored.
+ */
+pub fn field_link_groups<R: Record + ?Sized>(record: &R) -> Vec<FieldLinkGroup> {
+    let mut groups: BTreeMap<usize, Vec<(usize, FieldLink)>> = BTreeMap::new();
+    for (index, field) in record.field_iter(None).enumerate() {
+        if !RecordField::is_data_field_type(field.field_type) {
+            continue;
+        }
+        for link in field
+            .subfield_values(b'8')
+            .filter_map(|v| FieldLink::parse(&v))
+        {
+            groups
+                .entry(link.link_number)
+                .or_default()
+                .push((index, link));
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(link_number, mut fields)| {
+            fields.sort_by_key(|(index, link)| (link.sequence, *index));
+            FieldLinkGroup {
+                link_number,
+                fields,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::linkage::*;
+    use crate::testutil::{record, BOOK};
+
+    #[test]
+    fn parse() {
+        let l = Linkage::parse("245-01/$1").unwrap();
+        assert_eq!(l.tag, 245);
+        assert_eq!(l.occurrence, 1);
+        assert_eq!(l.script.as_deref(), Some("$1"));
+        assert_eq!(l.to_string(), "245-01/$1");
+        assert_eq!(
+            Linkage::parse("100-03/(3/r").unwrap().orientation,
+            Some(b'r')
+        );
+        assert_eq!(Linkage::parse("880-12").unwrap().to_string(), "880-12");
+        assert!(Linkage::parse("24501").is_none());
+        let link = FieldLink::parse("1.5\\a").unwrap();
+        assert_eq!(
+            (link.link_number, link.sequence, link.link_type),
+            (1, Some(5), Some(b'a'))
+        );
+        assert_eq!(FieldLink::parse("3").unwrap().sequence, None);
+    }
+
+    #[test]
+    fn pairs_and_problems() {
+        let r = record(
+            BOOK,
+            &[
+                (1, "123"),
+                (100, "1 \x1f6880-01\x1faTolstoĭ, Lev"),
+                (245, "10\x1f6880-02\x1faVoĭna i mir"),
+                (260, "  \x1f6880-03\x1faMoskva"),
+                (500, "  \x1f6880-x\x1faNote"),
+                (880, "1 \x1f6100-01/(N\x1faТолстой, Лев"),
+                (880, "10\x1f6245-02/(N\x1faВойна и мир"),
+                (880, "  \x1f6500-00/(N\x1faПримечание"),
+                (880, "  \x1f6250-04/(N\x1faИздание"),
+            ],
+        );
+        let linkage = link_fields(&r);
+        assert_eq!(linkage.pairs.len(), 2);
+        assert_eq!(linkage.alternate_of(2), Some(6));
+        assert_eq!(linkage.field_of(5), Some(1));
+        assert_eq!(linkage.unlinked, [7]);
+        assert_eq!(
+            linkage.problems,
+            [
+                LinkageProblem::MissingAlternate {
+                    field: 3,
+                    occurrence: 3
+                },
+                LinkageProblem::Malformed {
+                    field: 4,
+                    value: "880-x".to_string()
+                },
+                LinkageProblem::MissingField {
+                    alternate: 8,
+                    occurrence: 4
+                },
+            ]
+        );
+        let findings = LinkageValidator.findings(&r);
+        let fields: Vec<Option<usize>> = findings.iter().map(|f| f.field_type).collect();
+        assert_eq!(fields, [Some(260), Some(500), Some(880)]);
+    }
+
+    #[test]
+    fn renumber() {
+        let mut r = record(
+            BOOK,
+            &[
+                (245, "10\x1f6880-05\x1faVoĭna i mir"),
+                (260, "  \x1f6880-03\x1faMoskva"),
+                (880, "10\x1f6245-05/(N\x1faВойна и мир"),
+                (880, "  \x1f6250-04/(N\x1faИздание"),
+            ],
+        );
+        assert_eq!(renumber_links(&mut r).unwrap(), 1);
+        assert_eq!(r.field_data[0], "10\x1f6880-01\x1faVoĭna i mir".as_bytes());
+        assert_eq!(r.field_data[1], b"  \x1faMoskva");
+        assert_eq!(
+            r.field_data[2],
+            "10\x1f6245-01/(N\x1faВойна и мир".as_bytes()
+        );
+        assert_eq!(r.field_data[3], "  \x1f6250-00/(N\x1faИздание".as_bytes());
+        assert!(link_fields(&r).problems.is_empty());
+    }
+
+    #[test]
+    fn renumber_around_problems() {
+        let mut r = record(
+            BOOK,
+            &[
+                (245, "10\x1f6880-04\x1faVoĭna i mir"),
+                (246, "1 \x1f6880-01\x1faVojna i mir"),
+                (260, "  \x1f6880-05\x1faMoskva"),
+                (500, "  \x1f6500-02\x1faNote"),
+                (880, "10\x1f6245-04/(N\x1faВойна и мир"),
+                (880, "  \x1f6250-01/(N\x1faИздание"),
+                (880, "  \x1f6260-05/(N\x1faМосква"),
+            ],
+        );
+        let problems = link_fields(&r).problems;
+        assert_eq!(renumber_links(&mut r).unwrap(), 2);
+        // 01 and 02 stay with the mismatched pair and the malformed $6
+        assert_eq!(r.field_data[0], "10\x1f6880-03\x1faVoĭna i mir".as_bytes());
+        assert_eq!(r.field_data[1], "1 \x1f6880-01\x1faVojna i mir".as_bytes());
+        assert_eq!(r.field_data[2], b"  \x1f6880-04\x1faMoskva");
+        assert_eq!(r.field_data[3], b"  \x1f6500-02\x1faNote");
+        assert_eq!(
+            r.field_data[4],
+            "10\x1f6245-03/(N\x1faВойна и мир".as_bytes()
+        );
+        assert_eq!(r.field_data[6], "  \x1f6260-04/(N\x1faМосква".as_bytes());
+        assert_eq!(link_fields(&r).problems, problems);
+        assert_eq!(link_fields(&r).pairs.len(), 2);
+    }
+
+    #[test]
+    fn groups() {
+        let r = record(
+            BOOK,
+            &[
+                (1, "123"),
+                (500, "  \x1f81.2\\c\x1faSecond"),
+                (541, "  \x1f82\\p\x1faProvenance"),
+                (583, "  \x1f81.1\\c\x1f82\\p\x1faFirst"),
+            ],
+        );
+        let groups = field_link_groups(&r);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].link_number, 1);
+        let fields: Vec<usize> = groups[0].fields.iter().map(|(i, _)| *i).collect();
+        assert_eq!(fields, [3, 1]);
+        let fields: Vec<usize> = groups[1].fields.iter().map(|(i, _)| *i).collect();
+        assert_eq!(fields, [2, 3]);
+    }
+}
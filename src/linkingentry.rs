@@ -0,0 +1,431 @@
+use crate::authority::AuthorityLink;
+use crate::marcrecord::MarcReader;
+use crate::record::*;
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Seek;
+
+/** The relationship a linking entry field expresses, from its tag **/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkingEntryType {
+    MainSeries,
+    Subseries,
+    OriginalLanguage,
+    Translation,
+    Supplement,
+    SupplementParent,
+    Host,
+    Constituent,
+    OtherEdition,
+    AdditionalPhysicalForm,
+    IssuedWith,
+    Preceding,
+    Succeeding,
+    DataSource,
+    Other,
+}
+
+/** Where the linked record stands in a whole/part hierarchy **/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hierarchy {
+    Parent,
+    Child,
+    Peer,
+}
+
+impl LinkingEntryType {
+    pub fn from_tag(field_type: usize) -> Option<LinkingEntryType> {
+        use LinkingEntryType::*;
+        Some(match field_type {
+            760 => MainSeries,
+            762 => Subseries,
+            765 => OriginalLanguage,
+            767 => Translation,
+            770 => Supplement,
+            772 => SupplementParent,
+            773 => Host,
+            774 => Constituent,
+            775 => OtherEdition,
+            776 => AdditionalPhysicalForm,
+            777 => IssuedWith,
+            780 => Preceding,
+            785 => Succeeding,
+            786 => DataSource,
+            787 => Other,
+            _ => return None,
+        })
+    }
+
+    /** What the linked record is to the record that has the field **/
+    pub fn hierarchy(&self) -> Hierarchy {
+        match self {
+            LinkingEntryType::MainSeries
+            | LinkingEntryType::SupplementParent
+            | LinkingEntryType::Host => Hierarchy::Parent,
+            LinkingEntryType::Subseries
+            | LinkingEntryType::Supplement
+            | LinkingEntryType::Constituent => Hierarchy::Child,
+            _ => Hierarchy::Peer,
+        }
+    }
+}
+
+/** A linking entry field, 760 to 787 **/
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkingEntry {
+    pub field_type: usize,
+    pub entry_type: LinkingEntryType,
+    // first indicator 0, whether a note should be displayed
+    pub display_note: bool,
+    // second indicator of 780 and 785, e.g. `0` continues or `4` formed by union
+    pub relation_type: Option<u8>,
+    // $i
+    pub relationship: Option<String>,
+    // $a
+    pub main_entry: Option<String>,
+    // $t
+    pub title: Option<String>,
+    // $g
+    pub related_parts: Vec<String>,
+    // $x
+    pub issn: Option<String>,
+    // $z
+    pub isbns: Vec<String>,
+    // $w
+    pub control_numbers: Vec<AuthorityLink>,
+}
+
+impl LinkingEntry {
+    pub fn from_field(field: &RecordField) -> Option<LinkingEntry> {
+        let entry_type = LinkingEntryType::from_tag(field.field_type)?;
+        let first = |code| field.subfield_values(code).next().map(|s| s.to_string());
+        let all = |code| field.subfield_values(code).map(|s| s.to_string()).collect();
+        Some(LinkingEntry {
+            field_type: field.field_type,
+            entry_type,
+            display_note: field.indicator(0) == Some(b'0'),
+            relation_type: match field.field_type {
+                780 | 785 => field.indicator(1).filter(u8::is_ascii_digit),
+                _ => None,
+            },
+            relationship: first(b'i'),
+            main_entry: first(b'a'),
+            title: first(b't'),
+            related_parts: all(b'g'),
+            issn: first(b'x'),
+            isbns: all(b'z'),
+            control_numbers: field
+                .subfield_values(b'w')
+                .map(|v| AuthorityLink::parse(&v))
+                .collect(),
+        })
+    }
+
+    pub fn hierarchy(&self) -> Hierarchy {
+        self.entry_type.hierarchy()
+    }
+}
+
+/** All linking entry fields of the record, in record order **/
+pub fn linking_entries<R: Record + ?Sized>(record: &R) -> Vec<LinkingEntry> {
+    record
+        .field_iter(None)
+        .filter(|f| (760..=787).contains(&f.field_type))
+        .filter_map(|f| LinkingEntry::from_field(&f))
+        .collect()
+}
+
+/**
+ * The form control numbers are compared in: without blanks, and for OCLC
+ * numbers without the `ocm`, `ocn` or `on` prefix and leading zeros
+ */
+pub fn control_number_key(value: &str) -> String {
+    let key: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    match key.strip_prefix("(OCoLC)") {
+        Some(n) => format!(
+            "(OCoLC){}",
+            n.trim_start_matches(|c: char| c.is_alphabetic() || c == '0')
+        ),
+        None => key,
+    }
+}
+
+/**
+ * In-memory index from control numbers to the positions of records in a file,
+ * for resolving `$w`. Records are indexed by 001, by 001 qualified with 003,
+ * e.g. `(DLC)86645473`, and by 035 $a.
+ */
+#[derive(Default)]
+pub struct ControlNumberIndex {
+    keys: HashMap<String, Vec<usize>>,
+    control_numbers: Vec<Option<String>>,
+}
+
+impl ControlNumberIndex {
+    pub fn new() -> ControlNumberIndex {
+        ControlNumberIndex::default()
+    }
+
+    pub fn build<R: Read + Seek>(
+        reader: &mut MarcReader<R>,
+        buffer: &mut [u8],
+    ) -> std::io::Result<ControlNumberIndex> {
+        let mut index = ControlNumberIndex::new();
+        while let Some(batch) = reader.read_batch(buffer)? {
+            for record in batch.records.iter() {
+                index.add_record(record);
+            }
+        }
+        Ok(index)
+    }
+
+    /** Add the record at the next position **/
+    pub fn add_record<R: Record + ?Sized>(&mut self, record: &R) {
+        let position = self.control_numbers.len();
+        let control_number = record
+            .field_iter(Some(1))
+            .next()
+            .map(|f| f.utf8_data_lossy().to_string());
+        if let Some(cn) = &control_number {
+            self.add_key(cn, position);
+            if let Some(org) = record.field_iter(Some(3)).next() {
+                self.add_key(&format!("({}){}", org.utf8_data_lossy(), cn), position);
+            }
+        }
+        for field in record.field_iter(Some(35)) {
+            for value in field.subfield_values(b'a') {
+                self.add_key(&value, position);
+            }
+        }
+        self.control_numbers.push(control_number);
+    }
+
+    fn add_key(&mut self, value: &str, position: usize) {
+        let positions = self.keys.entry(control_number_key(value)).or_default();
+        if !positions.contains(&position) {
+            positions.push(position);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.control_numbers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.control_numbers.is_empty()
+    }
+
+    pub fn control_number(&self, position: usize) -> Option<&str> {
+        self.control_numbers.get(position)?.as_deref()
+    }
+
+    pub fn lookup(&self, value: &str) -> &[usize] {
+        self.keys
+            .get(&control_number_key(value))
+            .map_or(&[], |v| v.as_slice())
+    }
+
+    /** The single record a `$w` refers to, None if there is no or no unique match **/
+    pub fn resolve(&self, link: &AuthorityLink) -> Option<usize> {
+        match self.lookup(&link.to_string()) {
+            [position] => Some(*position),
+            _ => None,
+        }
+    }
+
+    /** The first `$w` of the entry that resolves **/
+    pub fn resolve_entry(&self, entry: &LinkingEntry) -> Option<usize> {
+        entry.control_numbers.iter().find_map(|l| self.resolve(l))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkEdge {
+    pub from: usize,
+    // None if no record in the file matches any $w
+    pub to: Option<usize>,
+    pub field_type: usize,
+    pub entry_type: LinkingEntryType,
+    // the first $w, or the title if there is none
+    pub target: String,
+}
+
+/** Collects records and their linking entries, resolving them once all are known **/
+#[derive(Default)]
+pub struct LinkGraphBuilder {
+    index: ControlNumberIndex,
+    pending: Vec<(usize, LinkingEntry)>,
+}
+
+impl LinkGraphBuilder {
+    pub fn new() -> LinkGraphBuilder {
+        LinkGraphBuilder::default()
+    }
+
+    pub fn add_record<R: Record + ?Sized>(&mut self, record: &R) {
+        let position = self.index.len();
+        self.index.add_record(record);
+        for entry in linking_entries(record) {
+            self.pending.push((position, entry));
+        }
+    }
+
+    pub fn build(self) -> LinkGraph {
+        let index = self.index;
+        let edges = self
+            .pending
+            .into_iter()
+            .map(|(from, entry)| LinkEdge {
+                from,
+                to: index.resolve_entry(&entry),
+                field_type: entry.field_type,
+                entry_type: entry.entry_type,
+                target: entry
+                    .control_numbers
+                    .first()
+                    .map(|l| l.to_string())
+                    .or(entry.title)
+                    .unwrap_or_default(),
+            })
+            .collect();
+        LinkGraph { index, edges }
+    }
+}
+
+/** The linking entry relationships between the records of a file **/
+pub struct LinkGraph {
+    index: ControlNumberIndex,
+    edges: Vec<LinkEdge>,
+}
+
+impl LinkGraph {
+    pub fn build<R: Read + Seek>(
+        reader: &mut MarcReader<R>,
+        buffer: &mut [u8],
+    ) -> std::io::Result<LinkGraph> {
+        let mut builder = LinkGraphBuilder::new();
+        while let Some(batch) = reader.read_batch(buffer)? {
+            for record in batch.records.iter() {
+                builder.add_record(record);
+            }
+        }
+        Ok(builder.build())
+    }
+
+    pub fn index(&self) -> &ControlNumberIndex {
+        &self.index
+    }
+
+    pub fn edges(&self) -> &[LinkEdge] {
+        &self.edges
+    }
+
+    /** Edges whose `$w` match no record in the file **/
+    pub fn unresolved_edges(&self) -> impl Iterator<Item = &LinkEdge> {
+        self.edges.iter().filter(|e| e.to.is_none())
+    }
+
+    fn related(&self, position: usize, wanted: Hierarchy) -> Vec<usize> {
+        let inverse = match wanted {
+            Hierarchy::Parent => Hierarchy::Child,
+            _ => Hierarchy::Parent,
+        };
+        let mut result = Vec::new();
+        for e in self.edges.iter() {
+            let h = e.entry_type.hierarchy();
+            let other = match (e.from == position, e.to == Some(position)) {
+                (true, _) if h == wanted => e.to,
+                (_, true) if h == inverse => Some(e.from),
+                _ => None,
+            };
+            if let Some(o) = other {
+                if o != position && !result.contains(&o) {
+                    result.push(o);
+                }
+            }
+        }
+        result
+    }
+
+    /** Hosts, main series and parents of supplements, from both sides of the link **/
+    pub fn parents(&self, position: usize) -> Vec<usize> {
+        self.related(position, Hierarchy::Parent)
+    }
+
+    /** Constituents, subseries and supplements, from both sides of the link **/
+    pub fn children(&self, position: usize) -> Vec<usize> {
+        self.related(position, Hierarchy::Child)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::linkingentry::*;
+    use crate::marcrecord::MarcReader;
+    use crate::ownedrecord::OwnedRecord;
+    use crate::testutil::{record, SERIAL};
+    use std::io::Cursor;
+
+    fn records() -> Vec<OwnedRecord> {
+        vec![
+            record(SERIAL, &[
+                (1, "h1"),
+                (3, "XX"),
+                (35, "  \x1fa(OCoLC)ocm00012345"),
+                (245, "00\x1faHost journal"),
+                (774, "0 \x1ftAn article\x1fwa1"),
+                (785, "00\x1ftLater journal\x1fw(XX)h9"),
+            ]),
+            record(SERIAL, &[
+                (1, "a1"),
+                (245, "03\x1faAn article"),
+                (
+                    773,
+                    "0 \x1fiIn:\x1ftHost journal\x1fgVol. 1 (1999), p. 1-10\x1fx0317-8471\x1fw(OCoLC)12345",
+                ),
+            ]),
+        ]
+    }
+
+    #[test]
+    fn entries() {
+        let rs = records();
+        let entries = linking_entries(&rs[1]);
+        assert_eq!(entries.len(), 1);
+        let e = &entries[0];
+        assert_eq!(e.entry_type, LinkingEntryType::Host);
+        assert_eq!(e.hierarchy(), Hierarchy::Parent);
+        assert!(e.display_note);
+        assert_eq!(e.relationship.as_deref(), Some("In:"));
+        assert_eq!(e.title.as_deref(), Some("Host journal"));
+        assert_eq!(e.related_parts, ["Vol. 1 (1999), p. 1-10"]);
+        assert_eq!(e.issn.as_deref(), Some("0317-8471"));
+        assert_eq!(e.control_numbers[0].source.as_deref(), Some("OCoLC"));
+        let entries = linking_entries(&rs[0]);
+        assert_eq!(entries[1].relation_type, Some(b'0'));
+        assert_eq!(entries[0].relation_type, None);
+    }
+
+    #[test]
+    fn graph() -> Result<(), String> {
+        let mut data = Vec::new();
+        for r in records() {
+            r.to_marc21(&mut data).map_err(|e| e.to_string())?;
+        }
+        let mut reader = MarcReader::new(Cursor::new(data));
+        let mut buffer = vec![0; 1000];
+        let graph = LinkGraph::build(&mut reader, &mut buffer).map_err(|e| e.to_string())?;
+        assert_eq!(graph.index().lookup("(XX)h1"), [0]);
+        assert_eq!(graph.index().lookup("(OCoLC) 12345"), [0]);
+        assert_eq!(graph.edges().len(), 3);
+        assert_eq!(graph.parents(1), [0]);
+        assert_eq!(graph.children(0), [1]);
+        assert!(graph.parents(0).is_empty());
+        let unresolved: Vec<&str> = graph
+            .unresolved_edges()
+            .map(|e| e.target.as_str())
+            .collect();
+        assert_eq!(unresolved, ["(XX)h9"]);
+        Ok(())
+    }
+}
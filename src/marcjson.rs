@@ -0,0 +1,269 @@
+/*!
+ * Reading MARC-in-JSON, either a JSON array of records or one record per
+ * line (NDJSON). Records are parsed one at a time, also from arrays. After a
+ * syntax error, NDJSON is read on with the next line, while an array ends. The
+ * writer produces NDJSON.
+ *
+ * A record looks like `{"leader": "...", "fields": [{"001": "..."},
+ * {"245": {"ind1": "1", "ind2": "0", "subfields": [{"a": "..."}]}}]}`.
+ */
+use crate::ownedrecord::OwnedRecord;
+use crate::record::*;
+use crate::recordreader::{invalid, parse_tag, set_leader, RecordReader};
+use crate::recordwriter::{check_unicode, RecordWriter};
+use crate::util::escape_json;
+use serde_json::Value;
+use std::io::{BufRead, Write};
+
+pub struct MarcJsonReader<R: BufRead> {
+    reader: R,
+    // whether the input is a JSON array, decided by its first byte
+    array: Option<bool>,
+    // after a syntax error inside an array
+    ended: bool,
+    line: Vec<u8>,
+}
+
+impl<R: BufRead> MarcJsonReader<R> {
+    pub fn new(reader: R) -> MarcJsonReader<R> {
+        MarcJsonReader {
+            reader,
+            array: None,
+            ended: false,
+            line: Vec::new(),
+        }
+    }
+
+    /** Skip whitespace, array brackets and commas between records, false at the end **/
+    fn skip_separators(&mut self) -> std::io::Result<bool> {
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(false);
+            }
+            let n = buf
+                .iter()
+                .take_while(|&&b| b.is_ascii_whitespace() || b == b'[' || b == b']' || b == b',')
+                .count();
+            if self.array.is_none() && buf[..n].contains(&b'[') {
+                self.array = Some(true);
+            }
+            let more = n < buf.len();
+            self.reader.consume(n);
+            if more {
+                self.array.get_or_insert(false);
+                return Ok(true);
+            }
+        }
+    }
+}
+
+fn json_error(e: serde_json::Error) -> std::io::Error {
+    invalid(format!("MARC-in-JSON: {}", e))
+}
+
+fn string<'v>(value: &'v Value, what: &str) -> std::io::Result<&'v str> {
+    value
+        .as_str()
+        .ok_or_else(|| invalid(format!("MARC-in-JSON: {} is not a string", what)))
+}
+
+/** The single key and value of `{"tag": ...}` objects **/
+fn entry<'v>(value: &'v Value, what: &str) -> std::io::Result<(&'v String, &'v Value)> {
+    match value.as_object() {
+        Some(o) if o.len() == 1 => Ok(o.iter().next().unwrap()),
+        _ => Err(invalid(format!(
+            "MARC-in-JSON: {} is not an object with one key",
+            what
+        ))),
+    }
+}
+
+fn indicator(field: &Value, name: &str) -> u8 {
+    field
+        .get(name)
+        .and_then(|i| i.as_str())
+        .and_then(|i| i.bytes().next())
+        .unwrap_or(b' ')
+}
+
+fn to_record(value: &Value) -> std::io::Result<OwnedRecord> {
+    let mut record = OwnedRecord::new();
+    let leader = value
+        .get("leader")
+        .ok_or_else(|| invalid("MARC-in-JSON: record without leader".to_string()))?;
+    set_leader(&mut record, string(leader, "leader")?.as_bytes())?;
+    let fields = value.get("fields").and_then(|f| f.as_array());
+    for field in fields.into_iter().flatten() {
+        let (tag, content) = entry(field, "field")?;
+        let field_type = parse_tag(tag)?;
+        let data = if content.is_string() {
+            string(content, tag)?.as_bytes().to_vec()
+        } else {
+            let mut data = vec![indicator(content, "ind1"), indicator(content, "ind2")];
+            let subfields = content.get("subfields").and_then(|s| s.as_array());
+            for subfield in subfields.into_iter().flatten() {
+                let (code, value) = entry(subfield, "subfield")?;
+                data.push(0x1f);
+                data.extend_from_slice(code.as_bytes());
+                data.extend_from_slice(string(value, code)?.as_bytes());
+            }
+            data
+        };
+        record.add_field(OwnedRecordField { field_type, data });
+    }
+    record.update_len()?;
+    Ok(record)
+}
+
+impl<R: BufRead + Send> RecordReader for MarcJsonReader<R> {
+    fn read_record(&mut self) -> std::io::Result<Option<OwnedRecord>> {
+        if self.ended || !self.skip_separators()? {
+            return Ok(None);
+        }
+        let value: Value = if self.array == Some(true) {
+            // reads exactly up to the end of the object
+            let mut deserializer = serde_json::Deserializer::from_reader(&mut self.reader);
+            match serde::Deserialize::deserialize(&mut deserializer) {
+                Ok(value) => value,
+                Err(e) => {
+                    // the next record cannot be found inside a damaged array
+                    self.ended = true;
+                    return Err(json_error(e));
+                }
+            }
+        } else {
+            // one record per line, a damaged line does not affect the next
+            self.line.clear();
+            self.reader.read_until(b'\n', &mut self.line)?;
+            serde_json::from_slice(&self.line).map_err(json_error)?
+        };
+        to_record(&value).map(Some)
+    }
+}
+
+pub struct MarcJsonWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> MarcJsonWriter<W> {
+    pub fn new(writer: W) -> MarcJsonWriter<W> {
+        MarcJsonWriter { writer }
+    }
+}
+
+fn json_string(data: &[u8]) -> String {
+    format!("\"{}\"", escape_json(&String::from_utf8_lossy(data)))
+}
+
+impl<W: Write> RecordWriter for MarcJsonWriter<W> {
+    fn write_record(&mut self, record: &dyn Record) -> std::io::Result<()> {
+        check_unicode(record, "MARC-in-JSON")?;
+        let mut fields = Vec::new();
+        for field in record.field_iter(None) {
+            if !RecordField::is_data_field_type(field.field_type) {
+                fields.push(format!(
+                    "{{\"{}\":{}}}",
+                    field.tag(),
+                    json_string(field.data)
+                ));
+                continue;
+            }
+            let indicator = |i: usize| json_string(field.data.get(i..i + 1).unwrap_or(b" "));
+            let subfields: Vec<String> = field
+                .subfields()
+                .map(|s| {
+                    format!(
+                        "{{{}:{}}}",
+                        json_string(&[s.code()]),
+                        json_string(s.value())
+                    )
+                })
+                .collect();
+            fields.push(format!(
+                "{{\"{}\":{{\"ind1\":{},\"ind2\":{},\"subfields\":[{}]}}}}",
+                field.tag(),
+                indicator(0),
+                indicator(1),
+                subfields.join(",")
+            ));
+        }
+        writeln!(
+            self.writer,
+            "{{\"leader\":{},\"fields\":[{}]}}",
+            json_string(record.leader()),
+            fields.join(",")
+        )
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::marcjson::*;
+    use crate::recordreader::open_any_reader;
+
+    fn read_all(json: &str) -> Result<Vec<OwnedRecord>, String> {
+        let mut reader = open_any_reader(std::io::Cursor::new(json.as_bytes().to_vec()))
+            .map_err(|e| e.to_string())?;
+        reader
+            .as_mut()
+            .collect::<std::io::Result<_>>()
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn array_and_lines() -> Result<(), String> {
+        let record = r#"{"leader": "00827nz  a2200241nc 4500", "fields": [
+            {"001": "040000028"},
+            {"150": {"ind1": " ", "ind2": " ", "subfields": [{"a": "A 302 D"}]}},
+            {"550": {"ind1": " ", "ind2": " ", "subfields": [{"a": "Integrierte \"Schaltung\""}, {"4": "obal"}]}}
+        ]}"#;
+        let array = read_all(&format!("[\n{},\n{}\n]\n", record, record))?;
+        let ndjson = read_all(&format!(
+            "{}\n{}\n",
+            record.replace('\n', ""),
+            record.replace('\n', "")
+        ))?;
+        assert_eq!(array.len(), 2);
+        assert_eq!(ndjson.len(), 2);
+        for r in array.iter().chain(ndjson.iter()) {
+            let fields: Vec<(usize, String)> = r
+                .field_iter(None)
+                .map(|f| (f.field_type, f.utf8_data().to_string()))
+                .collect();
+            assert_eq!(
+                fields,
+                [
+                    (1, "040000028".to_string()),
+                    (150, "  \x1faA 302 D".to_string()),
+                    (550, "  \x1faIntegrierte \"Schaltung\"\x1f4obal".to_string()),
+                ]
+            );
+        }
+        assert!(read_all(r#"[{"fields": []}]"#).is_err());
+
+        // a damaged line between two good ones
+        let line = record.replace('\n', "");
+        let damaged = format!("{}\n{{\"leader\": \"00827nz\n{}\n", line, line);
+        let mut reader = open_any_reader(std::io::Cursor::new(damaged.into_bytes()))
+            .map_err(|e| e.to_string())?;
+        let results: Vec<_> = reader.as_mut().collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok() && results[1].is_err() && results[2].is_ok());
+        // a damaged array ends
+        let damaged = format!("[{},\n{{\"leader\": 00827}},\n{}]", line, line);
+        let mut reader = open_any_reader(std::io::Cursor::new(damaged.into_bytes()))
+            .map_err(|e| e.to_string())?;
+        let results: Vec<_> = reader.as_mut().collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok() && results[1].is_err());
+        assert!(
+            read_all(r#"{"leader": "00827nz  a2200241nc 4500", "fields": [{"1": "x"}]}"#).is_err()
+        );
+        Ok(())
+    }
+}
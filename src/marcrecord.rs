@@ -6,7 +6,7 @@ use std::io::SeekFrom;
 use crate::ownedrecord::OwnedRecord;
 use crate::record::*;
 
-const MARCHEADER_SIZE: usize = 24;
+pub const MARCHEADER_SIZE: usize = 24;
 
 #[derive(Debug)]
 pub struct MarcHeader<'s> {
@@ -33,11 +33,9 @@ impl<'s> MarcHeader<'s> {
     pub fn record_length(&self) -> usize {
         parse_usize5(&self.header[0..5])
     }
-    pub fn record_type(&self) -> RecordType {
-        match self.header[6] {
-            b'z' => RecordType::Authority,
-            _ => todo!(),
-        }
+    /** None if leader/06 is not a known type of record **/
+    pub fn record_type(&self) -> Option<RecordType> {
+        RecordType::from_byte(self.header[6])
     }
 }
 
@@ -201,7 +199,10 @@ impl<'s> Iterator for MarcRecordFieldIterVec<'s> {
 }
 
 impl<'s> Record for MarcRecord<'s> {
-    fn record_type(&self) -> RecordType {
+    fn leader(&self) -> &[u8] {
+        self.header.header
+    }
+    fn record_type(&self) -> Option<RecordType> {
         self.header().record_type()
     }
     fn field_iter(&self, field_type: Option<usize>) -> Box<dyn Iterator<Item = RecordField> + '_> {
@@ -402,32 +403,344 @@ where
     }
 }
 
+/** What `RecordBuffer::next_record` found **/
+pub(crate) enum Scan {
+    // the record at this range of the buffer
+    Record(std::ops::Range<usize>),
+    // more input is needed
+    More,
+    End,
+}
+
+/**
+ * The state shared by the readers that cut records out of a buffer: the
+ * synchronous and asynchronous stream readers, which fill the buffer from
+ * their input, and `MarcSliceReader`, whose buffer is the whole input.
+ *
+ * After a damaged record, reading continues behind the next record
+ * terminator, so one damaged record gives one error. This includes records
+ * whose length is wrong: a record must not contain a record terminator
+ * before its end, and a record that is cut off by the end of the input is
+ * followed by whatever comes behind its first record terminator.
+ */
+pub(crate) struct RecordBuffer<B> {
+    pub(crate) buffer: B,
+    // the unparsed data is buffer[start..end]
+    start: usize,
+    end: usize,
+    // position of buffer[start] in the input
+    offset: u64,
+    eof: bool,
+    // skip to behind the next record terminator before the next record
+    resync: bool,
+}
+
+impl<B: AsRef<[u8]>> RecordBuffer<B> {
+    /** Position of the next record in the input **/
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.start += len;
+        self.offset += len as u64;
+    }
+
+    /** The next record in the buffer, `Scan::More` if more of the input has to be read first **/
+    pub(crate) fn next_record(&mut self) -> Result<Scan, std::io::Error> {
+        if self.resync {
+            let data = &self.buffer.as_ref()[self.start..self.end];
+            match memchr::memchr(RECORD_TERMINATOR, data) {
+                Some(i) => {
+                    self.consume(i + 1);
+                    self.resync = false;
+                }
+                None => {
+                    self.consume(data.len());
+                    if !self.eof {
+                        return Ok(Scan::More);
+                    }
+                    self.resync = false;
+                }
+            }
+        }
+        let data = &self.buffer.as_ref()[self.start..self.end];
+        match complete_record_length(data, self.offset) {
+            Ok(Some(len)) => {
+                let start = self.start;
+                self.consume(len);
+                Ok(Scan::Record(start..start + len))
+            }
+            Ok(None) if !self.eof => Ok(Scan::More),
+            Ok(None) if data.is_empty() => Ok(Scan::End),
+            Ok(None) => {
+                // the record length may be wrong, the following records may
+                // still be there
+                self.resync = true;
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("truncated record at byte {}", self.offset),
+                ))
+            }
+            Err(e) => {
+                self.resync = true;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<'s> RecordBuffer<&'s [u8]> {
+    pub(crate) fn from_slice(data: &'s [u8]) -> RecordBuffer<&'s [u8]> {
+        RecordBuffer {
+            buffer: data,
+            start: 0,
+            end: data.len(),
+            offset: 0,
+            eof: true,
+            resync: false,
+        }
+    }
+}
+
+impl RecordBuffer<Vec<u8>> {
+    pub(crate) fn new() -> RecordBuffer<Vec<u8>> {
+        RecordBuffer {
+            buffer: Vec::new(),
+            start: 0,
+            end: 0,
+            offset: 0,
+            eof: false,
+            resync: false,
+        }
+    }
+
+    /** Room for at least `min` bytes of input, after moving the unparsed data to the front **/
+    pub(crate) fn spare(&mut self, min: usize) -> &mut [u8] {
+        if self.start > 0 {
+            self.buffer.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        if self.buffer.len() - self.end < min {
+            self.buffer.resize(self.end + min, 0);
+        }
+        &mut self.buffer[self.end..]
+    }
+
+    /** `read` bytes were read into `spare`, 0 at the end of the input **/
+    pub(crate) fn filled(&mut self, read: usize) {
+        self.eof = read == 0;
+        self.end += read;
+    }
+}
+
+/**
+ * The record in `data`, which starts with the leader, as found by
+ * `RecordBuffer::next_record`.
+ */
+pub(crate) fn record_in(data: &[u8]) -> MarcRecord<'_> {
+    MarcRecord::new(
+        MarcHeader::new(&data[..MARCHEADER_SIZE]),
+        &data[MARCHEADER_SIZE..],
+    )
+}
+
+/**
+ * Reads records one by one from any `Read`, without `Seek`, e.g. from a
+ * decompressing reader or a pipe. The records borrow from an internal buffer
+ * that grows to hold the largest record.
+ */
+pub struct MarcStreamReader<R>
+where
+    R: Read,
+{
+    base_reader: R,
+    records: RecordBuffer<Vec<u8>>,
+}
+
+impl<R> MarcStreamReader<R>
+where
+    R: Read,
+{
+    pub fn new(reader: R) -> MarcStreamReader<R> {
+        MarcStreamReader {
+            base_reader: reader,
+            records: RecordBuffer::new(),
+        }
+    }
+
+    /**
+     * The next record, None at the end of the input. After an error, reading
+     * continues behind the next record terminator.
+     */
+    pub fn read_record(&mut self) -> Result<Option<MarcRecord<'_>>, std::io::Error> {
+        loop {
+            match self.records.next_record()? {
+                Scan::Record(range) => {
+                    return Ok(Some(record_in(&self.records.buffer[range])));
+                }
+                Scan::End => return Ok(None),
+                Scan::More => {}
+            }
+            let spare = self.records.spare(64 * 1024);
+            let read = loop {
+                match self.base_reader.read(spare) {
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    r => break r?,
+                }
+            };
+            self.records.filled(read);
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.base_reader
+    }
+}
+
+pub const RECORD_TERMINATOR: u8 = 0x1d;
+
+/**
+ * The length of the record at the start of `data` if all of it is there,
+ * None if more data is needed. Fails if the leader's record length is not a
+ * number or too small, the record does not end with a record terminator,
+ * contains one before its end or its directory is damaged, see
+ * `check_directory`. `offset` is the position
+ * of `data` in the input, for error messages.
+ */
+pub fn complete_record_length(data: &[u8], offset: u64) -> Result<Option<usize>, std::io::Error> {
+    use std::io::{Error, ErrorKind};
+    if data.len() < MARCHEADER_SIZE {
+        return Ok(None);
+    }
+    if !data[..5].iter().all(u8::is_ascii_digit) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid record length at byte {}", offset),
+        ));
+    }
+    let record_length = parse_usize5(&data[..5]);
+    if record_length <= MARCHEADER_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("record at byte {} is too short", offset),
+        ));
+    }
+    if record_length > data.len() {
+        return Ok(None);
+    }
+    if data[record_length - 1] != RECORD_TERMINATOR {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "record at byte {} does not end with a record terminator",
+                offset
+            ),
+        ));
+    }
+    if memchr::memchr(RECORD_TERMINATOR, &data[..record_length - 1]).is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "record at byte {} contains a record terminator before its end",
+                offset
+            ),
+        ));
+    }
+    check_directory(&data[..record_length], offset)?;
+    Ok(Some(record_length))
+}
+
+/**
+ * Check that the directory of the complete record in `record` consists of
+ * 12 digit entries ended by a field terminator, and that every field lies
+ * within the record, so that reading the fields cannot fail.
+ */
+fn check_directory(record: &[u8], offset: u64) -> Result<(), std::io::Error> {
+    let damaged = |what: String| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("record at byte {} {}", offset, what),
+        )
+    };
+    let data = &record[MARCHEADER_SIZE..];
+    let directory_end = end_of_entry_position(data)
+        .ok_or_else(|| damaged("has no directory terminator".to_string()))?;
+    let directory = &data[..directory_end];
+    if !directory.len().is_multiple_of(12) || !directory.iter().all(u8::is_ascii_digit) {
+        return Err(damaged("has a malformed directory".to_string()));
+    }
+    // the fields start behind the field terminator of the directory
+    let payload_len = data.len() - directory_end;
+    for entry in directory.chunks(12) {
+        let len = parse_usize4(&entry[3..7]);
+        let start = parse_usize5(&entry[7..12]);
+        if len == 0 || start + len > payload_len {
+            return Err(damaged(format!(
+                "has a field {} outside of the record",
+                String::from_utf8_lossy(&entry[..3])
+            )));
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Iterates over the records of a byte slice, e.g. a memory-mapped file,
+ * without copying. The records borrow from the slice. After a damaged
+ * record, iteration continues behind the next record terminator.
+ */
+pub struct MarcSliceReader<'s> {
+    records: RecordBuffer<&'s [u8]>,
+}
+
+impl<'s> MarcSliceReader<'s> {
+    pub fn new(data: &'s [u8]) -> MarcSliceReader<'s> {
+        MarcSliceReader {
+            records: RecordBuffer::from_slice(data),
+        }
+    }
+
+    /** Byte offset of the next record **/
+    pub fn offset(&self) -> usize {
+        self.records.offset() as usize
+    }
+
+    /** The record at `offset`, checking its length and terminator **/
+    pub fn record_at(data: &'s [u8], offset: usize) -> Result<MarcRecord<'s>, std::io::Error> {
+        let rest = &data[offset.min(data.len())..];
+        match complete_record_length(rest, offset as u64)? {
+            Some(record_length) => Ok(record_in(&rest[..record_length])),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("truncated record at byte {}", offset),
+            )),
+        }
+    }
+}
+
+impl<'s> Iterator for MarcSliceReader<'s> {
+    type Item = Result<MarcRecord<'s>, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data: &'s [u8] = self.records.buffer;
+        match self.records.next_record() {
+            Ok(Scan::Record(range)) => Some(Ok(record_in(&data[range]))),
+            // the whole input is in the buffer
+            Ok(Scan::More) | Ok(Scan::End) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::marcrecord::*;
     use crate::record::*;
+    use crate::testutil::STR;
     use std::io::BufReader;
     use std::io::Cursor;
-    static STR : &[u8]= "00827nz  a2200241nc 4500\
-001001000000\
-003000700010\
-005001700017\
-008004100034\
-024005100075\
-035002200126\
-035002200148\
-035002900170\
-040004000199\
-042000900239\
-065001600248\
-075001400264\
-079000900278\
-083004200287\
-150001200329\
-550019200341\
-670001200533\
-913004000545\
-040000028DE-10120100106125650.0880701n||azznnbabn           | ana    |c7 a4000002-30http://d-nb.info/gnd/4000002-32gnd  a(DE-101)040000028  a(DE-588)4000002-3  z(DE-588c)4000002-39v:zg  aDE-101cDE-1019r:DE-101bgerd0832  agnd1  a31.9b2sswd  bs2gndgen  agqs04a621.3815379d:29t:2010-01-06223/ger  aA 302 D  0(DE-101)0402724270(DE-588)4027242-40https://d-nb.info/gnd/4027242-4aIntegrierte Schaltung4obal4https://d-nb.info/standards/elementset/gnd#broaderTermGeneralwriOberbegriff allgemein  aVorlage  SswdisaA 302 D0(DE-588c)4000002-3".as_bytes();
 
     #[test]
     fn read_one_buffered() -> Result<(), String> {
@@ -551,4 +864,74 @@ mod tests {
             _ => Err("something bad".to_string()),
         }
     }
+
+    #[test]
+    fn stream_resync() -> Result<(), String> {
+        // a damaged record between two good ones
+        let mut data = STR.to_vec();
+        data.extend_from_slice(b"00100nz  a2200000nc 4500garbage\x1d");
+        data.extend_from_slice(STR);
+        let mut reader = MarcStreamReader::new(Cursor::new(data));
+        assert!(reader.read_record().map_err(|e| e.to_string())?.is_some());
+        assert!(reader.read_record().is_err());
+        let record = reader.read_record().map_err(|e| e.to_string())?;
+        assert_eq!(
+            record.ok_or("no record")?.record_type(),
+            Some(RecordType::Authority)
+        );
+        assert!(reader.read_record().map_err(|e| e.to_string())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn slice_resync() -> Result<(), String> {
+        let mut data = STR.to_vec();
+        data.extend_from_slice(b"00100nz  a2200000nc 4500garbage\x1d");
+        data.extend_from_slice(STR);
+        data.extend_from_slice(b"00827nz");
+        let results: Vec<_> = MarcSliceReader::new(&data).collect();
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok() && results[2].is_ok());
+        let err = results[3].as_ref().err().ok_or("no error")?;
+        assert!(err.to_string().contains("truncated"), "{}", err);
+        assert!(results[1].is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stream_wrong_length() -> Result<(), String> {
+        let damaged = |length: usize| {
+            let mut data = STR.to_vec();
+            data.extend_from_slice(
+                format!("{:05}nz  a2200000nc 4500garbage\x1d", length).as_bytes(),
+            );
+            data.extend_from_slice(STR);
+            data.extend_from_slice(STR);
+            data
+        };
+        // longer than the rest of the input, and ending at the end of the next record
+        for data in [damaged(99999), damaged(32 + STR.len())] {
+            let mut reader = MarcStreamReader::new(Cursor::new(data));
+            assert!(reader.read_record().map_err(|e| e.to_string())?.is_some());
+            assert!(reader.read_record().is_err());
+            for _ in 0..2 {
+                let record = reader.read_record().map_err(|e| e.to_string())?;
+                assert_eq!(record.ok_or("no record")?.record_length(), STR.len());
+            }
+            assert!(reader.read_record().map_err(|e| e.to_string())?.is_none());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_record_type() -> Result<(), String> {
+        let mut data = STR.to_vec();
+        data[6] = b'#';
+        let mut reader = MarcStreamReader::new(Cursor::new(data));
+        let record = reader.read_record().map_err(|e| e.to_string())?;
+        let record = record.ok_or("no record")?;
+        assert_eq!(record.record_type(), None);
+        assert_eq!(record.to_owned().record_type(), None);
+        Ok(())
+    }
 }
@@ -0,0 +1,329 @@
+/*!
+ * Reading MARCXML, with or without the `marc:` namespace prefix, and writing
+ * it without prefix.
+ */
+use crate::ownedrecord::OwnedRecord;
+use crate::record::*;
+use crate::recordreader::{invalid, parse_tag, set_leader, RecordReader};
+use crate::recordwriter::{check_unicode, RecordWriter};
+use crate::util::escape_xml;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::io::{BufRead, Write};
+
+pub struct MarcXmlReader<R: BufRead> {
+    reader: Reader<R>,
+    buffer: Vec<u8>,
+    // set after a syntax error, the rest of the document cannot be parsed
+    failed: bool,
+    // set after another error inside a record, the rest of it is skipped
+    skip_record: bool,
+}
+
+fn xml_error(e: quick_xml::Error) -> std::io::Error {
+    invalid(format!("MARCXML: {}", e))
+}
+
+fn attribute(element: &BytesStart, name: &str) -> std::io::Result<String> {
+    match element.try_get_attribute(name).map_err(xml_error)? {
+        Some(a) => Ok(a.unescape_value().map_err(xml_error)?.to_string()),
+        None => Err(invalid(format!(
+            "MARCXML: {} without {}",
+            String::from_utf8_lossy(element.local_name().as_ref()),
+            name
+        ))),
+    }
+}
+
+/** An indicator attribute, a blank if it is missing or empty **/
+fn indicator(element: &BytesStart, name: &str) -> u8 {
+    attribute(element, name)
+        .ok()
+        .and_then(|i| i.bytes().next())
+        .unwrap_or(b' ')
+}
+
+impl<R: BufRead> MarcXmlReader<R> {
+    pub fn new(reader: R) -> MarcXmlReader<R> {
+        MarcXmlReader {
+            reader: Reader::from_reader(reader),
+            buffer: Vec::new(),
+            failed: false,
+            skip_record: false,
+        }
+    }
+
+    /** Skip to behind the end of the current record **/
+    fn skip_record(&mut self) -> std::io::Result<()> {
+        while self.skip_record {
+            self.buffer.clear();
+            let event = self.reader.read_event_into(&mut self.buffer);
+            self.failed = event.is_err();
+            match event.map_err(xml_error)? {
+                Event::End(e) if e.local_name().as_ref() == b"record" => self.skip_record = false,
+                Event::Eof => self.skip_record = false,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead + Send> RecordReader for MarcXmlReader<R> {
+    fn read_record(&mut self) -> std::io::Result<Option<OwnedRecord>> {
+        if self.failed {
+            return Ok(None);
+        }
+        self.skip_record()?;
+        let mut record: Option<OwnedRecord> = None;
+        let result = self.parse_record(&mut record);
+        // skip the rest of a damaged record, so that it gives only one error
+        self.skip_record = result.is_err() && record.is_some() && !self.failed;
+        result
+    }
+}
+
+impl<R: BufRead> MarcXmlReader<R> {
+    /** The next record, `record` is the one being read **/
+    fn parse_record(
+        &mut self,
+        record: &mut Option<OwnedRecord>,
+    ) -> std::io::Result<Option<OwnedRecord>> {
+        // the field being read and the element whose text is collected
+        let mut field: Option<OwnedRecordField> = None;
+        let mut text: Option<String> = None;
+        loop {
+            self.buffer.clear();
+            let event = self.reader.read_event_into(&mut self.buffer);
+            self.failed = event.is_err();
+            match event.map_err(xml_error)? {
+                Event::Start(e) => match e.local_name().as_ref() {
+                    b"record" => *record = Some(OwnedRecord::new()),
+                    b"leader" => text = Some(String::new()),
+                    b"controlfield" => {
+                        field = Some(OwnedRecordField {
+                            field_type: parse_tag(&attribute(&e, "tag")?)?,
+                            data: Vec::new(),
+                        });
+                        text = Some(String::new());
+                    }
+                    b"datafield" => {
+                        field = Some(OwnedRecordField {
+                            field_type: parse_tag(&attribute(&e, "tag")?)?,
+                            data: vec![indicator(&e, "ind1"), indicator(&e, "ind2")],
+                        })
+                    }
+                    b"subfield" => {
+                        let code = attribute(&e, "code")?;
+                        let f = field.as_mut().ok_or_else(|| {
+                            invalid("MARCXML: subfield outside of a datafield".to_string())
+                        })?;
+                        f.data.push(0x1f);
+                        f.data.extend_from_slice(code.as_bytes());
+                        text = Some(String::new());
+                    }
+                    _ => {}
+                },
+                Event::Empty(e) if e.local_name().as_ref() == b"datafield" => {
+                    if let Some(r) = record.as_mut() {
+                        r.add_field(OwnedRecordField {
+                            field_type: parse_tag(&attribute(&e, "tag")?)?,
+                            data: vec![indicator(&e, "ind1"), indicator(&e, "ind2")],
+                        });
+                    }
+                }
+                Event::Text(t) => {
+                    if let Some(s) = text.as_mut() {
+                        s.push_str(&t.unescape().map_err(xml_error)?);
+                    }
+                }
+                Event::CData(t) => {
+                    if let Some(s) = text.as_mut() {
+                        s.push_str(&String::from_utf8_lossy(&t));
+                    }
+                }
+                Event::End(e) => match e.local_name().as_ref() {
+                    b"record" => {
+                        let mut r = record
+                            .take()
+                            .ok_or_else(|| invalid("MARCXML: unbalanced record".to_string()))?;
+                        r.update_len()?;
+                        return Ok(Some(r));
+                    }
+                    b"leader" => {
+                        let leader = text.take().unwrap_or_default();
+                        if let Some(r) = record.as_mut() {
+                            set_leader(r, leader.as_bytes())?;
+                        }
+                    }
+                    b"controlfield" => {
+                        if let (Some(mut f), Some(r)) = (field.take(), record.as_mut()) {
+                            f.data = text.take().unwrap_or_default().into_bytes();
+                            r.add_field(f);
+                        }
+                    }
+                    b"subfield" => {
+                        if let Some(f) = field.as_mut() {
+                            f.data
+                                .extend_from_slice(text.take().unwrap_or_default().as_bytes());
+                        }
+                    }
+                    b"datafield" => {
+                        if let (Some(f), Some(r)) = (field.take(), record.as_mut()) {
+                            r.add_field(f);
+                        }
+                    }
+                    _ => {}
+                },
+                Event::Eof => {
+                    if record.is_some() {
+                        return Err(invalid("MARCXML: truncated record".to_string()));
+                    }
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+pub struct MarcXmlWriter<W: Write> {
+    writer: W,
+    started: bool,
+}
+
+impl<W: Write> MarcXmlWriter<W> {
+    pub fn new(writer: W) -> MarcXmlWriter<W> {
+        MarcXmlWriter {
+            writer,
+            started: false,
+        }
+    }
+
+    fn start(&mut self) -> std::io::Result<()> {
+        if !self.started {
+            self.started = true;
+            writeln!(self.writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+            writeln!(
+                self.writer,
+                "<collection xmlns=\"http://www.loc.gov/MARC21/slim\">"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn text(data: &[u8]) -> String {
+    escape_xml(&String::from_utf8_lossy(data))
+}
+
+impl<W: Write> RecordWriter for MarcXmlWriter<W> {
+    fn write_record(&mut self, record: &dyn Record) -> std::io::Result<()> {
+        check_unicode(record, "MARCXML")?;
+        self.start()?;
+        writeln!(self.writer, "  <record>")?;
+        writeln!(
+            self.writer,
+            "    <leader>{}</leader>",
+            text(record.leader())
+        )?;
+        for field in record.field_iter(None) {
+            if !RecordField::is_data_field_type(field.field_type) {
+                writeln!(
+                    self.writer,
+                    "    <controlfield tag=\"{}\">{}</controlfield>",
+                    field.tag(),
+                    text(field.data)
+                )?;
+                continue;
+            }
+            let indicator = |i: usize| text(field.data.get(i..i + 1).unwrap_or(b" "));
+            writeln!(
+                self.writer,
+                "    <datafield tag=\"{}\" ind1=\"{}\" ind2=\"{}\">",
+                field.tag(),
+                indicator(0),
+                indicator(1)
+            )?;
+            for subfield in field.subfields() {
+                writeln!(
+                    self.writer,
+                    "      <subfield code=\"{}\">{}</subfield>",
+                    text(&[subfield.code()]),
+                    text(subfield.value())
+                )?;
+            }
+            writeln!(self.writer, "    </datafield>")?;
+        }
+        writeln!(self.writer, "  </record>")
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.start()?;
+        writeln!(self.writer, "</collection>")?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::marcxml::*;
+    use crate::recordreader::open_any_reader;
+
+    #[test]
+    fn read() -> Result<(), String> {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<marc:collection xmlns:marc="http://www.loc.gov/MARC21/slim">
+  <marc:record type="Authority">
+    <marc:leader>00827nz  a2200241nc 4500</marc:leader>
+    <marc:controlfield tag="001">040000028</marc:controlfield>
+    <marc:datafield tag="150" ind1=" " ind2=" ">
+      <marc:subfield code="a">A 302 D</marc:subfield>
+    </marc:datafield>
+    <marc:datafield tag="550" ind1=" " ind2=" ">
+      <marc:subfield code="a">Integrierte Schaltung &amp; Co</marc:subfield>
+      <marc:subfield code="4">obal</marc:subfield>
+    </marc:datafield>
+  </marc:record>
+  <marc:record>
+    <marc:leader>00000nz  a2200000nc 4500</marc:leader>
+    <marc:controlfield tag="001">040000036</marc:controlfield>
+  </marc:record>
+</marc:collection>"#;
+        let mut reader = open_any_reader(std::io::Cursor::new(xml.as_bytes().to_vec()))
+            .map_err(|e| e.to_string())?;
+        let records: Vec<OwnedRecord> = reader
+            .as_mut()
+            .collect::<std::io::Result<_>>()
+            .map_err(|e| e.to_string())?;
+        assert_eq!(records.len(), 2);
+        let fields: Vec<(usize, String)> = records[0]
+            .field_iter(None)
+            .map(|f| (f.field_type, f.utf8_data().to_string()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                (1, "040000028".to_string()),
+                (150, "  \x1faA 302 D".to_string()),
+                (
+                    550,
+                    "  \x1faIntegrierte Schaltung & Co\x1f4obal".to_string()
+                ),
+            ]
+        );
+        assert_eq!(&records[0].header[5..12], b"nz  a22");
+        assert_eq!(&records[0].header[17..], b"nc 4500");
+
+        let mut reader = MarcXmlReader::new("<record><leader>short</leader></record>".as_bytes());
+        assert!(reader.read_record().is_err());
+
+        // a damaged record gives one error, reading goes on with the next record
+        let xml = xml.replacen(r#"tag="150""#, r#"tag="15""#, 1);
+        let mut reader = MarcXmlReader::new(xml.as_bytes());
+        assert!(reader.read_record().is_err());
+        assert!(reader.read_record().map_err(|e| e.to_string())?.is_some());
+        assert!(reader.read_record().map_err(|e| e.to_string())?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+/*!
+ * Merging duplicate records into one master record.
+ *
+ * The record with the highest priority becomes the base. The other records
+ * are then applied in order of decreasing priority, each tag following its
+ * `MergeRule`. Which record contributed what can be recorded in a local field.
+ * `OwnedRecord::merge_from` applies a single record to another.
+ */
+use crate::naco::normalize_field;
+use crate::ownedrecord::OwnedRecord;
+use crate::record::*;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeRule {
+    // add the fields only if the merged record has none with this tag
+    Keep,
+    // replace the fields of the base with those of the first record that has the tag
+    Replace,
+    // add the fields that are not yet there, compared after NACO normalization
+    AppendUnique,
+    // add all fields
+    AppendAll,
+}
+
+impl MergeRule {
+    pub fn from_name(s: &str) -> Option<MergeRule> {
+        match s {
+            "keep" => Some(MergeRule::Keep),
+            "replace" => Some(MergeRule::Replace),
+            "append-unique" => Some(MergeRule::AppendUnique),
+            "append-all" => Some(MergeRule::AppendAll),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeConfig {
+    // for tags without a rule of their own
+    pub default_rule: MergeRule,
+    pub rules: HashMap<usize, MergeRule>,
+    /**
+     * Local field recording the provenance, one per merged record: `$a` the
+     * record's `(003)001`, `$b` `base` or `merged` and a `$t` for every tag
+     * it contributed
+     */
+    pub provenance_field: Option<usize>,
+}
+
+impl Default for MergeConfig {
+    fn default() -> MergeConfig {
+        MergeConfig {
+            default_rule: MergeRule::Keep,
+            rules: HashMap::new(),
+            provenance_field: None,
+        }
+    }
+}
+
+impl MergeConfig {
+    pub fn with_rule(mut self, field_type: usize, rule: MergeRule) -> MergeConfig {
+        self.rules.insert(field_type, rule);
+        self
+    }
+
+    pub fn with_provenance_field(mut self, field_type: usize) -> MergeConfig {
+        self.provenance_field = Some(field_type);
+        self
+    }
+
+    pub fn rule(&self, field_type: usize) -> MergeRule {
+        self.rules
+            .get(&field_type)
+            .cloned()
+            .unwrap_or(self.default_rule)
+    }
+}
+
+/**
+ * Priority by encoding level, leader/17: full level records first, then the
+ * OCLC and LC levels down to prepublication and unknown
+ */
+pub fn encoding_level_priority(record: &OwnedRecord) -> i64 {
+    match record.header[17] {
+        b' ' => 10,
+        b'1' | b'I' => 9,
+        b'4' | b'L' => 8,
+        b'7' | b'K' => 6,
+        b'2' | b'M' => 5,
+        b'5' => 3,
+        b'3' => 2,
+        b'8' | b'J' => 1,
+        _ => 0,
+    }
+}
+
+/**
+ * Priority by source, the 003 or else 040 $a. Sources listed earlier rank
+ * higher, unlisted sources lowest.
+ */
+pub fn source_priority<'a>(sources: &'a [&'a str]) -> impl Fn(&OwnedRecord) -> i64 + 'a {
+    move |record: &OwnedRecord| {
+        let source = record
+            .field_iter(Some(3))
+            .next()
+            .map(|f| f.utf8_data_lossy().to_string())
+            .or_else(|| {
+                record
+                    .field_iter(Some(40))
+                    .next()
+                    .and_then(|f| f.subfield_values(b'a').next().map(|s| s.to_string()))
+            });
+        match source.and_then(|s| sources.iter().position(|&p| p == s)) {
+            Some(i) => (sources.len() - i) as i64,
+            None => 0,
+        }
+    }
+}
+
+/** `(003)001`, or just the 001 **/
+fn record_id(record: &OwnedRecord) -> String {
+    let control_number = record
+        .field_iter(Some(1))
+        .next()
+        .map(|f| f.utf8_data_lossy().to_string())
+        .unwrap_or_default();
+    match record.field_iter(Some(3)).next() {
+        Some(org) => format!("({}){}", org.utf8_data_lossy(), control_number),
+        None => control_number,
+    }
+}
+
+/** What makes two fields the same for `AppendUnique` **/
+fn field_key(field: &RecordField, record_type: RecordType) -> (Vec<u8>, String) {
+    if !RecordField::is_data_field_type(field.field_type) {
+        return (field.data.to_vec(), String::new());
+    }
+    (
+        field.data[..2.min(field.data.len())].to_vec(),
+        normalize_field(field, record_type),
+    )
+}
+
+impl OwnedRecord {
+    /**
+     * Apply the fields of `other` following the rules of `config`, with this
+     * record as the base. The provenance field is neither copied nor written.
+     * Returns the tags `other` contributed, fails if the record no longer fits
+     * into ISO 2709.
+     */
+    pub fn merge_from(
+        &mut self,
+        other: &OwnedRecord,
+        config: &MergeConfig,
+    ) -> std::io::Result<Vec<usize>> {
+        // unknown record types are treated as bibliographic
+        let record_type = self.record_type().unwrap_or(RecordType::LanguageMaterial);
+        let mut contributed: Vec<usize> = Vec::new();
+        // records read from files need not be in tag order
+        let mut tags: Vec<usize> = other.field_types.clone();
+        tags.sort_unstable();
+        tags.dedup();
+        for tag in tags {
+            if Some(tag) == config.provenance_field {
+                continue;
+            }
+            let incoming: Vec<RecordField> = other.field_iter(Some(tag)).collect();
+            let added = match config.rule(tag) {
+                MergeRule::Keep if self.field_iter(Some(tag)).next().is_some() => Vec::new(),
+                MergeRule::Replace => {
+                    self.remove_fields(tag)?;
+                    incoming
+                }
+                MergeRule::AppendUnique => {
+                    let mut keys: Vec<(Vec<u8>, String)> = self
+                        .field_iter(Some(tag))
+                        .map(|f| field_key(&f, record_type))
+                        .collect();
+                    incoming
+                        .into_iter()
+                        .filter(|f| {
+                            let key = field_key(f, record_type);
+                            let new = !keys.contains(&key);
+                            if new {
+                                keys.push(key);
+                            }
+                            new
+                        })
+                        .collect()
+                }
+                MergeRule::Keep | MergeRule::AppendAll => incoming,
+            };
+            if !added.is_empty() {
+                contributed.push(tag);
+            }
+            for f in added {
+                self.insert_field(f.to_owned())?;
+            }
+        }
+        Ok(contributed)
+    }
+}
+
+/**
+ * Merge the records into one. None if there are no records. Ties in priority
+ * keep the order of `records`. Fails if the merged record does not fit into
+ * ISO 2709.
+ */
+pub fn merge<F>(
+    records: &[OwnedRecord],
+    priority: F,
+    config: &MergeConfig,
+) -> std::io::Result<Option<OwnedRecord>>
+where
+    F: Fn(&OwnedRecord) -> i64,
+{
+    let mut order: Vec<usize> = (0..records.len()).collect();
+    // stable, so ties keep their order
+    order.sort_by_key(|&i| -priority(&records[i]));
+    let base = match order.first() {
+        Some(&i) => &records[i],
+        None => return Ok(None),
+    };
+    let mut merged = base.clone();
+    if let Some(tag) = config.provenance_field {
+        merged.remove_fields(tag)?;
+    }
+    // only the first record with a tag replaces it, the later ones keep it
+    let mut config = config.clone();
+    let mut provenance: Vec<(String, Vec<usize>)> = vec![(record_id(base), Vec::new())];
+    for &i in order[1..].iter() {
+        let contributed = merged.merge_from(&records[i], &config)?;
+        for &tag in contributed.iter() {
+            if config.rule(tag) == MergeRule::Replace {
+                config.rules.insert(tag, MergeRule::Keep);
+            }
+        }
+        provenance.push((record_id(&records[i]), contributed));
+    }
+    if let Some(tag) = config.provenance_field {
+        for (n, (id, tags)) in provenance.into_iter().enumerate() {
+            let mut data = b"  \x1fa".to_vec();
+            data.extend_from_slice(id.as_bytes());
+            data.extend_from_slice(if n == 0 { b"\x1fbbase" } else { b"\x1fbmerged" });
+            for t in tags {
+                data.extend_from_slice(format!("\x1ft{:03}", t).as_bytes());
+            }
+            merged.insert_field(OwnedRecordField {
+                field_type: tag,
+                data,
+            })?;
+        }
+    }
+    Ok(Some(merged))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::merge::*;
+    use crate::testutil::record;
+
+    fn records() -> Vec<OwnedRecord> {
+        vec![
+            record(
+                b"00000nam a2200000 i 4500",
+                &[
+                    (1, "b1"),
+                    (3, "XX"),
+                    (20, "  \x1fa9783161484100"),
+                    (245, "10\x1faHandbook of things"),
+                    (650, " 0\x1faThings."),
+                    (856, "40\x1fuhttp://example.org/b1"),
+                ],
+            ),
+            record(
+                b"00000nam a2200000 i 4500",
+                &[
+                    (1, "a1"),
+                    (3, "YY"),
+                    (20, "  \x1fa316148410X"),
+                    (245, "10\x1faThe handbook of things :\x1fba guide"),
+                    (300, "  \x1fa350 p."),
+                    (650, " 0\x1faThings"),
+                    (650, " 0\x1faGadgets."),
+                    (856, "40\x1fuhttp://example.org/a1"),
+                ],
+            ),
+        ]
+    }
+
+    fn values(r: &OwnedRecord, field_type: usize) -> Vec<String> {
+        r.field_iter(Some(field_type))
+            .map(|f| f.utf8_data().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn priorities() {
+        let mut rs = records();
+        assert_eq!(encoding_level_priority(&rs[0]), 10);
+        rs[0].header[17] = b'3';
+        assert_eq!(encoding_level_priority(&rs[0]), 2);
+        rs[0].header[17] = b'u';
+        assert_eq!(encoding_level_priority(&rs[0]), 0);
+        let p = source_priority(&["YY", "XX"]);
+        assert_eq!((p(&rs[0]), p(&rs[1])), (1, 2));
+    }
+
+    #[test]
+    fn merge_rules() {
+        let rs = records();
+        let config = MergeConfig::default()
+            .with_rule(20, MergeRule::AppendAll)
+            .with_rule(245, MergeRule::Replace)
+            .with_rule(650, MergeRule::AppendUnique)
+            .with_provenance_field(995);
+        let sources = ["XX", "YY"];
+        let merged = merge(&rs, source_priority(&sources), &config)
+            .unwrap()
+            .unwrap();
+        assert_eq!(values(&merged, 1), ["b1"]);
+        assert_eq!(values(&merged, 20).len(), 2);
+        assert_eq!(
+            values(&merged, 245),
+            ["10\x1faThe handbook of things :\x1fba guide"]
+        );
+        assert_eq!(values(&merged, 300), ["  \x1fa350 p."]);
+        assert_eq!(values(&merged, 650), [" 0\x1faThings.", " 0\x1faGadgets."]);
+        assert_eq!(values(&merged, 856), ["40\x1fuhttp://example.org/b1"]);
+        assert_eq!(
+            values(&merged, 995),
+            [
+                "  \x1fa(XX)b1\x1fbbase",
+                "  \x1fa(YY)a1\x1fbmerged\x1ft020\x1ft245\x1ft300\x1ft650"
+            ]
+        );
+        let mut out = Vec::new();
+        merged.to_marc21(&mut out).unwrap();
+        assert_eq!(
+            crate::util::parse_usize(&out[..5]),
+            out.len(),
+            "record length is updated"
+        );
+        assert!(merge(&[], encoding_level_priority, &config)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn merge_from() -> Result<(), String> {
+        let rs = records();
+        let config = MergeConfig::default()
+            .with_rule(245, MergeRule::Replace)
+            .with_rule(650, MergeRule::AppendUnique);
+        let mut merged = rs[0].clone();
+        let contributed = merged
+            .merge_from(&rs[1], &config)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(contributed, [245, 300, 650]);
+        assert_eq!(
+            values(&merged, 245),
+            ["10\x1faThe handbook of things :\x1fba guide"]
+        );
+        assert_eq!(values(&merged, 650), [" 0\x1faThings.", " 0\x1faGadgets."]);
+        // the second time only Replace changes the record again
+        let contributed = merged
+            .merge_from(&rs[1], &config)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(contributed, [245]);
+        assert_eq!(values(&merged, 245).len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn unsorted_tags() {
+        let leader = b"00000nam a2200000 i 4500";
+        let base = record(leader, &[(1, "b1"), (3, "XX")]);
+        let mut other = record(leader, &[(1, "a1"), (3, "YY")]);
+        for (field_type, data) in [
+            (35, "  \x1fa(OCoLC)1"),
+            (40, "  \x1faYY"),
+            (35, "  \x1fa(OCoLC)2"),
+        ] {
+            other.add_field(OwnedRecordField {
+                field_type,
+                data: data.as_bytes().to_vec(),
+            });
+        }
+        let config = MergeConfig::default()
+            .with_rule(35, MergeRule::AppendAll)
+            .with_provenance_field(995);
+        let merged = merge(&[base, other], source_priority(&["XX", "YY"]), &config)
+            .unwrap()
+            .unwrap();
+        assert_eq!(values(&merged, 35), ["  \x1fa(OCoLC)1", "  \x1fa(OCoLC)2"]);
+        assert_eq!(
+            values(&merged, 995)[1],
+            "  \x1fa(YY)a1\x1fbmerged\x1ft035\x1ft040"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+/*!
+ * Zero-copy reading of local files through a memory mapping.
+ *
+ * Records returned by `MmapMarcReader` borrow straight from the mapping, so
+ * nothing is copied or shifted around as with `MarcReader::read_batch`. The
+ * mapped file must not be changed by other processes while it is mapped.
+ */
+use crate::marcrecord::{MarcRecord, MarcSliceReader};
+use crate::offsetindex::{KeyField, OffsetIndex};
+use memmap2::Mmap;
+use std::fs::File;
+use std::path::Path;
+
+pub struct MmapMarcReader {
+    mmap: Mmap,
+}
+
+impl MmapMarcReader {
+    pub fn open(path: &Path) -> std::io::Result<MmapMarcReader> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read-only, truncating or modifying the file
+        // while it is mapped is undefined behaviour and has to be avoided by
+        // the caller
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(MmapMarcReader { mmap })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.mmap
+    }
+
+    /** All records in file order **/
+    pub fn records(&self) -> MarcSliceReader<'_> {
+        MarcSliceReader::new(&self.mmap)
+    }
+
+    /** The record starting at byte `offset` **/
+    pub fn record_at(&self, offset: usize) -> std::io::Result<MarcRecord<'_>> {
+        MarcSliceReader::record_at(&self.mmap, offset)
+    }
+
+    /** The record at `position` of the index, None if there is no such position **/
+    pub fn get(
+        &self,
+        index: &OffsetIndex,
+        position: usize,
+    ) -> std::io::Result<Option<MarcRecord<'_>>> {
+        let (offset, len) = match index.location(position) {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        let record = self.record_at(offset as usize)?;
+        if record.record_length() != len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "record {} at offset {} does not match the index",
+                    position, offset
+                ),
+            ));
+        }
+        Ok(Some(record))
+    }
+
+    /** The first record with this key **/
+    pub fn get_by_key(
+        &self,
+        index: &OffsetIndex,
+        key_field: KeyField,
+        key: &str,
+    ) -> std::io::Result<Option<MarcRecord<'_>>> {
+        match index.lookup(key_field, key).first() {
+            Some(&position) => self.get(index, position),
+            None => Ok(None),
+        }
+    }
+
+    /** Index the mapped file, like `OffsetIndex::build` but without reading it again **/
+    pub fn build_index(&self, key_fields: &[KeyField]) -> std::io::Result<OffsetIndex> {
+        let mut index = OffsetIndex::new(key_fields);
+        for record in self.records() {
+            index.add_record(&record?);
+        }
+        index.finish();
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mmapreader::*;
+    use crate::record::*;
+    use crate::testutil::{record, AUTHORITY};
+
+    #[test]
+    fn read_and_index() -> Result<(), String> {
+        let mut data = Vec::new();
+        for control_number in ["1", "2", "3"] {
+            let fields = [
+                (1, control_number.to_string()),
+                (150, format!("  \x1faHeading {}", control_number)),
+            ];
+            record(AUTHORITY, &fields)
+                .to_marc21(&mut data)
+                .map_err(|e| e.to_string())?;
+        }
+        let path =
+            std::env::temp_dir().join(format!("marclib-mmap-test-{}.mrc", std::process::id()));
+        std::fs::write(&path, &data).map_err(|e| e.to_string())?;
+        let reader = MmapMarcReader::open(&path).map_err(|e| e.to_string())?;
+        std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+
+        let control_numbers: Vec<String> = reader
+            .records()
+            .map(|r| {
+                r.map(|r| {
+                    r.field_iter(Some(1))
+                        .next()
+                        .unwrap()
+                        .utf8_data()
+                        .to_string()
+                })
+            })
+            .collect::<std::io::Result<_>>()
+            .map_err(|e| e.to_string())?;
+        assert_eq!(control_numbers, ["1", "2", "3"]);
+
+        let index = reader
+            .build_index(&[KeyField::control_number()])
+            .map_err(|e| e.to_string())?;
+        let record = reader
+            .get_by_key(&index, KeyField::control_number(), "2")
+            .map_err(|e| e.to_string())?
+            .ok_or("record 2")?;
+        assert_eq!(
+            record
+                .field_iter(Some(150))
+                .next()
+                .ok_or("150")?
+                .utf8_data(),
+            "  \x1faHeading 2"
+        );
+        assert!(reader.get(&index, 3).map_err(|e| e.to_string())?.is_none());
+        assert!(reader.record_at(1).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+/*!
+ * Reading and writing MarcEdit's mnemonic MRK format.
+ *
+ * Each line holds one field, `=245  10$aTitle`, starting with `=LDR` for the
+ * leader, and records are separated by blank lines. A backslash stands for a
+ * blank in the leader, control fields and indicators, and a literal `$` is
+ * written `{dollar}`. The data is read and written byte for byte, so records
+ * that are not in UTF-8, e.g. MARC-8, survive the round trip.
+ */
+use crate::ownedrecord::OwnedRecord;
+use crate::record::*;
+use crate::recordreader::{invalid, parse_tag, set_leader, RecordReader};
+use crate::recordwriter::RecordWriter;
+use std::io::{BufRead, Write};
+
+pub struct MrkReader<R: BufRead> {
+    reader: R,
+    line: Vec<u8>,
+    line_number: usize,
+    // set after an error inside a record, the rest of it is skipped
+    skip_record: bool,
+}
+
+impl<R: BufRead> MrkReader<R> {
+    pub fn new(reader: R) -> MrkReader<R> {
+        MrkReader {
+            reader,
+            line: Vec::new(),
+            line_number: 0,
+            skip_record: false,
+        }
+    }
+
+    /** The next line without its line ending, None at the end of the input **/
+    fn next_line(&mut self) -> std::io::Result<Option<&[u8]>> {
+        self.line.clear();
+        if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+            return Ok(None);
+        }
+        self.line_number += 1;
+        let mut line = &self.line[..];
+        while let [rest @ .., b'\r' | b'\n'] = line {
+            line = rest;
+        }
+        Ok(Some(line))
+    }
+
+    /** Read the lines of the next record into `record`, which stays None at the end of the input **/
+    fn parse_record(&mut self, record: &mut Option<OwnedRecord>) -> std::io::Result<()> {
+        loop {
+            let line_number = self.line_number + 1;
+            let line = match self.next_line()? {
+                Some(line) => line,
+                None => return Ok(()),
+            };
+            if line.iter().all(u8::is_ascii_whitespace) {
+                if record.is_some() {
+                    return Ok(());
+                }
+                continue;
+            }
+            let line = line
+                .strip_prefix(b"=")
+                .ok_or_else(|| invalid(format!("line {} does not start with =", line_number)))?;
+            // the tag is followed by two blanks
+            let (tag, content) = (line.get(..3).unwrap_or(line), line.get(5..).unwrap_or(b""));
+            if tag == b"LDR" {
+                let mut r = OwnedRecord::new();
+                set_leader(&mut r, &blanks(content))?;
+                *record = Some(r);
+                continue;
+            }
+            let r = record
+                .as_mut()
+                .ok_or_else(|| invalid(format!("line {} precedes the leader", line_number)))?;
+            let field_type = parse_tag(&String::from_utf8_lossy(tag))?;
+            r.add_field(OwnedRecordField {
+                field_type,
+                data: field_data(field_type, content),
+            });
+        }
+    }
+}
+
+fn blanks(s: &[u8]) -> Vec<u8> {
+    s.iter()
+        .map(|&b| if b == b'\\' { b' ' } else { b })
+        .collect()
+}
+
+/** The field data of a line's content after the tag **/
+fn field_data(field_type: usize, content: &[u8]) -> Vec<u8> {
+    if !RecordField::is_data_field_type(field_type) {
+        return blanks(content);
+    }
+    let (indicators, mut subfields) = content.split_at(content.len().min(2));
+    let mut data = blanks(indicators);
+    while let Some((&b, rest)) = subfields.split_first() {
+        if let Some(rest) = subfields.strip_prefix(b"{dollar}") {
+            data.push(b'$');
+            subfields = rest;
+        } else {
+            data.push(if b == b'$' { 0x1f } else { b });
+            subfields = rest;
+        }
+    }
+    data
+}
+
+impl<R: BufRead + Send> RecordReader for MrkReader<R> {
+    fn read_record(&mut self) -> std::io::Result<Option<OwnedRecord>> {
+        while self.skip_record {
+            match self.next_line()? {
+                Some(line) if !line.iter().all(u8::is_ascii_whitespace) => {}
+                _ => self.skip_record = false,
+            }
+        }
+        let mut record: Option<OwnedRecord> = None;
+        if let Err(e) = self.parse_record(&mut record) {
+            // skip the rest of the damaged record, so that it gives only one error
+            self.skip_record = record.is_some();
+            return Err(e);
+        }
+        if let Some(r) = record.as_mut() {
+            r.update_len()?;
+        }
+        Ok(record)
+    }
+}
+
+pub struct MrkWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> MrkWriter<W> {
+    pub fn new(writer: W) -> MrkWriter<W> {
+        MrkWriter { writer }
+    }
+}
+
+fn backslashes(s: &[u8]) -> Vec<u8> {
+    s.iter()
+        .map(|&b| if b == b' ' { b'\\' } else { b })
+        .collect()
+}
+
+/** The line content after the tag, the inverse of `field_data`, the data is written as is **/
+fn mnemonic(field: &RecordField) -> Vec<u8> {
+    if !RecordField::is_data_field_type(field.field_type) {
+        return backslashes(field.data);
+    }
+    let (indicators, subfields) = field.data.split_at(field.data.len().min(2));
+    let mut line = backslashes(indicators);
+    for &b in subfields {
+        match b {
+            b'$' => line.extend_from_slice(b"{dollar}"),
+            0x1f => line.push(b'$'),
+            _ => line.push(b),
+        }
+    }
+    line
+}
+
+impl<W: Write> RecordWriter for MrkWriter<W> {
+    fn write_record(&mut self, record: &dyn Record) -> std::io::Result<()> {
+        self.writer.write_all(b"=LDR  ")?;
+        self.writer.write_all(&backslashes(record.leader()))?;
+        self.writer.write_all(b"\n")?;
+        for field in record.field_iter(None) {
+            write!(self.writer, "={}  ", field.tag())?;
+            self.writer.write_all(&mnemonic(&field))?;
+            self.writer.write_all(b"\n")?;
+        }
+        writeln!(self.writer)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mrk::*;
+    use crate::recordreader::open_any_reader;
+
+    #[test]
+    fn read() -> Result<(), String> {
+        let mrk = "=LDR  00000nz\\\\a2200000nc\\4500\n\
+                   =001  040000028\n\
+                   =008  880701n||azznnbabn\\\\\\\\\\\\\\\\\\\\\\|\\ana\\\\\\\\|c\n\
+                   =150  \\\\$aA 302 D\n\
+                   =670  \\\\$aPreis: 5{dollar}\n\
+                   \n\
+                   =LDR  00000nz\\\\a2200000nc\\4500\r\n\
+                   =001  040000036\r\n";
+        let mut reader = open_any_reader(std::io::Cursor::new(mrk.as_bytes().to_vec()))
+            .map_err(|e| e.to_string())?;
+        let records: Vec<OwnedRecord> = reader
+            .as_mut()
+            .collect::<std::io::Result<_>>()
+            .map_err(|e| e.to_string())?;
+        assert_eq!(records.len(), 2);
+        let r = &records[0];
+        assert_eq!(&r.header[5..12], b"nz  a22");
+        assert_eq!(&r.header[17..], b"nc 4500");
+        let fields: Vec<(usize, String)> = r
+            .field_iter(None)
+            .map(|f| (f.field_type, f.utf8_data().to_string()))
+            .collect();
+        assert_eq!(fields[0], (1, "040000028".to_string()));
+        assert_eq!(fields[1].1.len(), 40);
+        assert!(fields[1]
+            .1
+            .starts_with("880701n||azznnbabn           | ana"));
+        assert_eq!(fields[2], (150, "  \x1faA 302 D".to_string()));
+        assert_eq!(fields[3], (670, "  \x1faPreis: 5$".to_string()));
+        let mut out = Vec::new();
+        r.to_marc21(&mut out).map_err(|e| e.to_string())?;
+        assert_eq!(crate::util::parse_usize(&out[..5]), out.len());
+        assert_eq!(
+            records[1]
+                .field_iter(Some(1))
+                .next()
+                .ok_or("001")?
+                .utf8_data(),
+            "040000036"
+        );
+
+        let mut reader = MrkReader::new("=245  10$aTitle\n".as_bytes());
+        assert!(reader.read_record().is_err());
+
+        // a damaged record gives one error, reading goes on with the next record
+        let mrk = "=LDR  00000nz\\\\a2200000nc\\4500\n=1  x\n=150  \\\\$aA\n\n\
+                   =LDR  00000nz\\\\a2200000nc\\4500\n=001  040000036\n";
+        let mut reader = MrkReader::new(mrk.as_bytes());
+        assert!(reader.read_record().is_err());
+        assert!(reader.read_record().map_err(|e| e.to_string())?.is_some());
+        assert!(reader.read_record().map_err(|e| e.to_string())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn not_utf8() -> Result<(), String> {
+        // MARC-8 combining acute before the e
+        let mut r = OwnedRecord::new();
+        r.header.copy_from_slice(b"00000nz   2200000nc 4500");
+        r.add_field(OwnedRecordField {
+            field_type: 150,
+            data: b"  \x1faCaf\xe2e $5".to_vec(),
+        });
+        let mut out = Vec::new();
+        let mut writer = MrkWriter::new(&mut out);
+        writer.write_record(&r).map_err(|e| e.to_string())?;
+        assert_eq!(
+            out,
+            b"=LDR  00000nz\\\\\\2200000nc\\4500\n=150  \\\\$aCaf\xe2e {dollar}5\n\n"
+        );
+        let read = MrkReader::new(&out[..])
+            .read_record()
+            .map_err(|e| e.to_string())?
+            .ok_or("no record")?;
+        assert_eq!(read.field_data, r.field_data);
+        Ok(())
+    }
+}
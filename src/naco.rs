@@ -0,0 +1,190 @@
+/*!
+ * NACO normalization, following the Library of Congress "Authority File
+ * Comparison Rules". Two headings are considered the same if their normalized
+ * forms are equal.
+ *
+ * - letters are folded to upper case and stripped of diacritics, special
+ *   letters such as `Æ` or `Þ` are spelled out
+ * - `&`, `@`, `#` and `+` are kept, apostrophes and square brackets are
+ *   deleted, all other punctuation becomes a blank
+ * - the first comma in the first `$a` is kept, all other commas become blanks
+ * - subfield delimiters and codes become blanks
+ * - runs of blanks are collapsed, leading and trailing blanks removed
+ */
+use crate::authority::Heading;
+use crate::record::*;
+use std::borrow::Cow;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+fn spelled_out(c: char) -> Option<&'static str> {
+    // letters without a canonical decomposition into base letter and diacritic
+    Some(match c {
+        'Æ' | 'æ' => "AE",
+        'Œ' | 'œ' => "OE",
+        'Ø' | 'ø' => "O",
+        'Đ' | 'đ' | 'Ð' | 'ð' => "D",
+        'Þ' | 'þ' => "TH",
+        'ß' => "SS",
+        'Ł' | 'ł' => "L",
+        'ı' => "I",
+        _ => return None,
+    })
+}
+
+fn is_deleted(c: char) -> bool {
+    matches!(c, '\'' | '[' | ']' | 'ʹ' | 'ʺ' | 'ʻ' | 'ʼ' | '’')
+}
+
+fn is_retained(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '&' | '@' | '#' | '+' | '♭' | '♯')
+}
+
+/** Append the normalized form of one subfield value to `out` **/
+fn push_normalized(out: &mut String, value: &str, mut keep_comma: bool) {
+    for c in value.nfkd() {
+        if is_combining_mark(c) || is_deleted(c) {
+            continue;
+        }
+        if let Some(s) = spelled_out(c) {
+            out.push_str(s);
+        } else if is_retained(c) {
+            out.extend(c.to_uppercase());
+        } else if c == ',' && keep_comma {
+            keep_comma = false;
+            while out.ends_with(' ') {
+                out.pop();
+            }
+            out.push(',');
+        } else if !out.is_empty() && !out.ends_with(' ') {
+            out.push(' ');
+        }
+    }
+    if !out.ends_with(' ') {
+        out.push(' ');
+    }
+}
+
+fn finish(mut out: String) -> String {
+    let trimmed = out.trim_end().len();
+    out.truncate(trimmed);
+    // a comma that ended up last carries no information
+    if out.ends_with(',') {
+        out.pop();
+    }
+    out
+}
+
+/** Normalize a single string, without the special treatment of commas **/
+pub fn normalize(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    push_normalized(&mut out, value, false);
+    finish(out)
+}
+
+/** Normalize a sequence of subfields given as code and value **/
+pub fn normalize_subfields<'a, I>(subfields: I) -> String
+where
+    I: IntoIterator<Item = (u8, &'a str)>,
+{
+    let mut out = String::new();
+    let mut first_a = true;
+    for (code, value) in subfields {
+        let keep_comma = code == b'a' && first_a;
+        if code == b'a' {
+            first_a = false;
+        }
+        push_normalized(&mut out, value, keep_comma);
+    }
+    finish(out)
+}
+
+pub fn normalize_heading(heading: &Heading) -> String {
+    normalize_subfields(heading.subfields.iter().map(|(c, v)| (*c, v.as_str())))
+}
+
+/**
+ * Normalize the heading part of a field of a record of the given type. For
+ * heading tags this skips control and relationship subfields, for other fields
+ * all alphabetic subfields are used.
+ */
+pub fn normalize_field(field: &RecordField, record_type: RecordType) -> String {
+    if let Some(heading) = Heading::from_field(field, record_type) {
+        return normalize_heading(&heading);
+    }
+    if !field.has_subfields() {
+        return normalize(&field.utf8_data_lossy());
+    }
+    let subfields: Vec<(u8, Cow<str>)> = field
+        .subfields()
+        .filter(|s| s.code().is_ascii_lowercase())
+        .map(|s| (s.code(), s.utf8_value()))
+        .collect();
+    normalize_subfields(
+        subfields
+            .iter()
+            .map(|(code, value)| (*code, value.as_ref())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::marcrecord::MarcHeader;
+    use crate::marcrecord::MarcRecord;
+    use crate::naco::*;
+    use crate::testutil::STR;
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            normalize("  Müller-Lüdenscheidt,  Hans "),
+            "MULLER LUDENSCHEIDT HANS"
+        );
+        assert_eq!(
+            normalize("L'Œuvre de Þórr [Ausg.]"),
+            "LOEUVRE DE THORR AUSG"
+        );
+        assert_eq!(normalize("Straße & Łódź"), "STRASSE & LODZ");
+        assert_eq!(normalize("C++ / C#"), "C++ C#");
+        assert_eq!(normalize("H₂O"), "H2O");
+        assert_eq!(normalize("Ærø."), "AERO");
+    }
+
+    #[test]
+    fn subfields() {
+        assert_eq!(
+            normalize_subfields(vec![
+                (b'a', "Smith , John,"),
+                (b'd', "1900-1980."),
+                (b'a', "Doe, Jane")
+            ]),
+            "SMITH, JOHN 1900 1980 DOE JANE"
+        );
+        assert_eq!(normalize_subfields(vec![(b'a', "Goethe,")]), "GOETHE");
+        assert_eq!(
+            normalize_subfields(vec![(b'a', "Germany"), (b'x', "History"), (b'y', "1945-")]),
+            "GERMANY HISTORY 1945"
+        );
+    }
+
+    #[test]
+    fn fields() {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let keys: Vec<String> = record
+            .field_iter_vec(&[150, 550])
+            .map(|f| normalize_field(&f, RecordType::Authority))
+            .collect();
+        assert_eq!(keys, ["A 302 D", "INTEGRIERTE SCHALTUNG"]);
+        let f = record.field_iter(Some(670)).next().unwrap();
+        assert_eq!(normalize_field(&f, RecordType::Authority), "VORLAGE");
+        // MARC-8 data is not UTF-8, it must not abort a whole index run
+        let marc8 = RecordField {
+            field_type: 500,
+            data: b"  \x1faM\xe2uller",
+        };
+        assert_eq!(
+            normalize_field(&marc8, RecordType::LanguageMaterial),
+            "M ULLER"
+        );
+    }
+}
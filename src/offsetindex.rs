@@ -0,0 +1,397 @@
+/*!
+ * Random access to records of large files through an index of byte offsets.
+ *
+ * `OffsetIndex::build` scans a file once and records where each record starts
+ * and how long it is, together with the values of chosen key fields. The
+ * index can be saved in a compact binary format and loaded again, and
+ * `IndexedMarcReader` uses it to seek straight to a record.
+ *
+ * The format is little-endian: the magic `MARCIDX1`, the number of key fields
+ * (u32) and for each its tag (u16) and subfield code (u8, 0 for the whole
+ * field), the number of records (u64) and for each its offset (u64) and
+ * length (u32), then per key field the number of keys (u64) and the keys in
+ * sorted order, each as length (u16), UTF-8 bytes and record position (u32).
+ */
+use crate::marcrecord::{MarcHeader, MarcReader, MarcRecord, MARCHEADER_SIZE};
+use crate::record::*;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"MARCIDX1";
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/** A field whose values are indexed, the whole field for control fields or one subfield **/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyField {
+    pub field_type: usize,
+    pub subfield: Option<u8>,
+}
+
+impl KeyField {
+    pub fn control_number() -> KeyField {
+        KeyField {
+            field_type: 1,
+            subfield: None,
+        }
+    }
+
+    /** `001` or `035$a` **/
+    pub fn parse(s: &str) -> Option<KeyField> {
+        let (tag, code) = match s.find('$') {
+            Some(i) => (&s[..i], Some(*s.as_bytes().get(i + 1)?)),
+            None => (s, None),
+        };
+        if tag.len() != 3 {
+            return None;
+        }
+        Some(KeyField {
+            field_type: tag.parse().ok()?,
+            subfield: code,
+        })
+    }
+
+    /** The values of all occurrences of the field in the record **/
+    pub fn values<R: Record + ?Sized>(&self, record: &R) -> Vec<String> {
+        let mut values = Vec::new();
+        for field in record.field_iter(Some(self.field_type)) {
+            match self.subfield {
+                Some(code) => values.extend(field.subfield_values(code).map(|s| s.to_string())),
+                None => values.push(field.utf8_data_lossy().trim().to_string()),
+            }
+        }
+        values
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OffsetIndex {
+    key_fields: Vec<KeyField>,
+    // offset and length of the record at each position
+    locations: Vec<(u64, u32)>,
+    // per key field, sorted by key
+    keys: Vec<Vec<(String, u32)>>,
+}
+
+impl OffsetIndex {
+    pub fn new(key_fields: &[KeyField]) -> OffsetIndex {
+        OffsetIndex {
+            key_fields: key_fields.to_vec(),
+            locations: Vec::new(),
+            keys: vec![Vec::new(); key_fields.len()],
+        }
+    }
+
+    /** Index every record the reader yields, the reader has to be at the start of the file **/
+    pub fn build<R: Read + Seek>(
+        reader: &mut MarcReader<R>,
+        buffer: &mut [u8],
+        key_fields: &[KeyField],
+    ) -> std::io::Result<OffsetIndex> {
+        let mut index = OffsetIndex::new(key_fields);
+        while let Some(batch) = reader.read_batch(buffer)? {
+            for record in batch.records.iter() {
+                index.add_record(record);
+            }
+        }
+        index.finish();
+        Ok(index)
+    }
+
+    /** Add the record following the last one added **/
+    pub fn add_record(&mut self, record: &MarcRecord) {
+        let position = self.locations.len() as u32;
+        let offset = self
+            .locations
+            .last()
+            .map_or(0, |&(offset, len)| offset + len as u64);
+        self.locations.push((offset, record.record_length() as u32));
+        for (key_field, keys) in self.key_fields.iter().zip(self.keys.iter_mut()) {
+            // longer keys do not fit the on-disk format
+            keys.extend(
+                key_field
+                    .values(record)
+                    .into_iter()
+                    .filter(|k| k.len() <= u16::MAX as usize)
+                    .map(|k| (k, position)),
+            );
+        }
+    }
+
+    /** Sort the keys, needed before lookups after records were added **/
+    pub fn finish(&mut self) {
+        for keys in self.keys.iter_mut() {
+            keys.sort_unstable();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    pub fn key_fields(&self) -> &[KeyField] {
+        &self.key_fields
+    }
+
+    /** Offset and length of the record at `position` **/
+    pub fn location(&self, position: usize) -> Option<(u64, usize)> {
+        self.locations
+            .get(position)
+            .map(|&(offset, len)| (offset, len as usize))
+    }
+
+    /** Positions of the records with this key, in file order **/
+    pub fn lookup(&self, key_field: KeyField, key: &str) -> Vec<usize> {
+        let keys = match self.key_fields.iter().position(|&k| k == key_field) {
+            Some(i) => &self.keys[i],
+            None => return Vec::new(),
+        };
+        let start = keys.partition_point(|(k, _)| k.as_str() < key);
+        keys[start..]
+            .iter()
+            .take_while(|(k, _)| k == key)
+            .map(|&(_, position)| position as usize)
+            .collect()
+    }
+
+    pub fn control_number(&self, control_number: &str) -> Option<usize> {
+        self.lookup(KeyField::control_number(), control_number)
+            .first()
+            .cloned()
+    }
+
+    pub fn write_to(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(self.key_fields.len() as u32).to_le_bytes())?;
+        for key_field in self.key_fields.iter() {
+            writer.write_all(&(key_field.field_type as u16).to_le_bytes())?;
+            writer.write_all(&[key_field.subfield.unwrap_or(0)])?;
+        }
+        writer.write_all(&(self.locations.len() as u64).to_le_bytes())?;
+        for &(offset, len) in self.locations.iter() {
+            writer.write_all(&offset.to_le_bytes())?;
+            writer.write_all(&len.to_le_bytes())?;
+        }
+        for keys in self.keys.iter() {
+            writer.write_all(&(keys.len() as u64).to_le_bytes())?;
+            for (key, position) in keys.iter() {
+                writer.write_all(&(key.len() as u16).to_le_bytes())?;
+                writer.write_all(key.as_bytes())?;
+                writer.write_all(&position.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut dyn Read) -> std::io::Result<OffsetIndex> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not an offset index".to_string()));
+        }
+        let mut key_fields = Vec::new();
+        for _ in 0..read_u32(reader)? {
+            let mut tag = [0; 2];
+            let mut code = [0; 1];
+            reader.read_exact(&mut tag)?;
+            reader.read_exact(&mut code)?;
+            key_fields.push(KeyField {
+                field_type: u16::from_le_bytes(tag) as usize,
+                subfield: Some(code[0]).filter(|&c| c != 0),
+            });
+        }
+        let mut index = OffsetIndex::new(&key_fields);
+        let count = read_u64(reader)?;
+        for _ in 0..count {
+            let offset = read_u64(reader)?;
+            index.locations.push((offset, read_u32(reader)?));
+        }
+        for keys in index.keys.iter_mut() {
+            for _ in 0..read_u64(reader)? {
+                let mut len = [0; 2];
+                reader.read_exact(&mut len)?;
+                let mut key = vec![0; u16::from_le_bytes(len) as usize];
+                reader.read_exact(&mut key)?;
+                let key = String::from_utf8(key).map_err(|e| invalid(e.to_string()))?;
+                let position = read_u32(reader)?;
+                if position as u64 >= count {
+                    return Err(invalid(format!("key {} points past the last record", key)));
+                }
+                keys.push((key, position));
+            }
+        }
+        Ok(index)
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<OffsetIndex> {
+        OffsetIndex::read_from(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+fn read_u32(reader: &mut dyn Read) -> std::io::Result<u32> {
+    let mut b = [0; 4];
+    reader.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64(reader: &mut dyn Read) -> std::io::Result<u64> {
+    let mut b = [0; 8];
+    reader.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+/** Reads single records by position or key, seeking with the help of an `OffsetIndex` **/
+pub struct IndexedMarcReader<R>
+where
+    R: Read + Seek,
+{
+    base_reader: R,
+    index: OffsetIndex,
+    buffer: Vec<u8>,
+}
+
+impl<R> IndexedMarcReader<R>
+where
+    R: Read + Seek,
+{
+    pub fn new(reader: R, index: OffsetIndex) -> IndexedMarcReader<R> {
+        IndexedMarcReader {
+            base_reader: reader,
+            index,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn index(&self) -> &OffsetIndex {
+        &self.index
+    }
+
+    /** The record at `position`, None if there is no such position **/
+    pub fn get(&mut self, position: usize) -> std::io::Result<Option<MarcRecord<'_>>> {
+        let (offset, len) = match self.index.location(position) {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        if len < MARCHEADER_SIZE {
+            return Err(invalid(format!("record {} is too short", position)));
+        }
+        self.buffer.resize(len, 0);
+        self.base_reader.seek(SeekFrom::Start(offset))?;
+        self.base_reader.read_exact(&mut self.buffer)?;
+        let header = MarcHeader::new(&self.buffer[..MARCHEADER_SIZE]);
+        if header.record_length() != len {
+            return Err(invalid(format!(
+                "record {} at offset {} does not match the index",
+                position, offset
+            )));
+        }
+        Ok(Some(MarcRecord::new(
+            header,
+            &self.buffer[MARCHEADER_SIZE..],
+        )))
+    }
+
+    /** The first record with this key **/
+    pub fn get_by_key(
+        &mut self,
+        key_field: KeyField,
+        key: &str,
+    ) -> std::io::Result<Option<MarcRecord<'_>>> {
+        match self.index.lookup(key_field, key).first() {
+            Some(&position) => self.get(position),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_by_control_number(
+        &mut self,
+        control_number: &str,
+    ) -> std::io::Result<Option<MarcRecord<'_>>> {
+        self.get_by_key(KeyField::control_number(), control_number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offsetindex::*;
+    use crate::testutil::{record, AUTHORITY};
+    use std::io::Cursor;
+
+    fn file() -> Vec<u8> {
+        let mut out = Vec::new();
+        for (control_number, title, other) in [
+            ("1", "Eins", "(DE-588)1-1"),
+            ("2", "Zwei", "(DE-588)2-2"),
+            ("3", "Drei und etwas länger", "(DE-588)1-1"),
+        ] {
+            let fields = [
+                (1, control_number.to_string()),
+                (35, format!("  \x1fa{}\x1fa(X){}", other, control_number)),
+                (150, format!("  \x1fa{}", title)),
+            ];
+            record(AUTHORITY, &fields).to_marc21(&mut out).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn build_save_and_read() -> Result<(), String> {
+        let data = file();
+        let key_fields = [
+            KeyField::control_number(),
+            KeyField::parse("035$a").unwrap(),
+        ];
+        let mut buffer = vec![0; 100000];
+        let index = OffsetIndex::build(
+            &mut MarcReader::new(Cursor::new(&data)),
+            &mut buffer,
+            &key_fields,
+        )
+        .map_err(|e| e.to_string())?;
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.lookup(key_fields[1], "(DE-588)1-1"), [0, 2]);
+        assert_eq!(index.lookup(key_fields[1], "(X)2"), [1]);
+        assert!(index.lookup(key_fields[1], "(DE-588)3").is_empty());
+
+        let mut saved = Vec::new();
+        index.write_to(&mut saved).map_err(|e| e.to_string())?;
+        let loaded = OffsetIndex::read_from(&mut &saved[..]).map_err(|e| e.to_string())?;
+        assert_eq!(loaded, index);
+        assert!(OffsetIndex::read_from(&mut &saved[1..]).is_err());
+
+        let mut reader = IndexedMarcReader::new(Cursor::new(&data), loaded);
+        let record = reader
+            .get_by_control_number("3")
+            .map_err(|e| e.to_string())?
+            .ok_or("record 3")?;
+        let title = record.field_iter(Some(150)).next().ok_or("150")?;
+        assert_eq!(title.utf8_data(), "  \x1faDrei und etwas länger");
+        let record = reader
+            .get(1)
+            .map_err(|e| e.to_string())?
+            .ok_or("record 1")?;
+        assert_eq!(
+            record.field_iter(Some(1)).next().ok_or("001")?.utf8_data(),
+            "2"
+        );
+        assert!(reader.get(3).map_err(|e| e.to_string())?.is_none());
+        assert!(reader
+            .get_by_control_number("4")
+            .map_err(|e| e.to_string())?
+            .is_none());
+        Ok(())
+    }
+}
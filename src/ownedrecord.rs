@@ -1,5 +1,6 @@
 use crate::record::*;
 use crate::util::write_usize;
+#[derive(Clone)]
 pub struct OwnedRecord {
     pub header: [u8; 24],
     pub field_types: Vec<usize>,
@@ -22,21 +23,79 @@ impl OwnedRecord {
         self.field_data.push(field.data);
     }
 
-    pub fn add_field_from_iter(&mut self, field_iter: &mut dyn Iterator<Item = RecordField>) {
+    pub fn add_field_from_iter(
+        &mut self,
+        field_iter: &mut dyn Iterator<Item = RecordField>,
+    ) -> std::io::Result<()> {
         for field in field_iter {
             self.add_field(field.to_owned());
         }
-        self.update_len();
+        self.update_len()
     }
 
-    fn update_len(&mut self) {
-        let data_len: usize = self.field_data.iter().map(|x| x.len()).sum();
-        let dict_len: usize = 12 * self.field_data.len();
-        let mut l: usize = data_len + dict_len + self.header.len();
-        for i in 0..5 {
-            self.header[4 - i] = b'0' + (l % 10) as u8;
-            l /= 10;
+    /**
+     * Replace the first field with the same type, or insert the field if there
+     * is none. Like all setters it keeps the change and fails if the record no
+     * longer fits into ISO 2709.
+     */
+    pub fn set_field(&mut self, field: OwnedRecordField) -> std::io::Result<()> {
+        match self.field_types.iter().position(|&t| t == field.field_type) {
+            Some(idx) => {
+                self.field_data[idx] = field.data;
+                self.update_len()
+            }
+            None => self.insert_field(field),
+        }
+    }
+
+    /** Insert the field after all fields whose type is not larger, keeping tag order **/
+    pub fn insert_field(&mut self, field: OwnedRecordField) -> std::io::Result<()> {
+        let idx = self
+            .field_types
+            .iter()
+            .rposition(|&t| t <= field.field_type)
+            .map_or(0, |i| i + 1);
+        self.field_types.insert(idx, field.field_type);
+        self.field_data.insert(idx, field.data);
+        self.update_len()
+    }
+
+    /** Remove all fields of the given type, returns how many were removed **/
+    pub fn remove_fields(&mut self, field_type: usize) -> std::io::Result<usize> {
+        let before = self.field_types.len();
+        let mut i = 0;
+        while i < self.field_types.len() {
+            if self.field_types[i] == field_type {
+                self.field_types.remove(i);
+                self.field_data.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        self.update_len()?;
+        Ok(before - self.field_types.len())
+    }
+
+    /**
+     * Recompute record length and base address, needed after changing
+     * `field_data` directly. Fails if they do not fit into the leader, the
+     * leader is left unchanged then.
+     */
+    pub fn update_len(&mut self) -> std::io::Result<()> {
+        // +1 for the field separator of every field
+        let data_len: usize = self.field_data.iter().map(|x| x.len() + 1).sum();
+        // +1 for the field separator after the directory
+        let base_address = self.header.len() + 12 * self.field_data.len() + 1;
+        // +1 for the record terminator
+        let l = base_address + data_len + 1;
+        if l > 99999 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("record is {} bytes long, ISO 2709 allows 99999", l),
+            ));
         }
+        write_usize(l, 5, &mut &mut self.header[0..5])?;
+        write_usize(base_address, 5, &mut &mut self.header[12..17])
     }
 }
 
@@ -66,8 +125,11 @@ impl<'s> Iterator for OwnedRecordFieldIter<'s> {
 }
 
 impl Record for OwnedRecord {
-    fn record_type(&self) -> RecordType {
-        todo!();
+    fn leader(&self) -> &[u8] {
+        &self.header
+    }
+    fn record_type(&self) -> Option<RecordType> {
+        RecordType::from_byte(self.header[6])
     }
     fn field_iter_vec(&self, field_types: &[usize]) -> Box<dyn Iterator<Item = RecordField> + '_> {
         Box::new(OwnedRecordFieldIter {
@@ -112,28 +174,9 @@ mod tests {
     use crate::marcrecord::*;
     use crate::ownedrecord::*;
     use crate::record::*;
+    use crate::testutil::STR;
     use std::io::BufReader;
     use std::io::Cursor;
-    static STR : &[u8]= "00827nz  a2200241nc 4500\
-001001000000\
-003000700010\
-005001700017\
-008004100034\
-024005100075\
-035002200126\
-035002200148\
-035002900170\
-040004000199\
-042000900239\
-065001600248\
-075001400264\
-079000900278\
-083004200287\
-150001200329\
-550019200341\
-670001200533\
-913004000545\
-040000028DE-10120100106125650.0880701n||azznnbabn           | ana    |c7 a4000002-30http://d-nb.info/gnd/4000002-32gnd  a(DE-101)040000028  a(DE-588)4000002-3  z(DE-588c)4000002-39v:zg  aDE-101cDE-1019r:DE-101bgerd0832  agnd1  a31.9b2sswd  bs2gndgen  agqs04a621.3815379d:29t:2010-01-06223/ger  aA 302 D  0(DE-101)0402724270(DE-588)4027242-40https://d-nb.info/gnd/4027242-4aIntegrierte Schaltung4obal4https://d-nb.info/standards/elementset/gnd#broaderTermGeneralwriOberbegriff allgemein  aVorlage  SswdisaA 302 D0(DE-588c)4000002-3".as_bytes();
 
     #[test]
     fn conv_back() -> Result<(), String> {
@@ -160,4 +203,45 @@ mod tests {
             _ => Err("something bad".to_string()),
         }
     }
+
+    #[test]
+    fn edit_fields() -> Result<(), String> {
+        let header = MarcHeader::new(&STR[..24]);
+        let record = MarcRecord::new(header, &STR[24..]);
+        let mut owned_record = record.to_owned();
+        let last = owned_record
+            .field_iter(Some(913))
+            .next()
+            .unwrap()
+            .to_owned();
+        assert_eq!(owned_record.remove_fields(35).unwrap(), 3);
+        assert_eq!(owned_record.remove_fields(913).unwrap(), 1);
+        let mut result: Vec<u8> = Vec::new();
+        owned_record.to_marc21(&mut result).expect("not ok");
+        assert_eq!(&result[0..5], b"00666");
+        assert_eq!(&result[12..17], b"00193");
+        for field in record.field_iter(Some(35)) {
+            owned_record.insert_field(field.to_owned()).unwrap();
+        }
+        owned_record.insert_field(last).unwrap();
+        result.clear();
+        owned_record.to_marc21(&mut result).expect("not ok");
+        assert_eq!(result, STR);
+        Ok(())
+    }
+
+    #[test]
+    fn oversized() {
+        let mut owned_record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]).to_owned();
+        let field = || OwnedRecordField {
+            field_type: 500,
+            data: vec![b'a'; 9500],
+        };
+        for _ in 0..10 {
+            owned_record.insert_field(field()).unwrap();
+        }
+        assert!(owned_record.insert_field(field()).is_err());
+        assert_eq!(owned_record.field_iter(Some(500)).count(), 11);
+        assert_eq!(&owned_record.header[0..5], b"95957");
+    }
 }
@@ -0,0 +1,320 @@
+/*!
+ * Parallel processing of the records of a file.
+ *
+ * One thread reads the input in chunks that end at a record boundary, checked
+ * against the leader's record length and the 0x1D record terminator. A pool of
+ * workers turns each chunk into a `MarcRecordBatch` and runs the caller's
+ * closure on it. The results arrive on the calling thread either in input
+ * order or as soon as they are ready, see `ParallelConfig::ordered`.
+ */
+use crate::marcrecord::{
+    complete_record_length, MarcRecord, MarcRecordBatch, MarcSliceReader, MARCHEADER_SIZE,
+};
+use crate::record::Record;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::sync::mpsc::{channel, sync_channel, Receiver};
+use std::sync::{Arc, Mutex};
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[derive(Debug, Clone)]
+pub struct ParallelConfig {
+    pub threads: usize,
+    // bytes per chunk, chunks grow to hold records that are larger
+    pub chunk_size: usize,
+    // deliver results in input order, otherwise as they are finished
+    pub ordered: bool,
+}
+
+impl Default for ParallelConfig {
+    fn default() -> ParallelConfig {
+        ParallelConfig {
+            threads: std::thread::available_parallelism().map_or(4, |n| n.get()),
+            chunk_size: 4 << 20,
+            ordered: true,
+        }
+    }
+}
+
+/** The length of the complete records at the start of `data`, at `offset` in the input **/
+fn complete_records(data: &[u8], offset: u64) -> std::io::Result<usize> {
+    let mut i = 0;
+    while let Some(record_length) = complete_record_length(&data[i..], offset + i as u64)? {
+        i += record_length;
+    }
+    Ok(i)
+}
+
+/** A chunk of whole records and its position in the input **/
+struct Chunk {
+    sequence: usize,
+    data: Vec<u8>,
+}
+
+pub struct ParallelReader<R: Read + Send> {
+    reader: R,
+    config: ParallelConfig,
+}
+
+impl<R: Read + Send> ParallelReader<R> {
+    pub fn new(reader: R, config: ParallelConfig) -> ParallelReader<R> {
+        ParallelReader { reader, config }
+    }
+
+    /**
+     * Run `work` on every batch in the worker pool and hand its results to
+     * `consume` on the calling thread. An error from `consume` stops the
+     * processing and is returned.
+     */
+    pub fn process<T, F, C>(self, work: F, mut consume: C) -> std::io::Result<()>
+    where
+        T: Send,
+        F: Fn(&MarcRecordBatch) -> T + Sync,
+        C: FnMut(T) -> std::io::Result<()>,
+    {
+        let ParallelReader { mut reader, config } = self;
+        let threads = config.threads.max(1);
+        let chunk_size = config.chunk_size.max(MARCHEADER_SIZE);
+        let (chunk_tx, chunk_rx) = sync_channel::<Chunk>(threads * 2);
+        let chunk_rx = Arc::new(Mutex::new(chunk_rx));
+        let (result_tx, result_rx) = channel::<(usize, T)>();
+        let work = &work;
+        std::thread::scope(|scope| {
+            let read = scope.spawn(move || -> std::io::Result<()> {
+                let mut pending: Vec<u8> = Vec::new();
+                let mut offset = 0u64;
+                let mut sequence = 0;
+                loop {
+                    let start = pending.len();
+                    pending.resize(start + chunk_size, 0);
+                    let read = read_full(&mut reader, &mut pending[start..])?;
+                    pending.truncate(start + read);
+                    let complete = complete_records(&pending, offset)?;
+                    if read == 0 && complete < pending.len() {
+                        return Err(invalid(format!(
+                            "truncated record at byte {}",
+                            offset + complete as u64
+                        )));
+                    }
+                    if complete > 0 {
+                        let rest = pending[complete..].to_vec();
+                        pending.truncate(complete);
+                        let data = std::mem::replace(&mut pending, rest);
+                        if chunk_tx.send(Chunk { sequence, data }).is_err() {
+                            // the workers stopped, the error is reported elsewhere
+                            return Ok(());
+                        }
+                        sequence += 1;
+                        offset += complete as u64;
+                    }
+                    if read == 0 {
+                        return Ok(());
+                    }
+                }
+            });
+            for _ in 0..threads {
+                let chunk_rx = Arc::clone(&chunk_rx);
+                let result_tx = result_tx.clone();
+                scope.spawn(move || loop {
+                    let chunk = match next_chunk(&chunk_rx) {
+                        Some(chunk) => chunk,
+                        None => return,
+                    };
+                    // the reader checked the records already
+                    let records: Vec<MarcRecord> =
+                        MarcSliceReader::new(&chunk.data).flatten().collect();
+                    let result = work(&MarcRecordBatch { records });
+                    if result_tx.send((chunk.sequence, result)).is_err() {
+                        return;
+                    }
+                });
+            }
+            drop(chunk_rx);
+            drop(result_tx);
+
+            let mut consumed = Ok(());
+            let mut waiting: BTreeMap<usize, T> = BTreeMap::new();
+            let mut next = 0;
+            for (sequence, result) in result_rx.iter() {
+                if !config.ordered {
+                    consumed = consume(result);
+                } else {
+                    waiting.insert(sequence, result);
+                    while let Some(result) = waiting.remove(&next) {
+                        next += 1;
+                        consumed = consume(result);
+                        if consumed.is_err() {
+                            break;
+                        }
+                    }
+                }
+                if consumed.is_err() {
+                    break;
+                }
+            }
+            // stops the workers if consume failed
+            drop(result_rx);
+            let read = read.join().expect("reader thread panicked");
+            consumed.and(read)
+        })
+    }
+
+    /** Call `f` on every record, in no particular order **/
+    pub fn for_each<F>(self, f: F) -> std::io::Result<()>
+    where
+        F: Fn(&MarcRecord) + Sync,
+    {
+        self.process(|batch| batch.records.iter().for_each(&f), |_| Ok(()))
+    }
+
+    /** The results of `f` for every record, in input order if the config says so **/
+    pub fn map<T, F>(self, f: F) -> std::io::Result<Vec<T>>
+    where
+        T: Send,
+        F: Fn(&MarcRecord) -> T + Sync,
+    {
+        let mut results = Vec::new();
+        self.process(
+            |batch| batch.records.iter().map(&f).collect::<Vec<T>>(),
+            |batch_results| {
+                results.extend(batch_results);
+                Ok(())
+            },
+        )?;
+        Ok(results)
+    }
+
+    /** Write the records for which `f` returns true, returns their number **/
+    pub fn filter<F>(self, f: F, output: &mut dyn Write) -> std::io::Result<usize>
+    where
+        F: Fn(&MarcRecord) -> bool + Sync,
+    {
+        let mut count = 0;
+        self.process(
+            |batch| -> std::io::Result<(usize, Vec<u8>)> {
+                let mut out = Vec::new();
+                let mut n = 0;
+                for record in batch.records.iter().filter(|r| f(r)) {
+                    record.to_marc21(&mut out)?;
+                    n += 1;
+                }
+                Ok((n, out))
+            },
+            |result| {
+                let (n, out) = result?;
+                count += n;
+                output.write_all(&out)
+            },
+        )?;
+        output.flush()?;
+        Ok(count)
+    }
+}
+
+fn next_chunk(chunk_rx: &Mutex<Receiver<Chunk>>) -> Option<Chunk> {
+    chunk_rx.lock().ok()?.recv().ok()
+}
+
+/** Read until `buffer` is full or the input ends **/
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buffer.len() {
+        match reader.read(&mut buffer[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parallel::*;
+    use crate::testutil::{record, AUTHORITY};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn file(n: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        for i in 0..n {
+            let fields = [
+                (1, i.to_string()),
+                (150, format!("  \x1fa{}", "x".repeat(i % 50))),
+            ];
+            record(AUTHORITY, &fields).to_marc21(&mut out).unwrap();
+        }
+        out
+    }
+
+    fn control_number(record: &MarcRecord) -> usize {
+        record
+            .field_iter(Some(1))
+            .next()
+            .unwrap()
+            .utf8_data()
+            .parse()
+            .unwrap()
+    }
+
+    fn config(ordered: bool) -> ParallelConfig {
+        ParallelConfig {
+            threads: 4,
+            chunk_size: 500,
+            ordered,
+        }
+    }
+
+    #[test]
+    fn map_for_each_and_filter() -> Result<(), String> {
+        let data = file(300);
+        let ordered = ParallelReader::new(&data[..], config(true))
+            .map(control_number)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(ordered, (0..300).collect::<Vec<usize>>());
+
+        let mut unordered = ParallelReader::new(&data[..], config(false))
+            .map(control_number)
+            .map_err(|e| e.to_string())?;
+        unordered.sort_unstable();
+        assert_eq!(unordered, ordered);
+
+        let sum = AtomicUsize::new(0);
+        ParallelReader::new(&data[..], config(false))
+            .for_each(|r| {
+                sum.fetch_add(control_number(r), Ordering::Relaxed);
+            })
+            .map_err(|e| e.to_string())?;
+        assert_eq!(sum.into_inner(), 299 * 300 / 2);
+
+        let mut out = Vec::new();
+        let n = ParallelReader::new(&data[..], config(true))
+            .filter(|r| control_number(r).is_multiple_of(100), &mut out)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(n, 3);
+        let filtered = ParallelReader::new(&out[..], config(true))
+            .map(control_number)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(filtered, [0, 100, 200]);
+        Ok(())
+    }
+
+    #[test]
+    fn broken_records() {
+        let mut data = file(20);
+        let len = data.len();
+        data[len - 1] = b'x';
+        let err = ParallelReader::new(&data[..], config(true))
+            .map(control_number)
+            .unwrap_err();
+        assert!(err.to_string().contains("record terminator"), "{}", err);
+        let data = file(20);
+        let err = ParallelReader::new(&data[..len - 10], config(true))
+            .for_each(|_| {})
+            .unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{}", err);
+    }
+}
@@ -2,16 +2,6 @@
 use crate::marcrecord::*;
 use crate::record::*;
 
-pub enum AuthorityRecordStatus {
-    IncreaseEncodingLevel = b'a' as isize,
-    Corrected = b'c' as isize,
-    Deleted = b'd' as isize,
-    New = b'n' as isize,
-    Obsolete = b'o' as isize,
-    Split = b's' as isize,
-    Replaced = b'x' as isize,
-}
-
 pub enum AuthorityRecordCharacterCodingScheme {
     Marc8 = b'#' as isize,
     Unicode = b'a' as isize,
@@ -42,21 +32,12 @@ impl AuthorityRecordMeta {
     }
 
     pub fn new(r: &MarcRecord, dir: &MarcDirectory) -> AuthorityRecordMeta {
-        let t = r.header().record_type();
+        let t = r.header().record_type().expect("unknown record type");
         assert!(t == RecordType::Authority);
 
         // todo check whether other record types than authority parse differently here
         // and maybe move stuff to MarcHeader
-        let s = match r.header().header[5] {
-            b'a' => AuthorityRecordStatus::IncreaseEncodingLevel,
-            b'c' => AuthorityRecordStatus::Corrected,
-            b'd' => AuthorityRecordStatus::Deleted,
-            b'n' => AuthorityRecordStatus::New,
-            b'o' => AuthorityRecordStatus::Obsolete,
-            b's' => AuthorityRecordStatus::Split,
-            b'x' => AuthorityRecordStatus::Replaced,
-            _ => panic!("oopsie"),
-        };
+        let s = AuthorityRecordStatus::from_byte(r.header().header[5]).expect("oopsie");
 
         let coding_scheme = match r.header().header[9] {
             b'a' => AuthorityRecordCharacterCodingScheme::Unicode,
@@ -114,7 +95,7 @@ impl RecordMeta {
     }
     pub fn new(r: &MarcRecord, d: &MarcDirectory) -> RecordMeta {
         match r.header().record_type() {
-            RecordType::Authority => RecordMeta::AuthorityMeta(AuthorityRecordMeta::new(r, d)),
+            Some(RecordType::Authority) => RecordMeta::AuthorityMeta(AuthorityRecordMeta::new(r, d)),
         }
     }
 
@@ -208,8 +189,8 @@ impl ParsedRecord {
 }
 
 impl Record for ParsedRecord {
-    fn record_type(&self) -> RecordType {
-        self.meta.record_type()
+    fn record_type(&self) -> Option<RecordType> {
+        Some(self.meta.record_type())
     }
     fn field_iter(&self, field_type: Option<usize>) -> Box<dyn Iterator<Item = RecordField> + '_> {
         Box::new(ParsedRecordFieldIter::new(self, field_type))
@@ -0,0 +1,370 @@
+/*!
+ * Profiling which fields, subfields, indicator and leader values a file
+ * uses, e.g. before mapping data from a new source.
+ *
+ * Record length and base address are left out of the leader profile, record
+ * lengths have their own distribution in power-of-two buckets.
+ */
+use crate::marcrecord::*;
+use crate::record::*;
+use crate::recordreader::RecordReader;
+use crate::util::escape_json;
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, Write};
+
+/** The leader positions whose values are profiled **/
+pub const LEADER_POSITIONS: [usize; 14] = [5, 6, 7, 8, 9, 10, 11, 17, 18, 19, 20, 21, 22, 23];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubfieldProfile {
+    // fields containing the code
+    pub fields: usize,
+    pub occurrences: usize,
+    // most occurrences in one field
+    pub max_repeats: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldProfile {
+    // records containing the tag
+    pub records: usize,
+    pub occurrences: usize,
+    // most occurrences in one record
+    pub max_repeats: usize,
+    // counts of the first and second indicator values
+    pub indicators: [BTreeMap<u8, usize>; 2],
+    pub subfields: BTreeMap<u8, SubfieldProfile>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LengthDistribution {
+    pub min: usize,
+    pub max: usize,
+    pub total: usize,
+    // counts by the lower bound of power-of-two buckets, 512 holds 512 to 1023
+    pub buckets: BTreeMap<usize, usize>,
+}
+
+impl LengthDistribution {
+    fn add(&mut self, length: usize, first: bool) {
+        self.min = if first { length } else { self.min.min(length) };
+        self.max = self.max.max(length);
+        self.total += length;
+        let bucket = match length {
+            0 => 0,
+            _ => 1 << (usize::BITS - 1 - length.leading_zeros()),
+        };
+        *self.buckets.entry(bucket).or_insert(0) += 1;
+    }
+
+    /** The last length in a bucket **/
+    pub fn bucket_end(bucket: usize) -> usize {
+        (bucket * 2).max(1) - 1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub records: usize,
+    pub fields: BTreeMap<usize, FieldProfile>,
+    // value counts by leader position, empty for positions not profiled
+    pub leader: Vec<BTreeMap<u8, usize>>,
+    pub lengths: LengthDistribution,
+}
+
+/** Blanks are shown as `#`, as in the MARC documentation **/
+fn display(b: u8) -> char {
+    match b {
+        b' ' => '#',
+        _ => b as char,
+    }
+}
+
+fn json_char(b: u8) -> String {
+    escape_json(&(b as char).to_string())
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile::new()
+    }
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            records: 0,
+            fields: BTreeMap::new(),
+            leader: vec![BTreeMap::new(); 24],
+            lengths: LengthDistribution::default(),
+        }
+    }
+
+    pub fn build<R: Read + Seek>(
+        reader: &mut MarcReader<R>,
+        buffer: &mut [u8],
+    ) -> std::io::Result<Profile> {
+        let mut profile = Profile::new();
+        while let Some(batch) = reader.read_batch(buffer)? {
+            for record in batch.records.iter() {
+                profile.add_record(record);
+            }
+        }
+        Ok(profile)
+    }
+
+    /** Like `build`, for records in any format **/
+    pub fn from_records(reader: &mut dyn RecordReader) -> std::io::Result<Profile> {
+        let mut profile = Profile::new();
+        while let Some(record) = reader.read_record()? {
+            profile.add_record(&record);
+        }
+        Ok(profile)
+    }
+
+    pub fn add_record<R: Record + ?Sized>(&mut self, record: &R) {
+        let leader = record.leader();
+        for &i in LEADER_POSITIONS.iter() {
+            if let Some(&b) = leader.get(i) {
+                *self.leader[i].entry(b).or_insert(0) += 1;
+            }
+        }
+        // leader, directory and field terminator, and record terminator
+        let mut length = MARCHEADER_SIZE + 2;
+        let mut repeats: BTreeMap<usize, usize> = BTreeMap::new();
+        for field in record.field_iter(None) {
+            length += 12 + field.data.len() + 1;
+            *repeats.entry(field.field_type).or_insert(0) += 1;
+            let profile = self.fields.entry(field.field_type).or_default();
+            profile.occurrences += 1;
+            if !field.has_subfields() {
+                continue;
+            }
+            for (i, values) in profile.indicators.iter_mut().enumerate() {
+                if let Some(b) = field.indicator(i) {
+                    *values.entry(b).or_insert(0) += 1;
+                }
+            }
+            let mut codes: BTreeMap<u8, usize> = BTreeMap::new();
+            for subfield in field.subfields() {
+                *codes.entry(subfield.code()).or_insert(0) += 1;
+            }
+            for (code, n) in codes {
+                let subfield = profile.subfields.entry(code).or_default();
+                subfield.fields += 1;
+                subfield.occurrences += n;
+                subfield.max_repeats = subfield.max_repeats.max(n);
+            }
+        }
+        for (field_type, n) in repeats {
+            let profile = self.fields.get_mut(&field_type).unwrap();
+            profile.records += 1;
+            profile.max_repeats = profile.max_repeats.max(n);
+        }
+        self.lengths.add(length, self.records == 0);
+        self.records += 1;
+    }
+
+    /**
+     * A human-readable report. Subfield rows count the fields containing the
+     * code and the repeats within one field.
+     */
+    pub fn write_table(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(writer, "records: {}", self.records)?;
+        if let Some(mean) = self.lengths.total.checked_div(self.records) {
+            writeln!(
+                writer,
+                "record length: min {}, max {}, mean {}",
+                self.lengths.min, self.lengths.max, mean
+            )?;
+        }
+        for (&bucket, n) in self.lengths.buckets.iter() {
+            let range = format!("{}-{}", bucket, LengthDistribution::bucket_end(bucket));
+            writeln!(writer, "  {:>13} {:>9}", range, n)?;
+        }
+        writeln!(writer)?;
+        writeln!(writer, "leader")?;
+        for (i, values) in self.leader.iter().enumerate() {
+            for (&b, n) in values.iter() {
+                writeln!(writer, "  {:02}  {}  {:>9}", i, display(b), n)?;
+            }
+        }
+        writeln!(writer)?;
+        writeln!(writer, "tag     records  occurrences  max repeats")?;
+        for (field_type, field) in self.fields.iter() {
+            writeln!(
+                writer,
+                "{:03}  {:>10}  {:>11}  {:>11}",
+                field_type, field.records, field.occurrences, field.max_repeats
+            )?;
+            for (i, values) in field.indicators.iter().enumerate() {
+                if values.is_empty() {
+                    continue;
+                }
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|(&b, n)| format!("{}: {}", display(b), n))
+                    .collect();
+                writeln!(writer, "  ind{}  {}", i + 1, values.join(", "))?;
+            }
+            for (&code, subfield) in field.subfields.iter() {
+                writeln!(
+                    writer,
+                    "  ${}  {:>10}  {:>11}  {:>11}",
+                    code as char, subfield.fields, subfield.occurrences, subfield.max_repeats
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_json(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        let counts = |values: &BTreeMap<u8, usize>| {
+            let values: Vec<String> = values
+                .iter()
+                .map(|(&b, n)| format!("\"{}\":{}", json_char(b), n))
+                .collect();
+            format!("{{{}}}", values.join(","))
+        };
+        write!(writer, "{{\"records\":{},", self.records)?;
+        let buckets: Vec<String> = self
+            .lengths
+            .buckets
+            .iter()
+            .map(|(&bucket, n)| {
+                format!(
+                    "{{\"from\":{},\"to\":{},\"count\":{}}}",
+                    bucket,
+                    LengthDistribution::bucket_end(bucket),
+                    n
+                )
+            })
+            .collect();
+        write!(
+            writer,
+            "\"record_lengths\":{{\"min\":{},\"max\":{},\"total\":{},\"buckets\":[{}]}},",
+            self.lengths.min,
+            self.lengths.max,
+            self.lengths.total,
+            buckets.join(",")
+        )?;
+        let leader: Vec<String> = LEADER_POSITIONS
+            .iter()
+            .map(|&i| format!("\"{:02}\":{}", i, counts(&self.leader[i])))
+            .collect();
+        write!(writer, "\"leader\":{{{}}},", leader.join(","))?;
+        write!(writer, "\"fields\":{{")?;
+        for (n, (field_type, field)) in self.fields.iter().enumerate() {
+            if n > 0 {
+                write!(writer, ",")?;
+            }
+            let subfields: Vec<String> = field
+                .subfields
+                .iter()
+                .map(|(&code, s)| {
+                    format!(
+                        "\"{}\":{{\"fields\":{},\"occurrences\":{},\"max_repeats\":{}}}",
+                        json_char(code),
+                        s.fields,
+                        s.occurrences,
+                        s.max_repeats
+                    )
+                })
+                .collect();
+            write!(
+                writer,
+                "\"{:03}\":{{\"records\":{},\"occurrences\":{},\"max_repeats\":{},\"ind1\":{},\"ind2\":{},\"subfields\":{{{}}}}}",
+                field_type,
+                field.records,
+                field.occurrences,
+                field.max_repeats,
+                counts(&field.indicators[0]),
+                counts(&field.indicators[1]),
+                subfields.join(",")
+            )?;
+        }
+        writeln!(writer, "}}}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ownedrecord::OwnedRecord;
+    use crate::profiler::*;
+    use crate::testutil;
+
+    fn record(status: u8, fields: &[(usize, &str)]) -> OwnedRecord {
+        let mut r = testutil::record(testutil::AUTHORITY, fields);
+        r.header[5] = status;
+        r
+    }
+
+    #[test]
+    fn profile() -> Result<(), String> {
+        let records = [
+            record(
+                b'n',
+                &[
+                    (1, "1"),
+                    (150, "  \x1faA 302 D"),
+                    (
+                        550,
+                        "  \x1faIntegrierte Schaltung\x1f4obal\x1f4https://d-nb.info",
+                    ),
+                    (550, " 1\x1faSchaltung"),
+                ],
+            ),
+            record(b'c', &[(1, "2"), (150, "  \x1faBeispiel")]),
+        ];
+        let mut profile = Profile::new();
+        for r in records.iter() {
+            profile.add_record(r);
+        }
+        assert_eq!(profile.records, 2);
+        assert_eq!(profile.leader[5].get(&b'n'), Some(&1));
+        assert_eq!(profile.leader[5].get(&b'c'), Some(&1));
+        assert_eq!(profile.leader[6].get(&b'z'), Some(&2));
+        assert!(profile.leader[0].is_empty());
+
+        let field = &profile.fields[&550];
+        assert_eq!(
+            (field.records, field.occurrences, field.max_repeats),
+            (1, 2, 2)
+        );
+        assert_eq!(field.indicators[1].get(&b' '), Some(&1));
+        assert_eq!(field.indicators[1].get(&b'1'), Some(&1));
+        assert_eq!(
+            field.subfields[&b'4'],
+            SubfieldProfile {
+                fields: 1,
+                occurrences: 2,
+                max_repeats: 2
+            }
+        );
+        assert_eq!(field.subfields[&b'a'].fields, 2);
+        let control = &profile.fields[&1];
+        assert_eq!((control.records, control.occurrences), (2, 2));
+        assert!(control.indicators[0].is_empty());
+
+        let mut out = Vec::new();
+        records[0].to_marc21(&mut out).map_err(|e| e.to_string())?;
+        let first_length = out.len();
+        records[1].to_marc21(&mut out).map_err(|e| e.to_string())?;
+        assert_eq!(profile.lengths.total, out.len());
+        assert_eq!(profile.lengths.max, first_length);
+        assert_eq!(profile.lengths.buckets.values().sum::<usize>(), 2);
+
+        let mut table = Vec::new();
+        profile.write_table(&mut table).map_err(|e| e.to_string())?;
+        let table = String::from_utf8(table).map_err(|e| e.to_string())?;
+        assert!(table.contains("550           1            2            2"));
+        assert!(table.contains("  ind2  #: 1, 1: 1"));
+        let mut json = Vec::new();
+        profile.write_json(&mut json).map_err(|e| e.to_string())?;
+        let json = String::from_utf8(json).map_err(|e| e.to_string())?;
+        assert!(json.contains(
+            "\"550\":{\"records\":1,\"occurrences\":2,\"max_repeats\":2,\"ind1\":{\" \":2},\"ind2\":{\" \":1,\"1\":1}"
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,413 @@
+/*!
+ * Streaming export of authority records as SKOS or MADS/RDF, in Turtle or
+ * N-Triples.
+ *
+ * The subject IRI of a record is the first URI among its 024 fields (`$0`, or
+ * `$a` with `$2uri`), further 024 URIs become exact matches. Records without
+ * any get an IRI made from the configured base and their 001. 5XX tracings are
+ * linked through the first URI in their `$0`, or else through the base and the
+ * identifier of their first `$0`.
+ */
+use crate::authority::*;
+use crate::authoritygraph::EdgeType;
+use crate::record::*;
+use std::io::Write;
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const SKOS: &str = "http://www.w3.org/2004/02/skos/core#";
+const MADS: &str = "http://www.loc.gov/mads/rdf/v1#";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RdfSyntax {
+    Turtle,
+    NTriples,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RdfVocabulary {
+    Skos,
+    Mads,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Iri(String),
+    // namespace and local name
+    Name(&'static str, &'static str),
+    Blank(usize),
+    Literal(String, Option<&'static str>),
+}
+
+struct Triple {
+    subject: Term,
+    predicate: Term,
+    object: Term,
+}
+
+fn escape_iri(iri: &str) -> String {
+    let mut out = String::with_capacity(iri.len());
+    for c in iri.chars() {
+        if c <= ' ' || "<>\"{}|^`\\".contains(c) {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                out.push_str(&format!("%{:02X}", b));
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn escape_literal(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/** BCP 47 tags for the MARC language codes authority files are commonly cataloged in **/
+fn language_tag(marc_code: &str) -> Option<&'static str> {
+    Some(match marc_code {
+        "ger" => "de",
+        "eng" => "en",
+        "fre" => "fr",
+        "spa" => "es",
+        "ita" => "it",
+        "dut" => "nl",
+        "por" => "pt",
+        "rus" => "ru",
+        "pol" => "pl",
+        "swe" => "sv",
+        "dan" => "da",
+        "nor" => "no",
+        "cze" => "cs",
+        "lat" => "la",
+        _ => return None,
+    })
+}
+
+fn is_uri(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+fn prefix(namespace: &str) -> &'static str {
+    match namespace {
+        RDF => "rdf",
+        SKOS => "skos",
+        _ => "madsrdf",
+    }
+}
+
+pub struct RdfWriter<W: Write> {
+    writer: W,
+    syntax: RdfSyntax,
+    vocabulary: RdfVocabulary,
+    base: String,
+    scheme: Option<String>,
+    started: bool,
+    next_blank: usize,
+}
+
+impl<W: Write> RdfWriter<W> {
+    /** `base` is prepended to control numbers and identifiers that are not URIs **/
+    pub fn new(
+        writer: W,
+        syntax: RdfSyntax,
+        vocabulary: RdfVocabulary,
+        base: &str,
+    ) -> RdfWriter<W> {
+        RdfWriter {
+            writer,
+            syntax,
+            vocabulary,
+            base: base.to_string(),
+            scheme: None,
+            started: false,
+            next_blank: 0,
+        }
+    }
+
+    /** Declare every concept a member of this concept scheme **/
+    pub fn with_scheme(mut self, scheme: &str) -> RdfWriter<W> {
+        self.scheme = Some(scheme.to_string());
+        self
+    }
+
+    fn blank(&mut self) -> Term {
+        self.next_blank += 1;
+        Term::Blank(self.next_blank)
+    }
+
+    fn format_term(&self, term: &Term) -> String {
+        match term {
+            Term::Iri(iri) => format!("<{}>", escape_iri(iri)),
+            Term::Name(ns, local) if self.syntax == RdfSyntax::Turtle => {
+                format!("{}:{}", prefix(ns), local)
+            }
+            Term::Name(ns, local) => format!("<{}{}>", ns, local),
+            Term::Blank(n) => format!("_:b{}", n),
+            Term::Literal(s, Some(lang)) => format!("\"{}\"@{}", escape_literal(s), lang),
+            Term::Literal(s, None) => format!("\"{}\"", escape_literal(s)),
+        }
+    }
+
+    fn link_iri(&self, tracing: &Tracing) -> Option<String> {
+        if let Some(uri) = tracing.links.iter().find(|l| l.is_uri()) {
+            return Some(uri.identifier.clone());
+        }
+        tracing
+            .links
+            .first()
+            .map(|l| format!("{}{}", self.base, l.identifier))
+    }
+
+    fn triples<R: Record + ?Sized>(&mut self, record: &R) -> Vec<Triple> {
+        let authority = match AuthorityRecord::new(record) {
+            Some(a) => a,
+            None => return Vec::new(),
+        };
+        let mut uris: Vec<String> = Vec::new();
+        for field in record.field_iter(Some(24)) {
+            let uri_in_a = field.subfield_values(b'2').any(|s| s == "uri");
+            for s in field.subfields() {
+                if (s.code() == b'0' || (s.code() == b'a' && uri_in_a)) && is_uri(&s.utf8_value()) {
+                    uris.push(s.utf8_value().to_string());
+                }
+            }
+        }
+        let subject = match (uris.first(), authority.control_number()) {
+            (Some(uri), _) => Term::Iri(uri.clone()),
+            (None, Some(cn)) => Term::Iri(format!("{}{}", self.base, cn)),
+            (None, None) => return Vec::new(),
+        };
+        let lang = record.field_iter(Some(40)).next().and_then(|f| {
+            f.subfield_values(b'b')
+                .next()
+                .and_then(|v| language_tag(&v))
+        });
+        let heading = authority.established_heading();
+        let mads = self.vocabulary == RdfVocabulary::Mads;
+
+        let mut triples = Vec::new();
+        let mut add = |s: &Term, p: Term, o: Term| {
+            triples.push(Triple {
+                subject: s.clone(),
+                predicate: p,
+                object: o,
+            })
+        };
+        let rdf_type = || Term::Name(RDF, "type");
+        if mads {
+            add(&subject, rdf_type(), Term::Name(MADS, "Authority"));
+            if let Some(h) = &heading {
+                add(
+                    &subject,
+                    rdf_type(),
+                    Term::Name(MADS, mads_class(h.heading_type)),
+                );
+                add(
+                    &subject,
+                    Term::Name(MADS, "authoritativeLabel"),
+                    Term::Literal(h.text(), lang),
+                );
+            }
+        } else {
+            add(&subject, rdf_type(), Term::Name(SKOS, "Concept"));
+            if let Some(h) = &heading {
+                add(
+                    &subject,
+                    Term::Name(SKOS, "prefLabel"),
+                    Term::Literal(h.text(), lang),
+                );
+            }
+        }
+        if let Some(scheme) = &self.scheme {
+            let p = if mads {
+                Term::Name(MADS, "isMemberOfMADSScheme")
+            } else {
+                Term::Name(SKOS, "inScheme")
+            };
+            add(&subject, p, Term::Iri(scheme.clone()));
+        }
+        for tracing in authority.variant_headings() {
+            let label = Term::Literal(tracing.heading.text(), lang);
+            if mads {
+                let variant = self.blank();
+                add(&subject, Term::Name(MADS, "hasVariant"), variant.clone());
+                add(&variant, rdf_type(), Term::Name(MADS, "Variant"));
+                add(&variant, Term::Name(MADS, "variantLabel"), label);
+            } else {
+                add(&subject, Term::Name(SKOS, "altLabel"), label);
+            }
+        }
+        for tracing in authority.related_headings() {
+            let target = match self.link_iri(&tracing) {
+                Some(iri) => Term::Iri(iri),
+                None => continue,
+            };
+            let edge_type = EdgeType::from_tracing(&tracing);
+            let p = match (
+                mads,
+                edge_type.is_broader(),
+                edge_type == EdgeType::Narrower,
+            ) {
+                (false, true, _) => Term::Name(SKOS, "broader"),
+                (false, _, true) => Term::Name(SKOS, "narrower"),
+                (false, _, _) => Term::Name(SKOS, "related"),
+                (true, true, _) => Term::Name(MADS, "hasBroaderAuthority"),
+                (true, _, true) => Term::Name(MADS, "hasNarrowerAuthority"),
+                (true, _, _) => Term::Name(MADS, "hasReciprocalAuthority"),
+            };
+            add(&subject, p, target);
+        }
+        for uri in uris.iter().skip(1) {
+            let p = if mads {
+                Term::Name(MADS, "hasExactExternalAuthority")
+            } else {
+                Term::Name(SKOS, "exactMatch")
+            };
+            add(&subject, p, Term::Iri(uri.clone()));
+        }
+        triples
+    }
+
+    fn start(&mut self) -> std::io::Result<()> {
+        self.started = true;
+        if self.syntax == RdfSyntax::Turtle {
+            writeln!(self.writer, "@prefix rdf: <{}> .", RDF)?;
+            match self.vocabulary {
+                RdfVocabulary::Skos => writeln!(self.writer, "@prefix skos: <{}> .", SKOS)?,
+                RdfVocabulary::Mads => writeln!(self.writer, "@prefix madsrdf: <{}> .", MADS)?,
+            }
+            writeln!(self.writer)?;
+        }
+        Ok(())
+    }
+
+    /** Non-authority records are skipped **/
+    pub fn write_record<R: Record + ?Sized>(&mut self, record: &R) -> std::io::Result<()> {
+        if !self.started {
+            self.start()?;
+        }
+        let triples = self.triples(record);
+        if self.syntax == RdfSyntax::NTriples {
+            for t in triples.iter() {
+                writeln!(
+                    self.writer,
+                    "{} {} {} .",
+                    self.format_term(&t.subject),
+                    self.format_term(&t.predicate),
+                    self.format_term(&t.object)
+                )?;
+            }
+            return Ok(());
+        }
+        // Turtle, one block per subject with its predicates separated by `;`
+        for (i, t) in triples.iter().enumerate() {
+            let predicate = match &t.predicate {
+                Term::Name(RDF, "type") => "a".to_string(),
+                p => self.format_term(p),
+            };
+            if i == 0 || triples[i - 1].subject != t.subject {
+                write!(self.writer, "{}\n    ", self.format_term(&t.subject))?;
+            } else {
+                write!(self.writer, "    ")?;
+            }
+            write!(self.writer, "{} {}", predicate, self.format_term(&t.object))?;
+            let last_of_subject = i + 1 == triples.len() || triples[i + 1].subject != t.subject;
+            if last_of_subject {
+                writeln!(self.writer, " .\n")?;
+            } else {
+                writeln!(self.writer, " ;")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(mut self) -> std::io::Result<W> {
+        if !self.started {
+            self.start()?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn mads_class(heading_type: HeadingType) -> &'static str {
+    match heading_type {
+        HeadingType::PersonalName => "PersonalName",
+        HeadingType::CorporateName => "CorporateName",
+        HeadingType::MeetingName => "ConferenceName",
+        HeadingType::UniformTitle => "Title",
+        HeadingType::NamedEvent => "Topic",
+        HeadingType::ChronologicalTerm | HeadingType::ChronologicalSubdivision => "Temporal",
+        HeadingType::TopicalTerm | HeadingType::GeneralSubdivision => "Topic",
+        HeadingType::GeographicName | HeadingType::GeographicSubdivision => "Geographic",
+        HeadingType::GenreFormTerm | HeadingType::FormSubdivision => "GenreForm",
+        HeadingType::MediumOfPerformance => "Topic",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::marcrecord::MarcHeader;
+    use crate::marcrecord::MarcRecord;
+    use crate::rdfexport::*;
+    use crate::testutil::STR;
+
+    fn export(syntax: RdfSyntax, vocabulary: RdfVocabulary) -> String {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let mut writer = RdfWriter::new(Vec::new(), syntax, vocabulary, "https://example.org/gnd/");
+        writer.write_record(&record).unwrap();
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn skos_ntriples() {
+        let nt = export(RdfSyntax::NTriples, RdfVocabulary::Skos);
+        let lines: Vec<&str> = nt.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "<http://d-nb.info/gnd/4000002-3> <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <http://www.w3.org/2004/02/skos/core#Concept> .",
+                "<http://d-nb.info/gnd/4000002-3> <http://www.w3.org/2004/02/skos/core#prefLabel> \"A 302 D\"@de .",
+                "<http://d-nb.info/gnd/4000002-3> <http://www.w3.org/2004/02/skos/core#broader> <https://d-nb.info/gnd/4027242-4> .",
+            ]
+        );
+    }
+
+    #[test]
+    fn skos_turtle() {
+        let ttl = export(RdfSyntax::Turtle, RdfVocabulary::Skos);
+        assert!(ttl.starts_with("@prefix rdf: "));
+        assert!(ttl.contains(
+            "<http://d-nb.info/gnd/4000002-3>\n    a skos:Concept ;\n    skos:prefLabel \"A 302 D\"@de ;\n    skos:broader <https://d-nb.info/gnd/4027242-4> .\n"
+        ));
+    }
+
+    #[test]
+    fn mads() {
+        let ttl = export(RdfSyntax::Turtle, RdfVocabulary::Mads);
+        assert!(ttl.contains("@prefix madsrdf: <http://www.loc.gov/mads/rdf/v1#> ."));
+        assert!(ttl.contains("    a madsrdf:Topic ;\n"));
+        assert!(ttl.contains("    madsrdf:authoritativeLabel \"A 302 D\"@de ;\n"));
+        assert!(
+            ttl.contains("    madsrdf:hasBroaderAuthority <https://d-nb.info/gnd/4027242-4> .\n")
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape_literal("a \"b\"\n"), "a \\\"b\\\"\\n");
+        assert_eq!(escape_iri("http://x/a b<c>"), "http://x/a%20b%3Cc%3E");
+    }
+}
@@ -1,4 +1,6 @@
+use crate::resourceformat::ResourceFormat;
 use memchr::memchr;
+use std::borrow::Cow;
 pub fn end_of_entry_position(data: &[u8]) -> Option<usize> {
     // data.iter().position(|&x| x == b'\x1e')
     memchr(b'\x1e', data)
@@ -22,6 +24,10 @@ impl<'s> RecordField<'s> {
     pub fn utf8_data(&self) -> &str {
         std::str::from_utf8(self.data).unwrap()
     }
+    /** Like `utf8_data`, with invalid UTF-8 replaced instead of panicking **/
+    pub fn utf8_data_lossy(&self) -> Cow<'s, str> {
+        String::from_utf8_lossy(self.data)
+    }
     pub fn to_owned(&self) -> OwnedRecordField {
         OwnedRecordField {
             field_type: self.field_type,
@@ -47,6 +53,22 @@ impl<'s> RecordField<'s> {
     pub fn subfield_iter(&self) -> SubfieldIter<'s> {
         SubfieldIter { data: self.data }
     }
+    /** The subfields of a variable data field, without the leading indicators **/
+    pub fn subfields(&self) -> std::iter::Skip<SubfieldIter<'s>> {
+        // the first chunk before the first delimiter holds the indicators
+        let skip = if self.has_subfields() { 1 } else { usize::MAX };
+        self.subfield_iter().skip(skip)
+    }
+    /** The values of all subfields with the given code, in field order **/
+    pub fn subfield_values(&self, code: u8) -> impl Iterator<Item = Cow<'s, str>> {
+        self.subfields()
+            .filter(move |s| s.code() == code)
+            .map(|s| s.utf8_value())
+    }
+    /** The tag as it appears in the directory, e.g. "035" **/
+    pub fn tag(&self) -> String {
+        format!("{:03}", self.field_type)
+    }
 }
 
 pub struct Subfield<'s> {
@@ -78,25 +100,184 @@ impl<'s> Subfield<'s> {
     pub fn utf8_data(&self) -> &str {
         std::str::from_utf8(self.data).unwrap()
     }
+    pub fn code(&self) -> u8 {
+        self.data.first().cloned().unwrap_or(0)
+    }
+    pub fn value(&self) -> &'s [u8] {
+        if self.data.is_empty() {
+            self.data
+        } else {
+            &self.data[1..]
+        }
+    }
+    /** The value as text, invalid UTF-8 such as MARC-8 data is replaced, not rejected **/
+    pub fn utf8_value(&self) -> Cow<'s, str> {
+        String::from_utf8_lossy(self.value())
+    }
+}
+
+#[derive(std::cmp::PartialEq, Clone, Copy, Debug)]
+pub enum AuthorityRecordStatus {
+    IncreaseEncodingLevel = b'a' as isize,
+    Corrected = b'c' as isize,
+    Deleted = b'd' as isize,
+    New = b'n' as isize,
+    Obsolete = b'o' as isize,
+    Split = b's' as isize,
+    Replaced = b'x' as isize,
 }
 
-#[derive(std::cmp::PartialEq, Clone)]
+impl AuthorityRecordStatus {
+    /** Leader/05 **/
+    pub fn from_byte(b: u8) -> Option<AuthorityRecordStatus> {
+        use AuthorityRecordStatus::*;
+        Some(match b {
+            b'a' => IncreaseEncodingLevel,
+            b'c' => Corrected,
+            b'd' => Deleted,
+            b'n' => New,
+            b'o' => Obsolete,
+            b's' => Split,
+            b'x' => Replaced,
+            _ => return None,
+        })
+    }
+    /** Whether the record is no longer valid and should be removed from a file **/
+    pub fn is_removal(&self) -> bool {
+        matches!(
+            self,
+            AuthorityRecordStatus::Deleted
+                | AuthorityRecordStatus::Obsolete
+                | AuthorityRecordStatus::Split
+                | AuthorityRecordStatus::Replaced
+        )
+    }
+}
+
+#[derive(std::cmp::PartialEq, Clone, Copy, Debug)]
 pub enum RecordType {
+    LanguageMaterial = b'a' as isize,
+    NotatedMusic = b'c' as isize,
+    ManuscriptNotatedMusic = b'd' as isize,
+    CartographicMaterial = b'e' as isize,
+    ManuscriptCartographicMaterial = b'f' as isize,
+    ProjectedMedium = b'g' as isize,
+    NonmusicalSoundRecording = b'i' as isize,
+    MusicalSoundRecording = b'j' as isize,
+    TwoDimensionalNonprojectableGraphic = b'k' as isize,
+    ComputerFile = b'm' as isize,
+    Kit = b'o' as isize,
+    MixedMaterials = b'p' as isize,
+    Community = b'q' as isize,
+    ThreeDimensionalArtifact = b'r' as isize,
+    ManuscriptLanguageMaterial = b't' as isize,
+    UnknownHoldings = b'u' as isize,
+    MultipartItemHoldings = b'v' as isize,
+    Classification = b'w' as isize,
+    SinglePartItemHoldings = b'x' as isize,
+    SerialItemHoldings = b'y' as isize,
     Authority = b'z' as isize,
 }
 
 impl RecordType {
+    /** Leader/06 **/
+    pub fn from_byte(b: u8) -> Option<RecordType> {
+        use RecordType::*;
+        Some(match b {
+            b'a' => LanguageMaterial,
+            b'c' => NotatedMusic,
+            b'd' => ManuscriptNotatedMusic,
+            b'e' => CartographicMaterial,
+            b'f' => ManuscriptCartographicMaterial,
+            b'g' => ProjectedMedium,
+            b'i' => NonmusicalSoundRecording,
+            b'j' => MusicalSoundRecording,
+            b'k' => TwoDimensionalNonprojectableGraphic,
+            b'm' => ComputerFile,
+            b'o' => Kit,
+            b'p' => MixedMaterials,
+            b'q' => Community,
+            b'r' => ThreeDimensionalArtifact,
+            b't' => ManuscriptLanguageMaterial,
+            b'u' => UnknownHoldings,
+            b'v' => MultipartItemHoldings,
+            b'w' => Classification,
+            b'x' => SinglePartItemHoldings,
+            b'y' => SerialItemHoldings,
+            b'z' => Authority,
+            _ => return None,
+        })
+    }
+    pub fn is_bibliographic(&self) -> bool {
+        !matches!(
+            self,
+            RecordType::Community
+                | RecordType::UnknownHoldings
+                | RecordType::MultipartItemHoldings
+                | RecordType::Classification
+                | RecordType::SinglePartItemHoldings
+                | RecordType::SerialItemHoldings
+                | RecordType::Authority
+        )
+    }
+
+    /** The type of a one character leader/06 value, like `from_byte` **/
     pub fn from_str(s: &str) -> Option<RecordType> {
-        match s {
-            "a" => Some(RecordType::Authority),
-            "*" => None,
-            _ => todo!(),
+        match s.as_bytes() {
+            [b] => RecordType::from_byte(*b),
+            _ => None,
         }
     }
 }
 
+/** The material type of a bibliographic record, which also selects the layout of 008/18-34 **/
+#[derive(std::cmp::PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum MaterialType {
+    Books,
+    Serials,
+    Maps,
+    Music,
+    VisualMaterials,
+    ComputerFiles,
+    MixedMaterials,
+}
+
+impl MaterialType {
+    /** Derived from leader/06 and /07, None for non-bibliographic records **/
+    pub fn from_leader(leader: &[u8]) -> Option<MaterialType> {
+        use MaterialType::*;
+        Some(match leader[6] {
+            b'a' | b't' => match leader[7] {
+                b'b' | b'i' | b's' => Serials,
+                _ => Books,
+            },
+            b'm' => ComputerFiles,
+            b'e' | b'f' => Maps,
+            b'c' | b'd' | b'i' | b'j' => Music,
+            b'g' | b'k' | b'o' | b'r' => VisualMaterials,
+            b'p' => MixedMaterials,
+            _ => return None,
+        })
+    }
+}
+
 pub trait Record {
-    fn record_type(&self) -> RecordType;
+    /** The 24 bytes of the leader **/
+    fn leader(&self) -> &[u8];
+    /** None if leader/06 is not a known type of record, callers decide how to treat those **/
+    fn record_type(&self) -> Option<RecordType>;
+    /**
+     * The material type from leader/06 and /07, None for non-bibliographic
+     * records. It selects the layout of 008/18-34, so 006 and 007 do not
+     * change it, `resource_format` looks at them for electronic resources.
+     */
+    fn material_type(&self) -> Option<MaterialType> {
+        MaterialType::from_leader(self.leader())
+    }
+    /** A finer classification than the material type, e.g. telling e-books from books **/
+    fn resource_format(&self) -> Option<ResourceFormat> {
+        crate::resourceformat::resource_format(self)
+    }
     // todo nightly features might avoid the box
     // https://stackoverflow.com/questions/39482131/is-it-possible-to-use-impl-trait-as-a-functions-return-type-in-a-trait-defini/39490692#39490692
     fn field_iter(&self, field_type: Option<usize>) -> Box<dyn Iterator<Item = RecordField> + '_>;
@@ -0,0 +1,270 @@
+/*!
+ * Reading records from files in any of the supported serializations.
+ *
+ * `open_any` decompresses the file if needed, sniffs the first bytes to find
+ * out whether it holds ISO 2709, MARCXML, MARC-in-JSON or MarcEdit's MRK and
+ * returns a reader for that format, whatever the file extension says. MARCXML
+ * needs the `xml` feature and MARC-in-JSON the `json` feature.
+ */
+use crate::compression::{decompress, Compression};
+use crate::marcrecord::MarcStreamReader;
+use crate::ownedrecord::OwnedRecord;
+use crate::record::*;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Iso2709,
+    MarcXml,
+    MarcJson,
+    Mrk,
+}
+
+impl Format {
+    /** Recognize the format by the first bytes of the (decompressed) data **/
+    pub fn detect(data: &[u8]) -> Option<Format> {
+        let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+        // a leader: record length, status (p only for bibliographic records) and type
+        if data.len() >= 7
+            && data[..5].iter().all(u8::is_ascii_digit)
+            && (AuthorityRecordStatus::from_byte(data[5]).is_some() || data[5] == b'p')
+            && RecordType::from_byte(data[6]).is_some()
+        {
+            return Some(Format::Iso2709);
+        }
+        let start = data.iter().position(|b| !b.is_ascii_whitespace())?;
+        let data = &data[start..];
+        if data.starts_with(b"=LDR") {
+            Some(Format::Mrk)
+        } else if data.starts_with(b"<?xml")
+            || data.starts_with(b"<collection")
+            || data.starts_with(b"<record")
+            || data.starts_with(b"<marc:")
+        {
+            Some(Format::MarcXml)
+        } else if data.starts_with(b"[") || data.starts_with(b"{") {
+            Some(Format::MarcJson)
+        } else {
+            None
+        }
+    }
+
+    /** The format for a name given on the command line, e.g. `xml` **/
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "iso2709" | "marc" | "mrc" | "marc21" => Some(Format::Iso2709),
+            "marcxml" | "xml" => Some(Format::MarcXml),
+            "json" | "marcjson" | "ndjson" | "jsonl" => Some(Format::MarcJson),
+            "mrk" | "mnemonic" => Some(Format::Mrk),
+            _ => None,
+        }
+    }
+
+    /** Guess the format from the file extension, ignoring a compression suffix **/
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let path = match Compression::from_path(path) {
+            Compression::None => path,
+            _ => Path::new(path.file_stem()?),
+        };
+        Format::from_name(path.extension()?.to_str()?)
+    }
+}
+
+/** A source of records, whatever their serialization **/
+pub trait RecordReader: Send {
+    /** The next record, None at the end of the input **/
+    fn read_record(&mut self) -> std::io::Result<Option<OwnedRecord>>;
+}
+
+impl<R: Read + Send> RecordReader for MarcStreamReader<R> {
+    fn read_record(&mut self) -> std::io::Result<Option<OwnedRecord>> {
+        Ok(MarcStreamReader::read_record(self)?.map(|r| r.to_owned()))
+    }
+}
+
+impl Iterator for dyn RecordReader + '_ {
+    type Item = std::io::Result<OwnedRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+pub(crate) fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/** Parse the tag of a field, only numeric tags fit into records **/
+pub(crate) fn parse_tag(tag: &str) -> std::io::Result<usize> {
+    if tag.len() != 3 || !tag.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid(format!("invalid tag {:?}", tag)));
+    }
+    Ok(tag.parse().unwrap())
+}
+
+/** Set the leader, which has to be 24 bytes, length and base address are recomputed later **/
+pub(crate) fn set_leader(record: &mut OwnedRecord, leader: &[u8]) -> std::io::Result<()> {
+    if leader.len() != 24 {
+        return Err(invalid(format!(
+            "leader {:?} is not 24 bytes long",
+            String::from_utf8_lossy(leader)
+        )));
+    }
+    record.header.copy_from_slice(leader);
+    Ok(())
+}
+
+/** Open a file in any supported format, compressed or not **/
+pub fn open_any(path: &Path) -> std::io::Result<Box<dyn RecordReader>> {
+    open_any_reader(File::open(path)?)
+}
+
+/** Like `open_any`, for data from any reader **/
+pub fn open_any_reader<R: Read + Send + 'static>(
+    reader: R,
+) -> std::io::Result<Box<dyn RecordReader>> {
+    let mut reader = BufReader::new(decompress(reader)?);
+    let format = Format::detect(reader.fill_buf()?)
+        .ok_or_else(|| invalid("unrecognized record format".to_string()))?;
+    open_format(reader, format)
+}
+
+/** A reader for data in the given format **/
+pub fn open_format<R: BufRead + Send + 'static>(
+    reader: R,
+    format: Format,
+) -> std::io::Result<Box<dyn RecordReader>> {
+    Ok(match format {
+        Format::Iso2709 => Box::new(MarcStreamReader::new(reader)),
+        Format::Mrk => Box::new(crate::mrk::MrkReader::new(reader)),
+        #[cfg(feature = "xml")]
+        Format::MarcXml => Box::new(crate::marcxml::MarcXmlReader::new(reader)),
+        #[cfg(feature = "json")]
+        Format::MarcJson => Box::new(crate::marcjson::MarcJsonReader::new(reader)),
+        #[allow(unreachable_patterns)]
+        other => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{:?} support is not enabled", other),
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::recordreader::*;
+    use crate::testutil::{record, AUTHORITY};
+
+    #[test]
+    fn detect() {
+        assert_eq!(
+            Format::detect(b"00827nz  a2200241nc 4500"),
+            Some(Format::Iso2709)
+        );
+        assert_eq!(Format::detect(b"00827qz  a22"), None);
+        assert_eq!(
+            Format::detect(b"\xef\xbb\xbf<?xml version=\"1.0\"?>"),
+            Some(Format::MarcXml)
+        );
+        assert_eq!(
+            Format::detect(b"\n  <collection xmlns="),
+            Some(Format::MarcXml)
+        );
+        assert_eq!(
+            Format::detect(b"{\"leader\":\"00827nz"),
+            Some(Format::MarcJson)
+        );
+        assert_eq!(Format::detect(b" [\n{"), Some(Format::MarcJson));
+        assert_eq!(Format::detect(b"=LDR  00827nz"), Some(Format::Mrk));
+        assert_eq!(Format::detect(b"hello"), None);
+        assert_eq!(
+            Format::from_path(Path::new("gnd.xml.gz")),
+            Some(Format::MarcXml)
+        );
+        assert_eq!(
+            Format::from_path(Path::new("gnd.mrc")),
+            Some(Format::Iso2709)
+        );
+        assert_eq!(Format::from_path(Path::new("gnd")), None);
+    }
+
+    #[test]
+    fn open_iso2709() -> Result<(), String> {
+        let mut data = Vec::new();
+        for n in ["1", "2"] {
+            record(AUTHORITY, &[(1, n)])
+                .to_marc21(&mut data)
+                .map_err(|e| e.to_string())?;
+        }
+        let mut reader = open_any_reader(std::io::Cursor::new(data)).map_err(|e| e.to_string())?;
+        let records: Vec<OwnedRecord> = reader
+            .as_mut()
+            .collect::<std::io::Result<_>>()
+            .map_err(|e| e.to_string())?;
+        assert_eq!(records.len(), 2);
+        assert!(open_any_reader(std::io::Cursor::new(b"hello".to_vec())).is_err());
+        Ok(())
+    }
+
+    /** A record with 20 fields of 5000 bytes, more than ISO 2709 allows **/
+    fn oversized(format: Format) -> Result<(), String> {
+        let value = "x".repeat(5000);
+        let mut text = String::new();
+        match format {
+            Format::Mrk => {
+                text.push_str("=LDR  00000nz\\\\a2200000nc\\4500\n");
+                for _ in 0..20 {
+                    text.push_str(&format!("=500  \\\\$a{}\n", value));
+                }
+            }
+            Format::MarcXml => {
+                text.push_str("<record><leader>00000nz  a2200000nc 4500</leader>");
+                for _ in 0..20 {
+                    text.push_str(&format!(
+                        "<datafield tag=\"500\" ind1=\" \" ind2=\" \">\
+                         <subfield code=\"a\">{}</subfield></datafield>",
+                        value
+                    ));
+                }
+                text.push_str("</record>");
+            }
+            _ => {
+                let field = format!("{{\"500\":{{\"subfields\":[{{\"a\":\"{}\"}}]}}}}", value);
+                text = format!(
+                    "{{\"leader\":\"00000nz  a2200000nc 4500\",\"fields\":[{}]}}\n",
+                    vec![field; 20].join(",")
+                );
+            }
+        }
+        let mut reader = open_format(std::io::Cursor::new(text.into_bytes()), format)
+            .map_err(|e| e.to_string())?;
+        match reader.read_record() {
+            Err(e) => {
+                assert_eq!(e.kind(), ErrorKind::InvalidData);
+                assert!(e.to_string().contains("99999"), "{}", e);
+            }
+            Ok(_) => return Err("an oversized record was read".to_string()),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn oversized_mrk() -> Result<(), String> {
+        oversized(Format::Mrk)
+    }
+
+    #[cfg(feature = "xml")]
+    #[test]
+    fn oversized_marcxml() -> Result<(), String> {
+        oversized(Format::MarcXml)
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn oversized_marcjson() -> Result<(), String> {
+        oversized(Format::MarcJson)
+    }
+}
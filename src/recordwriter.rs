@@ -0,0 +1,206 @@
+/*!
+ * Writing records in any of the supported serializations, the counterpart
+ * of `recordreader`. MARCXML needs the `xml` feature and MARC-in-JSON the
+ * `json` feature.
+ */
+use crate::marcrecord::MARCHEADER_SIZE;
+use crate::record::*;
+use crate::recordreader::{invalid, Format};
+use std::io::{Error, ErrorKind, Write};
+
+/** A sink for records, whatever their serialization **/
+pub trait RecordWriter {
+    fn write_record(&mut self, record: &dyn Record) -> std::io::Result<()>;
+    /** Write what follows the last record, e.g. a closing element, and flush **/
+    fn finish(&mut self) -> std::io::Result<()>;
+}
+
+pub struct Iso2709Writer<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> Iso2709Writer<W> {
+    pub fn new(writer: W) -> Iso2709Writer<W> {
+        Iso2709Writer { writer }
+    }
+}
+
+/** Fail if a field or the record is too long for the lengths of ISO 2709 **/
+fn check_lengths(record: &dyn Record) -> std::io::Result<()> {
+    // +1 for the field separator after the directory and the record terminator
+    let mut length = MARCHEADER_SIZE + 2;
+    for field in record.field_iter(None) {
+        // +1 for the field separator
+        let field_length = field.data.len() + 1;
+        if field_length > 9999 {
+            return Err(invalid(format!(
+                "field {} is {} bytes long, ISO 2709 allows 9999",
+                field.tag(),
+                field_length
+            )));
+        }
+        length += 12 + field_length;
+    }
+    if length > 99999 {
+        return Err(invalid(format!(
+            "record is {} bytes long, ISO 2709 allows 99999",
+            length
+        )));
+    }
+    Ok(())
+}
+
+/**
+ * Fail unless leader/09 marks the record as Unicode, for the formats that can
+ * only hold UTF-8. Records in MARC-8 would otherwise be garbled.
+ */
+#[cfg(any(feature = "xml", feature = "json"))]
+pub(crate) fn check_unicode(record: &dyn Record, format: &str) -> std::io::Result<()> {
+    match record.leader().get(9) {
+        Some(b'a') => Ok(()),
+        other => Err(invalid(format!(
+            "{} needs Unicode records, leader/09 is {:?}",
+            format,
+            other.map_or(' ', |&b| b as char)
+        ))),
+    }
+}
+
+impl<W: Write> RecordWriter for Iso2709Writer<W> {
+    /** Fails without writing anything if the record does not fit into ISO 2709 **/
+    fn write_record(&mut self, record: &dyn Record) -> std::io::Result<()> {
+        check_lengths(record)?;
+        record.to_marc21(&mut self.writer)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/** A writer for records in the given format **/
+pub fn create_writer<'w, W: Write + 'w>(
+    writer: W,
+    format: Format,
+) -> std::io::Result<Box<dyn RecordWriter + 'w>> {
+    Ok(match format {
+        Format::Iso2709 => Box::new(Iso2709Writer::new(writer)),
+        Format::Mrk => Box::new(crate::mrk::MrkWriter::new(writer)),
+        #[cfg(feature = "xml")]
+        Format::MarcXml => Box::new(crate::marcxml::MarcXmlWriter::new(writer)),
+        #[cfg(feature = "json")]
+        Format::MarcJson => Box::new(crate::marcjson::MarcJsonWriter::new(writer)),
+        #[allow(unreachable_patterns)]
+        other => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{:?} support is not enabled", other),
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ownedrecord::OwnedRecord;
+    use crate::recordreader::{open_format, Format};
+    use crate::recordwriter::*;
+    use crate::testutil;
+
+    fn record() -> OwnedRecord {
+        testutil::record(
+            testutil::AUTHORITY,
+            &[
+                (1, "040000028"),
+                (550, "  \x1faIntegrierte <Schaltung> & \"Co\" 5$\x1f4obal"),
+            ],
+        )
+    }
+
+    fn roundtrip(format: Format) -> Result<(), String> {
+        let r = record();
+        let mut out = Vec::new();
+        let mut writer = create_writer(&mut out, format).map_err(|e| e.to_string())?;
+        for _ in 0..2 {
+            writer.write_record(&r).map_err(|e| e.to_string())?;
+        }
+        writer.finish().map_err(|e| e.to_string())?;
+        drop(writer);
+        assert_eq!(Format::detect(&out), Some(format));
+        let mut reader =
+            open_format(std::io::Cursor::new(out), format).map_err(|e| e.to_string())?;
+        let records: Vec<OwnedRecord> = reader
+            .as_mut()
+            .collect::<std::io::Result<_>>()
+            .map_err(|e| e.to_string())?;
+        assert_eq!(records.len(), 2);
+        for read in records {
+            assert_eq!(read.header, r.header);
+            assert_eq!(read.field_types, r.field_types);
+            assert_eq!(read.field_data, r.field_data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn iso2709() -> Result<(), String> {
+        roundtrip(Format::Iso2709)
+    }
+
+    #[test]
+    fn iso2709_lengths() {
+        let mut out = Vec::new();
+        {
+            let mut writer = Iso2709Writer::new(&mut out);
+            // add_field does not check the lengths
+            let mut r = record();
+            r.add_field(OwnedRecordField {
+                field_type: 500,
+                data: vec![b'x'; 9999],
+            });
+            let err = writer.write_record(&r).unwrap_err();
+            assert!(err.to_string().contains("field 500"), "{}", err);
+            let mut r = record();
+            for _ in 0..20 {
+                r.add_field(OwnedRecordField {
+                    field_type: 500,
+                    data: vec![b'x'; 5000],
+                });
+            }
+            let err = writer.write_record(&r).unwrap_err();
+            assert!(err.to_string().contains("99999"), "{}", err);
+        }
+        assert!(out.is_empty());
+    }
+
+    #[cfg(any(feature = "xml", feature = "json"))]
+    fn refuses_marc8(format: Format) {
+        let mut r = record();
+        r.header[9] = b' ';
+        let mut out = Vec::new();
+        let mut writer = create_writer(&mut out, format).unwrap();
+        let err = writer.write_record(&r).unwrap_err();
+        assert!(err.to_string().contains("leader/09"), "{}", err);
+        drop(writer);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn mrk() -> Result<(), String> {
+        roundtrip(Format::Mrk)
+    }
+
+    #[cfg(feature = "xml")]
+    #[test]
+    fn marcxml() -> Result<(), String> {
+        refuses_marc8(Format::MarcXml);
+        roundtrip(Format::MarcXml)
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn marcjson() -> Result<(), String> {
+        refuses_marc8(Format::MarcJson);
+        roundtrip(Format::MarcJson)
+    }
+}
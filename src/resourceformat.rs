@@ -0,0 +1,150 @@
+use crate::record::*;
+
+/** A classification of bibliographic records for faceting, finer than `MaterialType` **/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceFormat {
+    Book,
+    EBook,
+    Serial,
+    EJournal,
+    Map,
+    Score,
+    MusicRecording,
+    SpokenWordRecording,
+    Video,
+    Image,
+    ThreeDimensionalObject,
+    Kit,
+    ComputerFile,
+    MixedMaterials,
+}
+
+fn is_electronic_form(form_of_item: Option<&u8>) -> bool {
+    // online, direct electronic and generic electronic
+    matches!(form_of_item, Some(b'o') | Some(b'q') | Some(b's'))
+}
+
+fn form_of_item_position(material_type: MaterialType) -> Option<usize> {
+    material_type
+        .fixed_field_elements()
+        .iter()
+        .find(|e| e.name == "form_of_item")
+        .map(|e| e.start)
+}
+
+fn is_electronic<R: Record + ?Sized>(record: &R, material_type: MaterialType) -> bool {
+    if record
+        .field_iter(Some(7))
+        .any(|f| f.data.first() == Some(&b'c'))
+    {
+        return true;
+    }
+    if let (Some(f), Some(pos)) = (
+        record.field_iter(Some(8)).next(),
+        form_of_item_position(material_type),
+    ) {
+        if is_electronic_form(f.data.get(pos)) {
+            return true;
+        }
+    }
+    record.field_iter(Some(6)).any(|f| {
+        let form = match f.data.first() {
+            Some(b'm') => return true,
+            Some(&b) => b,
+            None => return false,
+        };
+        // 006/01-17 hold 008/18-34
+        MaterialType::from_form_of_material(form)
+            .and_then(form_of_item_position)
+            .is_some_and(|pos| is_electronic_form(f.data.get(pos - 17)))
+    })
+}
+
+fn is_video<R: Record + ?Sized>(record: &R) -> bool {
+    // videorecordings and motion pictures
+    let video = |b: Option<&u8>| matches!(b, Some(b'v') | Some(b'm'));
+    record.field_iter(Some(7)).any(|f| video(f.data.first()))
+        || record.field_iter(Some(8)).any(|f| video(f.data.get(33)))
+}
+
+/**
+ * `Record::resource_format`. Electronic books and journals are recognized by
+ * a 007 for electronic resources, a 006 for computer files or the form of
+ * item in 008 or 006.
+ */
+pub fn resource_format<R: Record + ?Sized>(record: &R) -> Option<ResourceFormat> {
+    use ResourceFormat::*;
+    let material_type = record.material_type()?;
+    Some(match record.leader()[6] {
+        b'a' | b't' => match (
+            material_type == MaterialType::Serials,
+            is_electronic(record, material_type),
+        ) {
+            (true, true) => EJournal,
+            (true, false) => Serial,
+            (false, true) => EBook,
+            (false, false) => Book,
+        },
+        b'c' | b'd' => Score,
+        b'e' | b'f' => Map,
+        b'i' => SpokenWordRecording,
+        b'j' => MusicRecording,
+        b'g' if is_video(record) => Video,
+        b'g' | b'k' => Image,
+        b'r' => ThreeDimensionalObject,
+        b'o' => Kit,
+        b'm' => ComputerFile,
+        b'p' => MixedMaterials,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::marcrecord::MarcHeader;
+    use crate::marcrecord::MarcRecord;
+    use crate::record::*;
+    use crate::resourceformat::*;
+    use crate::testutil::{record, STR};
+
+    #[test]
+    fn material_types() {
+        let authority = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        assert_eq!(authority.material_type(), None);
+        assert_eq!(authority.resource_format(), None);
+
+        let book = record::<&str>(b"00000nam a2200000 c 4500", &[]);
+        assert_eq!(book.material_type(), Some(MaterialType::Books));
+        assert_eq!(book.resource_format(), Some(ResourceFormat::Book));
+        let serial = record::<&str>(b"00000nas a2200000 c 4500", &[]);
+        assert_eq!(serial.material_type(), Some(MaterialType::Serials));
+        assert_eq!(serial.resource_format(), Some(ResourceFormat::Serial));
+        let score = record::<&str>(b"00000ncm a2200000 c 4500", &[]);
+        assert_eq!(score.material_type(), Some(MaterialType::Music));
+        assert_eq!(score.resource_format(), Some(ResourceFormat::Score));
+        let map = record::<&str>(b"00000nfm a2200000 c 4500", &[]);
+        assert_eq!(map.material_type(), Some(MaterialType::Maps));
+    }
+
+    #[test]
+    fn electronic_resources() {
+        let ebook = record(b"00000nam a2200000 c 4500", &[(7, b"cr |||||||||||")]);
+        assert_eq!(ebook.resource_format(), Some(ResourceFormat::EBook));
+        let mut f008 = [b' '; 40];
+        f008[23] = b'o';
+        let ejournal = record(b"00000nas a2200000 c 4500", &[(8, &f008)]);
+        assert_eq!(ejournal.resource_format(), Some(ResourceFormat::EJournal));
+        let ebook = record(b"00000nam a2200000 c 4500", &[(6, b"m     o  d |      ")]);
+        assert_eq!(ebook.resource_format(), Some(ResourceFormat::EBook));
+        let video = record(b"00000ngm a2200000 c 4500", &[(7, b"vd cvaizq")]);
+        assert_eq!(video.material_type(), Some(MaterialType::VisualMaterials));
+        assert_eq!(video.resource_format(), Some(ResourceFormat::Video));
+        let slides = record(b"00000ngm a2200000 c 4500", &[(7, b"gs cj")]);
+        assert_eq!(slides.resource_format(), Some(ResourceFormat::Image));
+        let audio = record(b"00000njm a2200000 c 4500", &[(7, b"sd fsngnnmmned")]);
+        assert_eq!(
+            audio.resource_format(),
+            Some(ResourceFormat::MusicRecording)
+        );
+    }
+}
@@ -0,0 +1,135 @@
+/*!
+ * Sorting more entries than fit into memory.
+ *
+ * Entries are collected in memory up to a limit, then sorted and spilled to a
+ * temporary file as a run. Reading them back merges the runs, so the entries
+ * come out in order while only one entry per run is held in memory.
+ */
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/** An entry that can be written to a run and read back **/
+pub trait RunEntry: Ord + Clone + Sized {
+    fn write(&self, writer: &mut dyn Write) -> std::io::Result<()>;
+    /** None at the end of the run **/
+    fn read(reader: &mut dyn Read) -> std::io::Result<Option<Self>>;
+}
+
+/** A temporary file that is deleted when dropped **/
+pub(crate) struct TempFile {
+    pub(crate) path: PathBuf,
+}
+
+impl TempFile {
+    pub(crate) fn create(dir: &Path, name: &str, n: usize) -> std::io::Result<(TempFile, File)> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let path = dir.join(format!(
+            "marclib-{}-{}-{}-{}.run",
+            name,
+            std::process::id(),
+            nanos,
+            n
+        ));
+        let file = File::create(&path)?;
+        Ok((TempFile { path }, file))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/** Entries sorted in memory or, beyond `max_in_memory`, in runs spilled to `temp_dir` **/
+pub struct SortedRuns<T: RunEntry> {
+    // part of the file names of the runs
+    name: &'static str,
+    max_in_memory: usize,
+    temp_dir: PathBuf,
+    entries: Vec<T>,
+    files: Vec<TempFile>,
+}
+
+impl<T: RunEntry> SortedRuns<T> {
+    pub fn new(name: &'static str, max_in_memory: usize, temp_dir: &Path) -> SortedRuns<T> {
+        SortedRuns {
+            name,
+            max_in_memory,
+            temp_dir: temp_dir.to_path_buf(),
+            entries: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, entry: T) -> std::io::Result<()> {
+        self.entries.push(entry);
+        if self.entries.len() >= self.max_in_memory {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> std::io::Result<()> {
+        self.entries.sort_unstable();
+        let (temp, file) = TempFile::create(&self.temp_dir, self.name, self.files.len())?;
+        let mut writer = BufWriter::new(file);
+        for entry in self.entries.drain(..) {
+            entry.write(&mut writer)?;
+        }
+        writer.flush()?;
+        self.files.push(temp);
+        Ok(())
+    }
+
+    /** All entries in order, the runs are kept so this can be called again **/
+    pub fn sorted(&mut self) -> std::io::Result<SortedEntries<'_, T>> {
+        if !self.files.is_empty() && !self.entries.is_empty() {
+            self.spill()?;
+        }
+        self.entries.sort_unstable();
+        let mut runs = Vec::new();
+        let mut heap = BinaryHeap::new();
+        for (n, temp) in self.files.iter().enumerate() {
+            let mut run = BufReader::new(File::open(&temp.path)?);
+            if let Some(entry) = T::read(&mut run)? {
+                heap.push(Reverse((entry, n)));
+            }
+            runs.push(run);
+        }
+        Ok(SortedEntries {
+            memory: self.entries.iter(),
+            runs,
+            heap,
+        })
+    }
+}
+
+/** The entries of `SortedRuns` in order **/
+pub struct SortedEntries<'a, T: RunEntry> {
+    memory: std::slice::Iter<'a, T>,
+    runs: Vec<BufReader<File>>,
+    heap: BinaryHeap<Reverse<(T, usize)>>,
+}
+
+impl<'a, T: RunEntry> SortedEntries<'a, T> {
+    pub fn next_entry(&mut self) -> std::io::Result<Option<T>> {
+        if self.runs.is_empty() {
+            return Ok(self.memory.next().cloned());
+        }
+        match self.heap.pop() {
+            Some(Reverse((entry, run))) => {
+                if let Some(next) = T::read(&mut self.runs[run])? {
+                    self.heap.push(Reverse((next, run)));
+                }
+                Ok(Some(entry))
+            }
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,666 @@
+/*!
+ * ISBN, ISSN and LCCN parsing, validation and normalization, and a validator
+ * that reports the invalid values of 020, 022 and 010.
+ */
+use crate::ownedrecord::OwnedRecord;
+use crate::record::*;
+use crate::validation::*;
+use std::io::Error;
+use std::io::ErrorKind;
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/** Split `9783161484100 (pbk.) :` into the number and the qualifier `pbk.` **/
+pub fn split_qualifier(value: &str) -> (&str, Option<&str>) {
+    let value = value.trim().trim_end_matches([' ', ':', ';']);
+    match value.find('(') {
+        Some(i) => {
+            let qualifier = value[i + 1..].trim_end_matches(')').trim();
+            (value[..i].trim(), Some(qualifier).filter(|q| !q.is_empty()))
+        }
+        None => (value, None),
+    }
+}
+
+/** The digits of a number with hyphens and blanks removed, `x` in upper case **/
+fn compact(value: &str) -> String {
+    value
+        .chars()
+        .filter(|&c| c != '-' && c != ' ')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn digit(c: u8) -> u32 {
+    if c == b'X' {
+        10
+    } else {
+        (c - b'0') as u32
+    }
+}
+
+fn isbn10_check_digit(first9: &[u8]) -> u8 {
+    let sum: u32 = first9
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| (10 - i as u32) * digit(c))
+        .sum();
+    match (11 - sum % 11) % 11 {
+        10 => b'X',
+        d => b'0' + d as u8,
+    }
+}
+
+fn isbn13_check_digit(first12: &[u8]) -> u8 {
+    let sum: u32 = first12
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| if i % 2 == 0 { 1 } else { 3 } * digit(c))
+        .sum();
+    b'0' + ((10 - sum % 10) % 10) as u8
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Isbn {
+    // always the 13 digit form
+    digits: String,
+}
+
+impl Isbn {
+    /** Parse a 10 or 13 digit ISBN, with or without hyphens, and check its check digit **/
+    pub fn parse(value: &str) -> std::io::Result<Isbn> {
+        let s = compact(value);
+        let b = s.as_bytes();
+        let well_formed = |n: usize| {
+            b.len() == n
+                && b[..n - 1].iter().all(u8::is_ascii_digit)
+                && (b[n - 1].is_ascii_digit() || (n == 10 && b[n - 1] == b'X'))
+        };
+        if well_formed(10) {
+            if isbn10_check_digit(&b[..9]) != b[9] {
+                return Err(invalid(format!("wrong ISBN check digit in {}", value)));
+            }
+            let mut digits = format!("978{}", &s[..9]);
+            digits.push(isbn13_check_digit(digits.as_bytes()) as char);
+            return Ok(Isbn { digits });
+        }
+        if well_formed(13) {
+            if !s.starts_with("978") && !s.starts_with("979") {
+                return Err(invalid(format!("{} is not an ISBN-13 prefix", &s[..3])));
+            }
+            if isbn13_check_digit(&b[..12]) != b[12] {
+                return Err(invalid(format!("wrong ISBN check digit in {}", value)));
+            }
+            return Ok(Isbn { digits: s });
+        }
+        Err(invalid(format!("{} is not an ISBN", value)))
+    }
+
+    pub fn to_isbn13(&self) -> String {
+        self.digits.clone()
+    }
+
+    /** None for 979 ISBNs, which have no 10 digit form **/
+    pub fn to_isbn10(&self) -> Option<String> {
+        if !self.digits.starts_with("978") {
+            return None;
+        }
+        let mut s = self.digits[3..12].to_string();
+        s.push(isbn10_check_digit(s.as_bytes()) as char);
+        Some(s)
+    }
+
+    /** The 13 digit form with hyphens **/
+    pub fn hyphenated(&self, ranges: &IsbnRanges) -> Result<String, HyphenationError> {
+        let (group, registrant, publication) = ranges.split(&self.digits)?;
+        Ok(format!(
+            "{}-{}-{}-{}-{}",
+            &self.digits[..3],
+            group,
+            registrant,
+            publication,
+            &self.digits[12..]
+        ))
+    }
+
+    pub fn hyphenated10(&self, ranges: &IsbnRanges) -> Result<String, HyphenationError> {
+        let isbn10 = self.to_isbn10().ok_or(HyphenationError::NoIsbn10)?;
+        let (group, registrant, publication) = ranges.split(&self.digits)?;
+        Ok(format!(
+            "{}-{}-{}-{}",
+            group,
+            registrant,
+            publication,
+            &isbn10[9..]
+        ))
+    }
+}
+
+impl std::fmt::Display for Isbn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.digits)
+    }
+}
+
+/** Why an ISBN could not be hyphenated **/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HyphenationError {
+    // the range data has no entry for the registration group
+    UnknownGroup,
+    // the group is known, but the number is in a range not yet assigned
+    UnassignedRange,
+    // 979 ISBNs have no 10 digit form
+    NoIsbn10,
+}
+
+impl std::fmt::Display for HyphenationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HyphenationError::UnknownGroup => "unknown ISBN registration group",
+            HyphenationError::UnassignedRange => "ISBN in an unassigned range",
+            HyphenationError::NoIsbn10 => "979 ISBNs have no 10 digit form",
+        })
+    }
+}
+
+impl std::error::Error for HyphenationError {}
+
+struct IsbnGroup {
+    prefix: String,
+    group: String,
+    // ranges over the 7 digits after the group, with the registrant length
+    ranges: Vec<(u32, u32, usize)>,
+}
+
+/** Registrant ranges of ISBN registration groups, used for hyphenation **/
+pub struct IsbnRanges {
+    groups: Vec<IsbnGroup>,
+}
+
+impl IsbnRanges {
+    /**
+     * Parse range data, one group per line: `978-3 0000000-0299999:2 ...`.
+     * Lines starting with `#` are comments.
+     */
+    pub fn load(data: &str) -> std::io::Result<IsbnRanges> {
+        let mut groups = Vec::new();
+        for (n, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad_line = || invalid(format!("bad ISBN range data on line {}", n + 1));
+            let mut parts = line.split_whitespace();
+            let (prefix, group) = parts
+                .next()
+                .and_then(|p| p.split_once('-'))
+                .ok_or_else(bad_line)?;
+            let mut ranges = Vec::new();
+            for range in parts {
+                let (from, rest) = range.split_once('-').ok_or_else(bad_line)?;
+                let (to, len) = rest.split_once(':').ok_or_else(bad_line)?;
+                ranges.push((
+                    from.parse().map_err(|_| bad_line())?,
+                    to.parse().map_err(|_| bad_line())?,
+                    len.parse().map_err(|_| bad_line())?,
+                ));
+            }
+            groups.push(IsbnGroup {
+                prefix: prefix.to_string(),
+                group: group.to_string(),
+                ranges,
+            });
+        }
+        Ok(IsbnRanges { groups })
+    }
+
+    /**
+     * Parse the `RangeMessage.xml` of the International ISBN Agency, see
+     * https://www.isbn-international.org/range_file_generation
+     */
+    pub fn from_range_message(xml: &str) -> std::io::Result<IsbnRanges> {
+        let bad = |what: &str| invalid(format!("bad RangeMessage.xml: {}", what));
+        let registration_groups = elements(xml, "RegistrationGroups")
+            .next()
+            .ok_or_else(|| bad("no RegistrationGroups"))?;
+        let mut groups = Vec::new();
+        for group in elements(registration_groups, "Group") {
+            let (prefix, group_id) = elements(group, "Prefix")
+                .next()
+                .and_then(|p| p.trim().split_once('-'))
+                .ok_or_else(|| bad("group without prefix"))?;
+            let mut ranges = Vec::new();
+            for rule in elements(group, "Rule") {
+                let range = elements(rule, "Range").next().unwrap_or("");
+                let len = elements(rule, "Length").next().unwrap_or("");
+                let (from, to) = range
+                    .trim()
+                    .split_once('-')
+                    .ok_or_else(|| bad(&format!("range {:?}", range)))?;
+                ranges.push((
+                    from.parse()
+                        .map_err(|_| bad(&format!("range {:?}", range)))?,
+                    to.parse().map_err(|_| bad(&format!("range {:?}", range)))?,
+                    len.trim()
+                        .parse()
+                        .map_err(|_| bad(&format!("length {:?}", len)))?,
+                ));
+            }
+            groups.push(IsbnGroup {
+                prefix: prefix.to_string(),
+                group: group_id.to_string(),
+                ranges,
+            });
+        }
+        Ok(IsbnRanges { groups })
+    }
+
+    /** Range data as a file, either `RangeMessage.xml` or in the format of `load` **/
+    pub fn from_path(path: &str) -> std::io::Result<IsbnRanges> {
+        let data = std::fs::read_to_string(path)?;
+        if data
+            .trim_start_matches('\u{feff}')
+            .trim_start()
+            .starts_with('<')
+        {
+            IsbnRanges::from_range_message(&data)
+        } else {
+            IsbnRanges::load(&data)
+        }
+    }
+
+    /** Write the ranges in the format of `load` **/
+    pub fn write(&self, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        for group in &self.groups {
+            write!(writer, "{}-{}", group.prefix, group.group)?;
+            for (from, to, len) in &group.ranges {
+                write!(writer, " {:07}-{:07}:{}", from, to, len)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * The ranges shipped with this crate, see data/isbn_ranges.txt for how to
+     * bring them up to date. Groups missing there are `UnknownGroup`.
+     */
+    pub fn shipped() -> IsbnRanges {
+        IsbnRanges::load(include_str!("../data/isbn_ranges.txt")).unwrap()
+    }
+
+    /** Group, registrant and publication element of a 13 digit ISBN **/
+    fn split<'a>(&self, digits: &'a str) -> Result<(&'a str, &'a str, &'a str), HyphenationError> {
+        let (prefix, rest) = (&digits[..3], &digits[3..12]);
+        let group = self
+            .groups
+            .iter()
+            .find(|g| g.prefix == prefix && rest.starts_with(&g.group))
+            .ok_or(HyphenationError::UnknownGroup)?;
+        let rest = &rest[group.group.len()..];
+        let mut key = rest.to_string();
+        while key.len() < 7 {
+            key.push('0');
+        }
+        // the digits were validated when parsing
+        let key: u32 = key[..7].parse().unwrap_or_default();
+        let len = group
+            .ranges
+            .iter()
+            .find(|&&(from, to, _)| from <= key && key <= to)
+            .map_or(0, |&(_, _, len)| len);
+        if len == 0 || len >= rest.len() {
+            return Err(HyphenationError::UnassignedRange);
+        }
+        Ok((
+            &digits[3..3 + group.group.len()],
+            &rest[..len],
+            &rest[len..],
+        ))
+    }
+}
+
+/** The contents of the elements `<name>` in `xml`, which must not have attributes or nest **/
+fn elements<'x>(xml: &'x str, name: &str) -> impl Iterator<Item = &'x str> + 'x {
+    let start = format!("<{}>", name);
+    let end = format!("</{}>", name);
+    let mut rest = xml;
+    std::iter::from_fn(move || {
+        let i = rest.find(&start)? + start.len();
+        let j = rest[i..].find(&end)? + i;
+        let content = &rest[i..j];
+        rest = &rest[j + end.len()..];
+        Some(content)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Issn {
+    digits: String,
+}
+
+impl Issn {
+    pub fn parse(value: &str) -> std::io::Result<Issn> {
+        let s = compact(value);
+        let b = s.as_bytes();
+        if b.len() != 8
+            || !b[..7].iter().all(u8::is_ascii_digit)
+            || !(b[7].is_ascii_digit() || b[7] == b'X')
+        {
+            return Err(invalid(format!("{} is not an ISSN", value)));
+        }
+        let sum: u32 = b[..7]
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| (8 - i as u32) * digit(c))
+            .sum();
+        let check = match (11 - sum % 11) % 11 {
+            10 => b'X',
+            d => b'0' + d as u8,
+        };
+        if check != b[7] {
+            return Err(invalid(format!("wrong ISSN check digit in {}", value)));
+        }
+        Ok(Issn { digits: s })
+    }
+}
+
+/** The ISSN in its standard form, `0317-8471` **/
+impl std::fmt::Display for Issn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", &self.digits[..4], &self.digits[4..])
+    }
+}
+
+/** A Library of Congress Control Number, see https://www.loc.gov/marc/lccn-namespace.html **/
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Lccn {
+    normalized: String,
+}
+
+impl Lccn {
+    /**
+     * Normalize following the LoC rules: remove blanks, remove a slash and
+     * everything after it, remove a hyphen and left pad the digits after it
+     * with zeros to six. The result must be an alphabetic prefix of up to
+     * three letters followed by a two or four digit year and a six digit
+     * serial number, twelve characters at most.
+     */
+    pub fn parse(value: &str) -> std::io::Result<Lccn> {
+        let mut s: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+        if let Some(i) = s.find('/') {
+            s.truncate(i);
+        }
+        if let Some(i) = s.find('-') {
+            let serial = &s[i + 1..];
+            if serial.is_empty() || !serial.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid(format!("{} is not an LCCN", value)));
+            }
+            s = format!("{}{:0>6}", &s[..i], serial);
+        }
+        let prefix_len = s.bytes().take_while(u8::is_ascii_alphabetic).count();
+        let digits = &s[prefix_len..];
+        if prefix_len > 3
+            || !(digits.len() == 8 || digits.len() == 10)
+            || !digits.bytes().all(|b| b.is_ascii_digit())
+            || s.len() > 12
+        {
+            return Err(invalid(format!("{} is not an LCCN", value)));
+        }
+        Ok(Lccn {
+            normalized: s.to_ascii_lowercase(),
+        })
+    }
+
+    pub fn prefix(&self) -> &str {
+        let len = self
+            .normalized
+            .bytes()
+            .take_while(u8::is_ascii_alphabetic)
+            .count();
+        &self.normalized[..len]
+    }
+}
+
+impl std::fmt::Display for Lccn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.normalized)
+    }
+}
+
+/**
+ * Where an invalid value of 010, 020 or 022 $a belongs: $z for LCCNs and
+ * ISBNs, $y (incorrect ISSN) for ISSNs
+ */
+fn invalid_subfield(field_type: usize) -> u8 {
+    if field_type == 22 {
+        b'y'
+    } else {
+        b'z'
+    }
+}
+
+fn check(field_type: usize, value: &str) -> std::io::Result<()> {
+    match field_type {
+        10 => Lccn::parse(value).map(|_| ()),
+        20 => Isbn::parse(split_qualifier(value).0).map(|_| ()),
+        _ => Issn::parse(value).map(|_| ()),
+    }
+}
+
+/** Reports every 010, 020 and 022 $a that does not parse as LCCN, ISBN or ISSN **/
+pub struct StandardNumberValidator;
+
+impl Validator for StandardNumberValidator {
+    fn validate(&self, record: &dyn Record, findings: &mut Vec<Finding>) {
+        for field in record.field_iter_vec(&[10, 20, 22]) {
+            for value in field.subfield_values(b'a') {
+                if let Err(e) = check(field.field_type, &value) {
+                    findings.push(Finding {
+                        severity: Severity::Error,
+                        rule: "standard-number".to_string(),
+                        field_type: Some(field.field_type),
+                        subfield: Some(b'a'),
+                        message: format!(
+                            "{}, belongs in ${}",
+                            e,
+                            invalid_subfield(field.field_type) as char
+                        ),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/** Move the invalid values the validator reports to $z, or $y for 022. Returns how many were moved. **/
+pub fn move_invalid_numbers(record: &mut OwnedRecord) -> usize {
+    let mut moved = 0;
+    for (i, data) in record.field_data.iter_mut().enumerate() {
+        let field_type = record.field_types[i];
+        if !matches!(field_type, 10 | 20 | 22) {
+            continue;
+        }
+        // positions of the subfield codes, after each delimiter
+        let codes: Vec<usize> = data
+            .iter()
+            .enumerate()
+            .filter(|(_, &b)| b == 0x1f)
+            .map(|(p, _)| p + 1)
+            .collect();
+        for (n, &p) in codes.iter().enumerate() {
+            if data.get(p) != Some(&b'a') {
+                continue;
+            }
+            let end = codes.get(n + 1).map_or(data.len(), |&next| next - 1);
+            let value = String::from_utf8_lossy(&data[p + 1..end]).to_string();
+            if check(field_type, value.trim_end_matches('\x1e')).is_err() {
+                data[p] = invalid_subfield(field_type);
+                moved += 1;
+            }
+        }
+    }
+    moved
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::standardnumber::*;
+    use crate::testutil::{record, BOOK};
+
+    #[test]
+    fn isbn() -> Result<(), String> {
+        let ranges = IsbnRanges::shipped();
+        let isbn = Isbn::parse("3-16-148410-x").map_err(|e| e.to_string())?;
+        assert_eq!(isbn.to_isbn13(), "9783161484100");
+        assert_eq!(isbn.to_isbn10().as_deref(), Some("316148410X"));
+        assert_eq!(isbn.hyphenated(&ranges).as_deref(), Ok("978-3-16-148410-0"));
+        assert_eq!(isbn.hyphenated10(&ranges).as_deref(), Ok("3-16-148410-X"));
+        let isbn = Isbn::parse("9780306406157").map_err(|e| e.to_string())?;
+        assert_eq!(isbn.hyphenated(&ranges).as_deref(), Ok("978-0-306-40615-7"));
+        assert_eq!(isbn.to_isbn10().as_deref(), Some("0306406152"));
+        // Spain, not in the shipped ranges
+        let isbn = Isbn::parse("9788437604947").map_err(|e| e.to_string())?;
+        assert_eq!(
+            isbn.hyphenated(&ranges),
+            Err(HyphenationError::UnknownGroup)
+        );
+        assert!(Isbn::parse("9780306406158").is_err());
+        assert!(Isbn::parse("0306406153").is_err());
+        assert!(Isbn::parse("97803064061").is_err());
+        assert_eq!(
+            split_qualifier("9783161484100 (pbk.) :"),
+            ("9783161484100", Some("pbk."))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn range_data() {
+        let ranges =
+            IsbnRanges::load("# test\n978-99 0000000-4999999:2 5000000-9999999:0\n").unwrap();
+        let isbn = Isbn::parse("9789912345676").unwrap();
+        assert_eq!(isbn.hyphenated(&ranges).as_deref(), Ok("978-99-12-34567-6"));
+        let isbn = Isbn::parse("9789962345671").unwrap();
+        assert_eq!(
+            isbn.hyphenated(&ranges),
+            Err(HyphenationError::UnassignedRange)
+        );
+        assert!(IsbnRanges::load("978-0 0000000:2").is_err());
+    }
+
+    #[test]
+    fn known_hyphenations() {
+        let ranges = IsbnRanges::shipped();
+        for hyphenated in [
+            "978-0-19-852663-6",
+            "978-0-306-40615-7",
+            "978-0-2280-1234-4",
+            "978-0-6398000-1-1",
+            "978-1-4028-9462-6",
+            "978-2-07-036822-8",
+            "978-3-16-148410-0",
+            "979-10-90636-07-1",
+        ] {
+            let isbn = Isbn::parse(hyphenated).unwrap();
+            assert_eq!(isbn.hyphenated(&ranges).as_deref(), Ok(hyphenated));
+        }
+    }
+
+    #[test]
+    fn range_message() {
+        // an excerpt of RangeMessage.xml
+        let xml = "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<ISBNRangeMessage>
+  <EAN.UCCPrefixes>
+    <EAN.UCC><Prefix>978</Prefix><Agency>International ISBN Agency</Agency><Rules>
+      <Rule><Range>0000000-5999999</Range><Length>1</Length></Rule>
+    </Rules></EAN.UCC>
+  </EAN.UCCPrefixes>
+  <RegistrationGroups>
+    <Group>
+      <Prefix>978-84</Prefix>
+      <Agency>Spain</Agency>
+      <Rules>
+        <Rule><Range>0000000-0999999</Range><Length>2</Length></Rule>
+        <Rule><Range>1000000-1049999</Range><Length>5</Length></Rule>
+        <Rule><Range>1050000-1199999</Range><Length>4</Length></Rule>
+        <Rule><Range>1200000-1299999</Range><Length>6</Length></Rule>
+        <Rule><Range>1300000-1399999</Range><Length>4</Length></Rule>
+        <Rule><Range>1400000-1499999</Range><Length>3</Length></Rule>
+        <Rule><Range>1500000-1999999</Range><Length>5</Length></Rule>
+        <Rule><Range>2000000-6999999</Range><Length>3</Length></Rule>
+        <Rule><Range>7000000-9999999</Range><Length>0</Length></Rule>
+      </Rules>
+    </Group>
+  </RegistrationGroups>
+</ISBNRangeMessage>";
+        let ranges = IsbnRanges::from_range_message(xml).unwrap();
+        let isbn = Isbn::parse("9788437604947").unwrap();
+        assert_eq!(isbn.hyphenated(&ranges).as_deref(), Ok("978-84-376-0494-7"));
+        let isbn = Isbn::parse("9780306406157").unwrap();
+        assert_eq!(
+            isbn.hyphenated(&ranges),
+            Err(HyphenationError::UnknownGroup)
+        );
+        // converted to the shipped format and back
+        let mut data = Vec::new();
+        ranges.write(&mut data).unwrap();
+        let data = String::from_utf8(data).unwrap();
+        assert!(data.starts_with("978-84 0000000-0999999:2 1000000-1049999:5 "));
+        let ranges = IsbnRanges::load(&data).unwrap();
+        let isbn = Isbn::parse("9788437604947").unwrap();
+        assert_eq!(isbn.hyphenated(&ranges).as_deref(), Ok("978-84-376-0494-7"));
+        assert!(IsbnRanges::from_range_message("<ISBNRangeMessage/>").is_err());
+    }
+
+    #[test]
+    fn issn() {
+        assert_eq!(Issn::parse("03178471").unwrap().to_string(), "0317-8471");
+        assert_eq!(Issn::parse("2049-3630").unwrap().to_string(), "2049-3630");
+        assert!(Issn::parse("0317-8472").is_err());
+        assert!(Issn::parse("0317-847").is_err());
+    }
+
+    #[test]
+    fn lccn() {
+        for (value, normalized) in [
+            ("n78-890351", "n78890351"),
+            ("n78-89035", "n78089035"),
+            ("n 78890351 ", "n78890351"),
+            ("   85000002 ", "85000002"),
+            ("85-2 ", "85000002"),
+            ("2001-000002", "2001000002"),
+            ("75-425165//r75", "75425165"),
+            (" 79139101 /AC/r932", "79139101"),
+        ] {
+            assert_eq!(Lccn::parse(value).unwrap().to_string(), normalized);
+        }
+        assert_eq!(Lccn::parse("sn 85-2").unwrap().prefix(), "sn");
+        assert!(Lccn::parse("abcd12345678").is_err());
+        assert!(Lccn::parse("85-2a").is_err());
+        assert!(Lccn::parse("123").is_err());
+    }
+
+    #[test]
+    fn report_and_move() {
+        let mut r = record(
+            BOOK,
+            &[
+                (10, "  \x1fa85-2"),
+                (20, "  \x1fa9783161484100 (pbk.)\x1fc19.99"),
+                (20, "  \x1fa3161484101 :"),
+                (22, "0 \x1fa0317-8472"),
+            ],
+        );
+        let findings = StandardNumberValidator.findings(&r);
+        let fields: Vec<Option<usize>> = findings.iter().map(|f| f.field_type).collect();
+        assert_eq!(fields, [Some(20), Some(22)]);
+        assert_eq!(move_invalid_numbers(&mut r), 2);
+        assert_eq!(r.field_data[2], b"  \x1fz3161484101 :");
+        assert_eq!(r.field_data[3], b"0 \x1fy0317-8472");
+        assert!(StandardNumberValidator.findings(&r).is_empty());
+    }
+}
@@ -0,0 +1,45 @@
+/*!
+ * Records shared by the unit tests.
+ */
+use crate::ownedrecord::OwnedRecord;
+use crate::record::OwnedRecordField;
+
+/** The GND authority record for "Integrierte Schaltung" in ISO 2709 **/
+pub static STR : &[u8]= "00827nz  a2200241nc 4500\
+001001000000\
+003000700010\
+005001700017\
+008004100034\
+024005100075\
+035002200126\
+035002200148\
+035002900170\
+040004000199\
+042000900239\
+065001600248\
+075001400264\
+079000900278\
+083004200287\
+150001200329\
+550019200341\
+670001200533\
+913004000545\
+040000028DE-10120100106125650.0880701n||azznnbabn           | ana    |c7 a4000002-30http://d-nb.info/gnd/4000002-32gnd  a(DE-101)040000028  a(DE-588)4000002-3  z(DE-588c)4000002-39v:zg  aDE-101cDE-1019r:DE-101bgerd0832  agnd1  a31.9b2sswd  bs2gndgen  agqs04a621.3815379d:29t:2010-01-06223/ger  aA 302 D  0(DE-101)0402724270(DE-588)4027242-40https://d-nb.info/gnd/4027242-4aIntegrierte Schaltung4obal4https://d-nb.info/standards/elementset/gnd#broaderTermGeneralwriOberbegriff allgemein  aVorlage  SswdisaA 302 D0(DE-588c)4000002-3".as_bytes();
+
+pub const BOOK: &[u8] = b"00000nam a2200000 i 4500";
+pub const SERIAL: &[u8] = b"00000nas a2200000 i 4500";
+pub const AUTHORITY: &[u8] = b"00000nz  a2200000nc 4500";
+
+/** A record with the given leader and fields, in the given order **/
+pub fn record<D: AsRef<[u8]>>(leader: &[u8], fields: &[(usize, D)]) -> OwnedRecord {
+    let mut r = OwnedRecord::new();
+    r.header.copy_from_slice(leader);
+    for (field_type, data) in fields {
+        r.add_field(OwnedRecordField {
+            field_type: *field_type,
+            data: data.as_ref().to_vec(),
+        });
+    }
+    r.update_len().unwrap();
+    r
+}
@@ -0,0 +1,410 @@
+/*!
+ * Applying incremental update files, as delivered by the DNB or OCLC, to a
+ * full file.
+ *
+ * Each record of an update file carries its status in leader/05, see
+ * `AuthorityRecordStatus`: `d` deletes the record with the same key, `x` and
+ * `s` remove a record that was replaced by or split into others, explained in
+ * a 682, and everything else adds the record or replaces the existing one.
+ *
+ * Only the positions of the update records are held in memory, the base file
+ * is streamed once. Later update files win over earlier ones.
+ */
+use crate::compare::record_key;
+use crate::marcrecord::MarcReader;
+use crate::record::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct UpdateConfig {
+    // records are keyed by the first occurrence of this field
+    pub key_field: usize,
+    // and of this subfield, None for control fields
+    pub key_subfield: Option<u8>,
+    // read buffer, must hold the largest record
+    pub buffer_size: usize,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> UpdateConfig {
+        UpdateConfig {
+            key_field: 1,
+            key_subfield: None,
+            buffer_size: 1 << 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateAction {
+    Added,
+    Replaced,
+    Deleted,
+    // removed because it was replaced by or split into other records
+    Superseded,
+    // a removal for a key neither the base nor an earlier update had
+    NotFound,
+    // a further base record with the key of an updated record, dropped
+    Duplicate,
+}
+
+impl fmt::Display for UpdateAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UpdateAction::Added => "added",
+            UpdateAction::Replaced => "replaced",
+            UpdateAction::Deleted => "deleted",
+            UpdateAction::Superseded => "superseded",
+            UpdateAction::NotFound => "not-found",
+            UpdateAction::Duplicate => "duplicate",
+        })
+    }
+}
+
+/**
+ * One line of the log. Dropped duplicates are logged with the last update
+ * record for their key.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateLogEntry {
+    pub key: String,
+    pub action: UpdateAction,
+    // leader/05 of the update record
+    pub status: u8,
+    // index of the update file the record came from
+    pub source: usize,
+    // the 682 of replaced and split records
+    pub note: Option<String>,
+}
+
+impl fmt::Display for UpdateLogEntry {
+    /** Tab-separated: action, key, status, update file index, note **/
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            self.action,
+            self.key,
+            self.status as char,
+            self.source,
+            self.note.as_deref().unwrap_or("")
+        )
+    }
+}
+
+/** The number of log lines per action, and of update records without key **/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateSummary {
+    pub unchanged: usize,
+    pub added: usize,
+    pub replaced: usize,
+    pub deleted: usize,
+    pub superseded: usize,
+    pub not_found: usize,
+    // update records without the key field, they are ignored
+    pub missing_keys: usize,
+    // further base records with the key of an updated record, they are dropped
+    pub duplicate_keys: usize,
+}
+
+/** An update record, only its position is kept **/
+struct Update {
+    source: usize,
+    offset: u64,
+    len: usize,
+    status: u8,
+    note: Option<String>,
+    // for keeping new records in the order of the updates
+    sequence: usize,
+}
+
+impl Update {
+    fn is_removal(&self) -> bool {
+        AuthorityRecordStatus::from_byte(self.status).is_some_and(|s| s.is_removal())
+    }
+}
+
+/** The update records for a key, in the order of the updates **/
+#[derive(Default)]
+struct Pending {
+    updates: Vec<Update>,
+    applied: bool,
+}
+
+/** The 682 $i and $a, what a replaced or split record was replaced by **/
+fn deletion_note<R: Record + ?Sized>(record: &R) -> Option<String> {
+    let field = record.field_iter(Some(682)).next()?;
+    let note: Vec<String> = field
+        .subfield_iter()
+        .filter(|sf| sf.code() == b'i' || sf.code() == b'a')
+        .map(|sf| sf.utf8_value().trim().to_string())
+        .collect();
+    Some(note.join(" "))
+}
+
+fn read_record(file: &mut File, offset: u64, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut data = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/**
+ * Apply the `updates` in order to `base`, writing the resulting full file to
+ * `output` and to `log` one line per update record, also those that a later
+ * update overrides, and per dropped duplicate base record. Base records keep
+ * their position, new records follow at the end.
+ */
+pub fn apply_updates(
+    base: &Path,
+    updates: &[PathBuf],
+    output: &mut dyn Write,
+    log: &mut dyn Write,
+    config: &UpdateConfig,
+) -> std::io::Result<UpdateSummary> {
+    let mut summary = UpdateSummary::default();
+    let mut buffer = vec![0; config.buffer_size];
+    let mut pending: HashMap<String, Pending> = HashMap::new();
+    let mut sequence = 0;
+    for (source, path) in updates.iter().enumerate() {
+        let mut reader = MarcReader::new(File::open(path)?);
+        let mut offset = 0u64;
+        while let Some(batch) = reader.read_batch(&mut buffer)? {
+            for record in batch.records.iter() {
+                let len = record.record_length();
+                match record_key(record, config.key_field, config.key_subfield) {
+                    Some(key) => {
+                        pending.entry(key).or_default().updates.push(Update {
+                            source,
+                            offset,
+                            len,
+                            status: record.leader()[5],
+                            note: deletion_note(record),
+                            sequence,
+                        });
+                        sequence += 1;
+                    }
+                    None => summary.missing_keys += 1,
+                }
+                offset += len as u64;
+            }
+        }
+    }
+
+    let mut files = updates
+        .iter()
+        .map(File::open)
+        .collect::<std::io::Result<Vec<File>>>()?;
+    let mut reader = MarcReader::new(File::open(base)?);
+    while let Some(batch) = reader.read_batch(&mut buffer)? {
+        for record in batch.records.iter() {
+            let key = record_key(record, config.key_field, config.key_subfield);
+            let (key, p) = match key.and_then(|k| pending.get_mut(&k).map(|p| (k, p))) {
+                Some(found) => found,
+                None => {
+                    record.to_marc21(output)?;
+                    summary.unchanged += 1;
+                    continue;
+                }
+            };
+            let last = p.updates.last().unwrap();
+            if p.applied {
+                summary.duplicate_keys += 1;
+                write_log(log, &key, UpdateAction::Duplicate, last)?;
+                continue;
+            }
+            p.applied = true;
+            if let Some(u) = apply_key(&key, &p.updates, true, log, &mut summary)? {
+                output.write_all(&read_record(&mut files[u.source], u.offset, u.len)?)?;
+            }
+        }
+    }
+
+    let mut remaining: Vec<(String, Pending)> =
+        pending.into_iter().filter(|(_, p)| !p.applied).collect();
+    remaining.sort_by_key(|(_, p)| p.updates.last().map(|u| u.sequence));
+    for (key, p) in remaining {
+        if let Some(u) = apply_key(&key, &p.updates, false, log, &mut summary)? {
+            output.write_all(&read_record(&mut files[u.source], u.offset, u.len)?)?;
+        }
+    }
+    output.flush()?;
+    log.flush()?;
+    Ok(summary)
+}
+
+/**
+ * Log the updates of a key in order and return the one whose record ends up
+ * in the output, if any. `exists` tells whether the base has the key.
+ */
+fn apply_key<'u>(
+    key: &str,
+    updates: &'u [Update],
+    mut exists: bool,
+    log: &mut dyn Write,
+    summary: &mut UpdateSummary,
+) -> std::io::Result<Option<&'u Update>> {
+    let mut result = None;
+    for u in updates {
+        let action = match AuthorityRecordStatus::from_byte(u.status) {
+            Some(AuthorityRecordStatus::Deleted) | Some(AuthorityRecordStatus::Obsolete)
+                if exists =>
+            {
+                summary.deleted += 1;
+                UpdateAction::Deleted
+            }
+            Some(AuthorityRecordStatus::Split) | Some(AuthorityRecordStatus::Replaced)
+                if exists =>
+            {
+                summary.superseded += 1;
+                UpdateAction::Superseded
+            }
+            _ if u.is_removal() => {
+                summary.not_found += 1;
+                UpdateAction::NotFound
+            }
+            _ if exists => {
+                summary.replaced += 1;
+                UpdateAction::Replaced
+            }
+            _ => {
+                summary.added += 1;
+                UpdateAction::Added
+            }
+        };
+        exists = !u.is_removal();
+        result = Some(u).filter(|_| exists);
+        write_log(log, key, action, u)?;
+    }
+    Ok(result)
+}
+
+fn write_log(
+    log: &mut dyn Write,
+    key: &str,
+    action: UpdateAction,
+    u: &Update,
+) -> std::io::Result<()> {
+    let entry = UpdateLogEntry {
+        key: key.to_string(),
+        action,
+        status: u.status,
+        source: u.source,
+        note: u
+            .note
+            .clone()
+            .filter(|_| action == UpdateAction::Superseded),
+    };
+    writeln!(log, "{}", entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil;
+    use crate::update::*;
+
+    fn record(status: u8, control_number: &str, fields: &[(usize, &str)]) -> Vec<u8> {
+        let mut all = vec![(1, control_number)];
+        all.extend_from_slice(fields);
+        let mut r = testutil::record(testutil::AUTHORITY, &all);
+        r.header[5] = status;
+        let mut out = Vec::new();
+        r.to_marc21(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn apply() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("marclib-update-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let files = [
+            (
+                "base.mrc",
+                vec![
+                    record(b'n', "1", &[(150, "  \x1faEins")]),
+                    record(b'n', "2", &[(150, "  \x1faZwei")]),
+                    record(b'n', "3", &[(150, "  \x1faDrei")]),
+                    record(b'n', "4", &[(150, "  \x1faVier")]),
+                    record(b'n', "2", &[(150, "  \x1faZwei doppelt")]),
+                ],
+            ),
+            (
+                "delta1.mrc",
+                vec![
+                    record(b'c', "2", &[(150, "  \x1faZwei!")]),
+                    record(b'n', "5", &[(150, "  \x1faFünf")]),
+                    record(b'n', "6", &[(150, "  \x1faSechs")]),
+                    record(b'd', "1", &[]),
+                ],
+            ),
+            (
+                "delta2.mrc",
+                vec![
+                    record(
+                        b'x',
+                        "3",
+                        &[(682, "  \x1fiErsetzt durch\x1f0(DE-101)4\x1faVier")],
+                    ),
+                    record(b'd', "6", &[]),
+                    record(b'd', "7", &[]),
+                    record(b'c', "2", &[(150, "  \x1faZwei!!")]),
+                ],
+            ),
+        ];
+        let mut paths = Vec::new();
+        for (name, records) in files.iter() {
+            let path = dir.join(name);
+            std::fs::write(&path, records.concat()).map_err(|e| e.to_string())?;
+            paths.push(path);
+        }
+        let (mut output, mut log) = (Vec::new(), Vec::new());
+        let summary = apply_updates(
+            &paths[0],
+            &paths[1..],
+            &mut output,
+            &mut log,
+            &UpdateConfig::default(),
+        )
+        .map_err(|e| e.to_string())?;
+        std::fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+        assert_eq!(
+            summary,
+            UpdateSummary {
+                unchanged: 1,
+                added: 2,
+                replaced: 2,
+                deleted: 2,
+                superseded: 1,
+                not_found: 1,
+                missing_keys: 0,
+                duplicate_keys: 1,
+            }
+        );
+        assert_eq!(
+            output,
+            [
+                record(b'c', "2", &[(150, "  \x1faZwei!!")]),
+                record(b'n', "4", &[(150, "  \x1faVier")]),
+                record(b'n', "5", &[(150, "  \x1faFünf")]),
+            ]
+            .concat()
+        );
+        assert_eq!(
+            String::from_utf8(log).map_err(|e| e.to_string())?,
+            "deleted\t1\td\t0\t\n\
+             replaced\t2\tc\t0\t\n\
+             replaced\t2\tc\t1\t\n\
+             superseded\t3\tx\t1\tErsetzt durch Vier\n\
+             duplicate\t2\tc\t1\t\n\
+             added\t5\tn\t0\t\n\
+             added\t6\tn\t0\t\n\
+             deleted\t6\td\t1\t\n\
+             not-found\t7\td\t1\t\n"
+        );
+        Ok(())
+    }
+}
@@ -42,8 +42,14 @@ pub fn parse_usize(slice: &[u8]) -> usize {
     n
 }
 
+/** Write `n` with `len` digits, padded with zeros. Fails if it does not fit. **/
 pub fn write_usize(n: usize, len: usize, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
-    assert!(n < 100000);
+    if len > 5 || n >= 10usize.pow(len as u32) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} does not fit into {} digits", n, len),
+        ));
+    }
     let mut n_len: usize = 0;
     let mut m = n;
     let mut buf: [u8; 5] = [b'0', b'0', b'0', b'0', b'0'];
@@ -54,3 +60,50 @@ pub fn write_usize(n: usize, len: usize, writer: &mut dyn std::io::Write) -> std
     }
     writer.write_all(&buf[5 - len..])
 }
+
+/**
+ * Escape the characters that are special in XML text and attribute values,
+ * dropping the control characters XML 1.0 does not allow
+ */
+pub fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            _ if c < ' ' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/** Quote a CSV field if it contains a separator, quote or line break **/
+pub fn escape_csv(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/** Escape a string for use inside a JSON string literal **/
+pub fn escape_json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => out.push(c),
+        }
+    }
+    out
+}
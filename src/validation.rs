@@ -0,0 +1,272 @@
+/*!
+ * The findings model shared by all validators, and the structural checks
+ * every record has to pass whatever profile it is validated against: leader
+ * values, field and record lengths, indicators and subfield codes.
+ */
+use crate::record::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn from_name(s: &str) -> Option<Severity> {
+        match s {
+            "info" => Some(Severity::Info),
+            "warning" => Some(Severity::Warning),
+            "error" => Some(Severity::Error),
+            _ => None,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/** One problem a validator found in a record **/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    // the rule that produced this finding, e.g. "required[0]"
+    pub rule: String,
+    // None for findings about the record as a whole
+    pub field_type: Option<usize>,
+    pub subfield: Option<u8>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} [{}]", self.severity.name(), self.rule)?;
+        if let Some(t) = self.field_type {
+            write!(f, " {:03}", t)?;
+            if let Some(c) = self.subfield {
+                write!(f, " ${}", c as char)?;
+            }
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+pub trait Validator {
+    fn validate(&self, record: &dyn Record, findings: &mut Vec<Finding>);
+
+    fn findings(&self, record: &dyn Record) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        self.validate(record, &mut findings);
+        findings
+    }
+}
+
+fn finding(rule: &str, field_type: Option<usize>, message: String) -> Finding {
+    Finding {
+        severity: Severity::Error,
+        rule: rule.to_string(),
+        field_type,
+        subfield: None,
+        message,
+    }
+}
+
+fn valid_indicator(b: u8) -> bool {
+    b == b' ' || b.is_ascii_digit() || b.is_ascii_lowercase()
+}
+
+fn valid_subfield_code(b: u8) -> bool {
+    b.is_ascii_digit() || b.is_ascii_lowercase()
+}
+
+/**
+ * Checks what ISO 2709 and MARC 21 require of every record: a known record
+ * type and status, the fixed leader positions, lengths that fit the
+ * directory, and well-formed indicators and subfield codes.
+ */
+pub struct StructuralValidator;
+
+impl StructuralValidator {
+    fn validate_leader(&self, leader: &[u8], findings: &mut Vec<Finding>) {
+        if leader.len() != 24 {
+            findings.push(finding(
+                "leader",
+                None,
+                format!("leader has {} bytes, not 24", leader.len()),
+            ));
+            return;
+        }
+        let record_type = RecordType::from_byte(leader[6]);
+        if record_type.is_none() {
+            findings.push(finding(
+                "leader",
+                None,
+                format!("invalid record type `{}` in leader/06", leader[6] as char),
+            ));
+        }
+        let status_valid = match record_type {
+            Some(RecordType::Authority) => AuthorityRecordStatus::from_byte(leader[5]).is_some(),
+            _ => b"acdnp".contains(&leader[5]),
+        };
+        if !status_valid {
+            findings.push(finding(
+                "leader",
+                None,
+                format!("invalid record status `{}` in leader/05", leader[5] as char),
+            ));
+        }
+        for (range, expected) in [(10..12, "22"), (20..24, "4500")] {
+            if &leader[range.clone()] != expected.as_bytes() {
+                findings.push(finding(
+                    "leader",
+                    None,
+                    format!(
+                        "leader/{:02}-{:02} is `{}`, not `{}`",
+                        range.start,
+                        range.end - 1,
+                        String::from_utf8_lossy(&leader[range.clone()]),
+                        expected
+                    ),
+                ));
+            }
+        }
+    }
+
+    fn validate_field(&self, field: &RecordField, findings: &mut Vec<Finding>) {
+        let field_type = Some(field.field_type);
+        // the directory has four digits for the length, including the terminator
+        if field.data.len() + 1 > 9999 {
+            findings.push(finding(
+                "field-length",
+                field_type,
+                format!(
+                    "field is {} bytes long, at most 9999 fit",
+                    field.data.len() + 1
+                ),
+            ));
+        }
+        if field.data.iter().any(|&b| b == 0x1e || b == 0x1d) {
+            findings.push(finding(
+                "terminator",
+                field_type,
+                "field or record terminator inside the field data".to_string(),
+            ));
+        }
+        if !RecordField::is_data_field_type(field.field_type) {
+            if field.has_subfields() {
+                findings.push(finding(
+                    "control-field",
+                    field_type,
+                    "control field with subfield delimiters".to_string(),
+                ));
+            }
+            return;
+        }
+        if field.data.len() < 2 || field.data[..2].contains(&0x1f) {
+            findings.push(finding(
+                "indicator",
+                field_type,
+                "data field without two indicators".to_string(),
+            ));
+            return;
+        }
+        for (i, &b) in field.data[..2].iter().enumerate() {
+            if !valid_indicator(b) {
+                findings.push(finding(
+                    "indicator",
+                    field_type,
+                    format!("invalid indicator {} `{}`", i + 1, b as char),
+                ));
+            }
+        }
+        if field.data.get(2) != Some(&0x1f) {
+            findings.push(finding(
+                "subfield-code",
+                field_type,
+                "data field does not start with a subfield after the indicators".to_string(),
+            ));
+        }
+        for subfield in field.subfields() {
+            if !valid_subfield_code(subfield.code()) {
+                findings.push(Finding {
+                    subfield: Some(subfield.code()),
+                    ..finding(
+                        "subfield-code",
+                        field_type,
+                        format!("invalid subfield code `{}`", subfield.code() as char),
+                    )
+                });
+            }
+        }
+    }
+}
+
+impl Validator for StructuralValidator {
+    fn validate(&self, record: &dyn Record, findings: &mut Vec<Finding>) {
+        self.validate_leader(record.leader(), findings);
+        // leader, directory with its terminator, and record terminator
+        let mut length = 24 + 2;
+        for field in record.field_iter(None) {
+            length += 12 + field.data.len() + 1;
+            self.validate_field(&field, findings);
+        }
+        if length > 99999 {
+            findings.push(finding(
+                "record-length",
+                None,
+                format!("record is {} bytes long, at most 99999 fit", length),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ownedrecord::OwnedRecord;
+    use crate::validation::*;
+
+    #[test]
+    fn structure() {
+        let mut r = OwnedRecord::new();
+        r.header.copy_from_slice(b"00000nz  a2200000nc 4500");
+        r.add_field(OwnedRecordField {
+            field_type: 1,
+            data: b"040000028".to_vec(),
+        });
+        r.add_field(OwnedRecordField {
+            field_type: 150,
+            data: b"  \x1faA 302 D".to_vec(),
+        });
+        assert!(StructuralValidator.findings(&r).is_empty());
+
+        r.header[5] = b'p';
+        r.header[22] = b'1';
+        r.add_field(OwnedRecordField {
+            field_type: 550,
+            data: b"A \x1fAIntegrierte Schaltung".to_vec(),
+        });
+        r.add_field(OwnedRecordField {
+            field_type: 500,
+            data: [b"  \x1fa".to_vec(), vec![b'x'; 10000]].concat(),
+        });
+        let findings: Vec<(String, Option<usize>)> = StructuralValidator
+            .findings(&r)
+            .into_iter()
+            .map(|f| (f.rule, f.field_type))
+            .collect();
+        assert_eq!(
+            findings,
+            [
+                ("leader".to_string(), None),
+                ("leader".to_string(), None),
+                ("indicator".to_string(), Some(550)),
+                ("subfield-code".to_string(), Some(550)),
+                ("field-length".to_string(), Some(500)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,652 @@
+/*!
+ * Declarative, user-defined validation rules loaded at runtime.
+ *
+ * A profile is a TOML document with a name and lists of rules:
+ *
+ * ```toml
+ * name = "gnd"
+ *
+ * [[required]]
+ * tag = "075"
+ * when = { "2" = "^gndgen$" }
+ *
+ * [[forbidden]]
+ * tag = "999"
+ *
+ * [[conditional]]
+ * if = { tag = "024", when = { "2" = "^gnd$" } }
+ * then = { tag = "035", code = "a", pattern = "^\\(DE-588\\)" }
+ *
+ * [[subfield]]
+ * tag = "079"
+ * code = "v"
+ * required = true
+ *
+ * [[equal]]
+ * left = { tag = "024", code = "a", when = { "2" = "^gnd$" } }
+ * right = { tag = "035", code = "a", pattern = "^\\(DE-588\\)(.*)$" }
+ * ```
+ *
+ * A selector picks fields by `tag`, optionally restricted by `when`, a table
+ * from subfield code to a regex that some occurrence of that subfield has to
+ * match. With `code` (and optionally `pattern`) a selector also yields
+ * subfield values; if `pattern` has a capture group, the first group is the
+ * value. Control fields yield their whole data as value.
+ *
+ * Every rule can set `severity` ("info", "warning", "error"), `message` and
+ * `id`. The profile-wide default severity is set with a top-level `severity`.
+ * Unknown keys are rejected, with their line where it can be found.
+ */
+use crate::record::*;
+use crate::validation::*;
+use regex::Regex;
+use std::io::{Error, ErrorKind};
+use toml::{Table, Value};
+
+struct Selector {
+    field_type: usize,
+    when: Vec<(u8, Regex)>,
+    code: Option<u8>,
+    pattern: Option<Regex>,
+}
+
+enum RuleKind {
+    Required(Selector),
+    Forbidden(Selector),
+    Conditional {
+        condition: Selector,
+        consequence: Selector,
+        forbid: bool,
+    },
+    Subfield {
+        selector: Selector,
+        code: u8,
+        pattern: Option<Regex>,
+        required: bool,
+    },
+    Equal {
+        left: Selector,
+        right: Selector,
+    },
+}
+
+struct Rule {
+    id: String,
+    severity: Severity,
+    message: Option<String>,
+    kind: RuleKind,
+}
+
+pub struct ValidationProfile {
+    name: String,
+    rules: Vec<Rule>,
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn get_str<'t>(table: &'t Table, key: &str, ctx: &str) -> std::io::Result<Option<&'t str>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.as_str())),
+        Some(_) => Err(invalid(format!("{}: `{}` must be a string", ctx, key))),
+    }
+}
+
+fn get_table<'t>(table: &'t Table, key: &str, ctx: &str) -> std::io::Result<Option<&'t Table>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::Table(t)) => Ok(Some(t)),
+        Some(_) => Err(invalid(format!("{}: `{}` must be a table", ctx, key))),
+    }
+}
+
+fn parse_tag(s: &str, ctx: &str) -> std::io::Result<usize> {
+    if s.len() != 3 || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid(format!("{}: `{}` is not a valid tag", ctx, s)));
+    }
+    Ok(s.parse().unwrap())
+}
+
+fn parse_code(s: &str, ctx: &str) -> std::io::Result<u8> {
+    if s.len() != 1 || !s.as_bytes()[0].is_ascii_graphic() {
+        return Err(invalid(format!("{}: `{}` is not a subfield code", ctx, s)));
+    }
+    Ok(s.as_bytes()[0])
+}
+
+fn parse_regex(s: &str, ctx: &str) -> std::io::Result<Regex> {
+    Regex::new(s).map_err(|e| invalid(format!("{}: {}", ctx, e)))
+}
+
+impl Selector {
+    fn parse(table: &Table, ctx: &str) -> std::io::Result<Selector> {
+        let tag = get_str(table, "tag", ctx)?
+            .ok_or_else(|| invalid(format!("{}: missing `tag`", ctx)))?;
+        let mut when = Vec::new();
+        if let Some(w) = get_table(table, "when", ctx)? {
+            for (code, pattern) in w.iter() {
+                let pattern = match pattern {
+                    Value::String(p) => parse_regex(p, ctx)?,
+                    _ => return Err(invalid(format!("{}: `when` values must be strings", ctx))),
+                };
+                when.push((parse_code(code, ctx)?, pattern));
+            }
+        }
+        let code = match get_str(table, "code", ctx)? {
+            Some(c) => Some(parse_code(c, ctx)?),
+            None => None,
+        };
+        let pattern = match get_str(table, "pattern", ctx)? {
+            Some(p) => Some(parse_regex(p, ctx)?),
+            None => None,
+        };
+        Ok(Selector {
+            field_type: parse_tag(tag, ctx)?,
+            when,
+            code,
+            pattern,
+        })
+    }
+
+    fn selects_field(&self, field: &RecordField) -> bool {
+        field.field_type == self.field_type
+            && self
+                .when
+                .iter()
+                .all(|(code, re)| field.subfield_values(*code).any(|v| re.is_match(&v)))
+    }
+
+    fn extract(&self, value: &str) -> Option<String> {
+        match &self.pattern {
+            None => Some(value.to_string()),
+            Some(re) => re.captures(value).map(|c| {
+                c.get(1)
+                    .unwrap_or_else(|| c.get(0).unwrap())
+                    .as_str()
+                    .to_string()
+            }),
+        }
+    }
+
+    fn field_values(&self, field: &RecordField) -> Vec<String> {
+        if !field.has_subfields() {
+            return self.extract(&field.utf8_data_lossy()).into_iter().collect();
+        }
+        match self.code {
+            Some(code) => field
+                .subfield_values(code)
+                .filter_map(|v| self.extract(&v))
+                .collect(),
+            None => self.extract(&field.utf8_data_lossy()).into_iter().collect(),
+        }
+    }
+
+    fn matches(&self, field: &RecordField) -> bool {
+        if !self.selects_field(field) {
+            return false;
+        }
+        if self.code.is_none() && self.pattern.is_none() {
+            return true;
+        }
+        !self.field_values(field).is_empty()
+    }
+
+    fn values(&self, record: &dyn Record) -> Vec<String> {
+        record
+            .field_iter(Some(self.field_type))
+            .filter(|f| self.selects_field(f))
+            .flat_map(|f| self.field_values(&f))
+            .collect()
+    }
+
+    fn describe(&self) -> String {
+        let mut s = format!("{:03}", self.field_type);
+        if let Some(c) = self.code {
+            s.push_str(&format!(" ${}", c as char));
+        }
+        if let Some(p) = &self.pattern {
+            s.push_str(&format!(" =~ /{}/", p.as_str()));
+        }
+        for (code, re) in self.when.iter() {
+            s.push_str(&format!(" where ${} =~ /{}/", *code as char, re.as_str()));
+        }
+        s
+    }
+}
+
+impl Rule {
+    fn finding(&self, field_type: Option<usize>, subfield: Option<u8>, default: String) -> Finding {
+        Finding {
+            severity: self.severity,
+            rule: self.id.clone(),
+            field_type,
+            subfield,
+            message: self.message.clone().unwrap_or(default),
+        }
+    }
+
+    fn check(&self, record: &dyn Record, findings: &mut Vec<Finding>) {
+        match &self.kind {
+            RuleKind::Required(s) => {
+                if !record.field_iter(Some(s.field_type)).any(|f| s.matches(&f)) {
+                    findings.push(self.finding(
+                        Some(s.field_type),
+                        s.code,
+                        format!("missing required field {}", s.describe()),
+                    ));
+                }
+            }
+            RuleKind::Forbidden(s) => {
+                for field in record.field_iter(Some(s.field_type)) {
+                    if s.matches(&field) {
+                        findings.push(self.finding(
+                            Some(s.field_type),
+                            s.code,
+                            format!("forbidden field {} present", s.describe()),
+                        ));
+                    }
+                }
+            }
+            RuleKind::Conditional {
+                condition,
+                consequence,
+                forbid,
+            } => {
+                let triggered = record
+                    .field_iter(Some(condition.field_type))
+                    .any(|f| condition.matches(&f));
+                if !triggered {
+                    return;
+                }
+                let present = record
+                    .field_iter(Some(consequence.field_type))
+                    .any(|f| consequence.matches(&f));
+                if present == *forbid {
+                    let verb = if *forbid { "forbids" } else { "requires" };
+                    findings.push(self.finding(
+                        Some(consequence.field_type),
+                        consequence.code,
+                        format!(
+                            "{} {} {}",
+                            condition.describe(),
+                            verb,
+                            consequence.describe()
+                        ),
+                    ));
+                }
+            }
+            RuleKind::Subfield {
+                selector,
+                code,
+                pattern,
+                required,
+            } => {
+                for field in record.field_iter(Some(selector.field_type)) {
+                    if !selector.selects_field(&field) {
+                        continue;
+                    }
+                    let mut seen = false;
+                    for value in field.subfield_values(*code) {
+                        seen = true;
+                        if let Some(re) = pattern {
+                            if !re.is_match(&value) {
+                                findings.push(self.finding(
+                                    Some(field.field_type),
+                                    Some(*code),
+                                    format!("value `{}` does not match /{}/", value, re.as_str()),
+                                ));
+                            }
+                        }
+                    }
+                    if *required && !seen {
+                        findings.push(self.finding(
+                            Some(field.field_type),
+                            Some(*code),
+                            format!("missing required subfield ${}", *code as char),
+                        ));
+                    }
+                }
+            }
+            RuleKind::Equal { left, right } => {
+                let right_values = right.values(record);
+                for value in left.values(record) {
+                    if !right_values.contains(&value) {
+                        findings.push(self.finding(
+                            Some(left.field_type),
+                            left.code,
+                            format!(
+                                "value `{}` of {} has no counterpart in {}",
+                                value,
+                                left.describe(),
+                                right.describe()
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+const RULE_SECTIONS: [&str; 5] = ["required", "forbidden", "conditional", "subfield", "equal"];
+const RULE_KEYS: [&str; 3] = ["id", "severity", "message"];
+const SELECTOR_KEYS: [&str; 4] = ["tag", "when", "code", "pattern"];
+
+/**
+ * The line of `key` in the `n`th `[[section]]` of the profile, or in the
+ * top-level table without a section. None if the key is not found there,
+ * e.g. because the rules are written as inline tables.
+ */
+fn key_line(profile: &str, section: Option<(&str, usize)>, key: &str) -> Option<usize> {
+    let lines: Vec<&str> = profile.lines().collect();
+    let start = match section {
+        None => 0,
+        Some((name, n)) => {
+            let header = format!("[[{}]]", name);
+            let is_header = |l: &&str| l.split_whitespace().collect::<String>() == header;
+            lines
+                .iter()
+                .enumerate()
+                .filter(|(_, l)| is_header(l))
+                .nth(n)?
+                .0
+                + 1
+        }
+    };
+    let end = lines[start..]
+        .iter()
+        .position(|l| l.trim_start().starts_with('['))
+        .map_or(lines.len(), |p| start + p);
+    let re = Regex::new(&format!(
+        r#"(^|[{{,\s])("{0}"|{0})\s*="#,
+        regex::escape(key)
+    ))
+    .ok()?;
+    (start..end).find(|&i| re.is_match(lines[i])).map(|i| i + 1)
+}
+
+fn unknown_key(profile: &str, section: Option<(&str, usize)>, ctx: &str, key: &str) -> Error {
+    match key_line(profile, section, key) {
+        Some(line) => invalid(format!("line {}: {}: unknown key `{}`", line, ctx, key)),
+        None => invalid(format!("{}: unknown key `{}`", ctx, key)),
+    }
+}
+
+impl ValidationProfile {
+    pub fn from_toml(s: &str) -> std::io::Result<ValidationProfile> {
+        let doc: Table = s
+            .parse()
+            .map_err(|e: toml::de::Error| invalid(e.to_string()))?;
+        let name = get_str(&doc, "name", "profile")?.unwrap_or("").to_string();
+        let default_severity = match get_str(&doc, "severity", "profile")? {
+            Some(s) => Severity::from_name(s)
+                .ok_or_else(|| invalid(format!("profile: unknown severity `{}`", s)))?,
+            None => Severity::Error,
+        };
+        for key in doc.keys() {
+            if key != "name" && key != "severity" && !RULE_SECTIONS.contains(&key.as_str()) {
+                return Err(unknown_key(s, None, "profile", key));
+            }
+        }
+
+        let mut rules = Vec::new();
+        for section in RULE_SECTIONS.iter() {
+            let entries = match doc.get(*section) {
+                None => continue,
+                Some(Value::Array(a)) => a,
+                Some(_) => {
+                    return Err(invalid(format!(
+                        "profile: `{}` must be an array of tables",
+                        section
+                    )))
+                }
+            };
+            for (i, entry) in entries.iter().enumerate() {
+                let default_id = format!("{}[{}]", section, i);
+                let table = match entry {
+                    Value::Table(t) => t,
+                    _ => return Err(invalid(format!("{}: must be a table", default_id))),
+                };
+                let id = get_str(table, "id", &default_id)?
+                    .map(|s| s.to_string())
+                    .unwrap_or(default_id);
+                // keys of the rule besides RULE_KEYS, and the ones holding a selector
+                let (keys, selectors): (&[&str], &[&str]) = match *section {
+                    "conditional" => (&["if", "then", "forbid"], &["if", "then", "forbid"]),
+                    "subfield" => (&["tag", "when", "code", "pattern", "required"], &[]),
+                    "equal" => (&["left", "right"], &["left", "right"]),
+                    _ => (&SELECTOR_KEYS, &[]),
+                };
+                let nested = selectors.iter().filter_map(|key| match table.get(*key) {
+                    Some(Value::Table(t)) => Some(t),
+                    _ => None,
+                });
+                let unknown = table
+                    .keys()
+                    .find(|k| !keys.contains(&k.as_str()) && !RULE_KEYS.contains(&k.as_str()))
+                    .or_else(|| {
+                        nested
+                            .flat_map(|t| t.keys())
+                            .find(|k| !SELECTOR_KEYS.contains(&k.as_str()))
+                    });
+                if let Some(key) = unknown {
+                    return Err(unknown_key(s, Some((section, i)), &id, key));
+                }
+                let severity = match get_str(table, "severity", &id)? {
+                    Some(s) => Severity::from_name(s)
+                        .ok_or_else(|| invalid(format!("{}: unknown severity `{}`", id, s)))?,
+                    None => default_severity,
+                };
+                let message = get_str(table, "message", &id)?.map(|s| s.to_string());
+                let kind = Self::parse_rule(section, table, &id)?;
+                rules.push(Rule {
+                    id,
+                    severity,
+                    message,
+                    kind,
+                });
+            }
+        }
+        Ok(ValidationProfile { name, rules })
+    }
+
+    fn parse_rule(section: &str, table: &Table, id: &str) -> std::io::Result<RuleKind> {
+        let selector = |key: &str| -> std::io::Result<Selector> {
+            let t = get_table(table, key, id)?
+                .ok_or_else(|| invalid(format!("{}: missing `{}`", id, key)))?;
+            Selector::parse(t, id)
+        };
+        Ok(match section {
+            "required" => RuleKind::Required(Selector::parse(table, id)?),
+            "forbidden" => RuleKind::Forbidden(Selector::parse(table, id)?),
+            "conditional" => {
+                let (consequence, forbid) =
+                    match (table.contains_key("then"), table.contains_key("forbid")) {
+                        (true, false) => (selector("then")?, false),
+                        (false, true) => (selector("forbid")?, true),
+                        _ => {
+                            return Err(invalid(format!(
+                                "{}: needs exactly one of `then` or `forbid`",
+                                id
+                            )))
+                        }
+                    };
+                RuleKind::Conditional {
+                    condition: selector("if")?,
+                    consequence,
+                    forbid,
+                }
+            }
+            "subfield" => {
+                // `code` and `pattern` constrain the subfield here, they do not select fields
+                let mut s = Selector::parse(table, id)?;
+                let code = s
+                    .code
+                    .take()
+                    .ok_or_else(|| invalid(format!("{}: missing `code`", id)))?;
+                let required = match table.get("required") {
+                    None => false,
+                    Some(Value::Boolean(b)) => *b,
+                    Some(_) => {
+                        return Err(invalid(format!("{}: `required` must be a boolean", id)))
+                    }
+                };
+                RuleKind::Subfield {
+                    pattern: s.pattern.take(),
+                    selector: s,
+                    code,
+                    required,
+                }
+            }
+            "equal" => RuleKind::Equal {
+                left: selector("left")?,
+                right: selector("right")?,
+            },
+            _ => unreachable!(),
+        })
+    }
+
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<ValidationProfile> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn num_rules(&self) -> usize {
+        self.rules.len()
+    }
+}
+
+impl Validator for ValidationProfile {
+    fn validate(&self, record: &dyn Record, findings: &mut Vec<Finding>) {
+        for rule in self.rules.iter() {
+            rule.check(record, findings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::marcrecord::MarcHeader;
+    use crate::marcrecord::MarcRecord;
+    use crate::testutil::STR;
+    use crate::validation::*;
+    use crate::validationprofile::*;
+
+    static GND: &str = r#"
+name = "gnd"
+
+[[required]]
+tag = "075"
+when = { "2" = "^gndgen$" }
+
+[[equal]]
+left = { tag = "024", code = "a", when = { "2" = "^gnd$" } }
+right = { tag = "035", code = "a", pattern = "^\\(DE-588\\)(.*)$" }
+
+[[subfield]]
+id = "079-v"
+tag = "079"
+code = "v"
+required = true
+severity = "warning"
+"#;
+
+    #[test]
+    fn gnd_profile() -> Result<(), String> {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let profile = ValidationProfile::from_toml(GND).map_err(|e| e.to_string())?;
+        assert_eq!(profile.name(), "gnd");
+        assert_eq!(profile.num_rules(), 3);
+        let findings = profile.findings(&record);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, "079-v");
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert_eq!(findings[0].field_type, Some(79));
+        assert_eq!(findings[0].subfield, Some(b'v'));
+        Ok(())
+    }
+
+    #[test]
+    fn violations() -> Result<(), String> {
+        let record = MarcRecord::new(MarcHeader::new(&STR[..24]), &STR[24..]);
+        let profile = ValidationProfile::from_toml(
+            r#"
+[[forbidden]]
+tag = "913"
+
+[[required]]
+tag = "075"
+code = "b"
+pattern = "^p$"
+
+[[conditional]]
+if = { tag = "150" }
+forbid = { tag = "550", code = "4", pattern = "^obal$" }
+
+[[subfield]]
+tag = "024"
+code = "a"
+pattern = "^[0-9]+-[0-9X]$"
+
+[[equal]]
+left = { tag = "001" }
+right = { tag = "035", code = "a", pattern = "^\\(DE-588\\)(.*)$" }
+"#,
+        )
+        .map_err(|e| e.to_string())?;
+        let rules: Vec<String> = profile
+            .findings(&record)
+            .into_iter()
+            .map(|f| f.rule)
+            .collect();
+        assert_eq!(
+            rules,
+            ["required[0]", "forbidden[0]", "conditional[0]", "equal[0]"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bad_profiles() {
+        assert!(ValidationProfile::from_toml("[[required]]\ncode = \"a\"").is_err());
+        assert!(ValidationProfile::from_toml("[[required]]\ntag = \"75\"").is_err());
+        assert!(
+            ValidationProfile::from_toml("[[forbidden]]\ntag = \"075\"\npattern = \"(\"").is_err()
+        );
+        assert!(ValidationProfile::from_toml("[[conditional]]\nif = { tag = \"075\" }").is_err());
+        assert!(ValidationProfile::from_toml("[[mandatory]]\ntag = \"075\"").is_err());
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let error = |profile: &str| match ValidationProfile::from_toml(profile) {
+            Ok(_) => String::new(),
+            Err(e) => e.to_string(),
+        };
+        assert_eq!(
+            error("name = \"gnd\"\n\n[[required]]\ntag = \"075\"\n\n[[required]]\ntag = \"079\"\nsevrity = \"info\""),
+            "line 8: required[1]: unknown key `sevrity`"
+        );
+        assert_eq!(
+            error("[[subfield]]\nid = \"079v\"\ntag = \"079\"\ncode = \"v\"\nsubfeilds = [\"a\"]"),
+            "line 5: 079v: unknown key `subfeilds`"
+        );
+        assert_eq!(
+            error("[[equal]]\nleft = { tag = \"024\", cod = \"a\" }\nright = { tag = \"035\" }"),
+            "line 2: equal[0]: unknown key `cod`"
+        );
+        assert_eq!(
+            error("nmae = \"gnd\""),
+            "line 1: profile: unknown key `nmae`"
+        );
+        assert_eq!(
+            error("required = [{ tag = \"075\", sevrity = \"info\" }]"),
+            "required[0]: unknown key `sevrity`"
+        );
+    }
+}
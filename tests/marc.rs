@@ -0,0 +1,62 @@
+/*!
+ * Runs the `marc` tool on damaged ISO 2709 input.
+ */
+use std::process::{Command, Output};
+
+// 001 "abc", length and base address are right
+const GOOD: &[u8] = b"00042nz  a2200037nc 4500001000400000\x1eabc\x1e\x1d";
+
+fn run_lenient_count(name: &str, damaged: &[u8]) -> Output {
+    let mut data = GOOD.to_vec();
+    data.extend_from_slice(damaged);
+    data.extend_from_slice(GOOD);
+    let path =
+        std::env::temp_dir().join(format!("marclib-cli-{}-{}.mrc", name, std::process::id()));
+    std::fs::write(&path, &data).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_marc"))
+        .args(["--lenient", "count"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    output
+}
+
+fn assert_skipped_one(output: &Output) {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stdout.starts_with("records\t2\n"), "{}", stdout);
+    assert!(stderr.contains("skipped 1 damaged records"), "{}", stderr);
+}
+
+#[test]
+fn missing_directory_terminator() {
+    let output = run_lenient_count("terminator", b"00030nz  a2200025nc 450000100\x1d");
+    assert_skipped_one(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no directory terminator"));
+}
+
+#[test]
+fn field_outside_of_the_record() {
+    let output = run_lenient_count(
+        "outside",
+        b"00040nz  a2200037nc 4500001000599001\x1ex\x1e\x1d",
+    );
+    assert_skipped_one(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("field 001 outside"));
+}
+
+#[test]
+fn strict_fails() {
+    let path = std::env::temp_dir().join(format!("marclib-cli-strict-{}.mrc", std::process::id()));
+    std::fs::write(&path, b"00030nz  a2200025nc 450000100\x1d").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_marc"))
+        .arg("count")
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("marc: "));
+}